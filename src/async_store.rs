@@ -0,0 +1,204 @@
+use std::panic;
+
+use bytes::Bytes;
+use tokio::{sync::mpsc, task};
+
+use crate::{
+    watch::{Watcher, WATCH_BUFFER},
+    Store, WatchEvent,
+};
+
+/// An async handle to a [`Store`], whose operations wait for the store's lock
+/// on Tokio's blocking thread pool instead of on the task's executor thread.
+///
+/// It shares its records with the `Store` it was made from, so sync and async
+/// code can use the same data: convert with `AsyncStore::from(store)` and
+/// [`AsyncStore::into_blocking`]. A write held for a long time by either side
+/// only delays the tasks waiting on the store, never the rest of the runtime.
+///
+/// Every method must be awaited from within a Tokio runtime. Like `Store`,
+/// cloning an `AsyncStore` shares the same records.
+#[derive(Debug, Clone, Default)]
+pub struct AsyncStore(Store);
+
+impl AsyncStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The blocking handle to the same records.
+    pub fn into_blocking(self) -> Store {
+        self.0
+    }
+
+    pub async fn get(&self, k: Bytes) -> Option<Bytes> {
+        self.blocking(move |store| store.get(k)).await
+    }
+
+    /// Like [`Store::set`], inserts a value and returns the one it replaced.
+    pub async fn set(&self, k: Bytes, v: Bytes) -> Option<Bytes> {
+        self.blocking(move |store| store.set(k, v)).await
+    }
+
+    /// Removes a value, returning it if it was present.
+    pub async fn remove(&self, k: Bytes) -> Option<Bytes> {
+        self.blocking(move |store| store.remove(k)).await
+    }
+
+    /// Like [`Store::update`], atomically replaces the value of `k` with
+    /// `f(current)` and returns the new value. `f` runs under the lock, so it
+    /// must not call back into the store.
+    pub async fn update<F>(&self, k: Bytes, f: F) -> Option<Bytes>
+    where
+        F: FnOnce(Option<Bytes>) -> Option<Bytes> + Send + 'static,
+    {
+        self.blocking(move |store| store.update(k, f)).await
+    }
+
+    pub async fn contains_key(&self, k: Bytes) -> bool {
+        self.blocking(move |store| store.contains_key(&k)).await
+    }
+
+    pub async fn len(&self) -> usize {
+        self.blocking(Store::len).await
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.blocking(Store::is_empty).await
+    }
+
+    /// Like [`Store::watch`], but the events arrive on a Tokio channel.
+    pub async fn watch(&self, k: Bytes) -> mpsc::Receiver<WatchEvent> {
+        let (sender, receiver) = mpsc::channel(WATCH_BUFFER);
+        self.blocking(move |store| store.add_watcher(k, Watcher::Async(sender)))
+            .await;
+        receiver
+    }
+
+    // Runs `op` on the blocking pool, passing on any panic as if it had
+    // happened here
+    async fn blocking<T, F>(&self, op: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&Store) -> T + Send + 'static,
+    {
+        let store = self.0.clone();
+        match task::spawn_blocking(move || op(&store)).await {
+            Ok(result) => result,
+            Err(err) => match err.try_into_panic() {
+                Ok(payload) => panic::resume_unwind(payload),
+                Err(err) => panic!("store operation was cancelled: {err}"),
+            },
+        }
+    }
+}
+
+impl From<Store> for AsyncStore {
+    fn from(store: Store) -> Self {
+        Self(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncStore;
+    use crate::{Store, StoreError, WatchEvent};
+    use bytes::Bytes;
+    use std::{sync::mpsc, thread, time::Duration};
+
+    #[tokio::test]
+    async fn set_get_remove() {
+        let store = AsyncStore::new();
+        assert_eq!(store.set(Bytes::from("k"), Bytes::from("v")).await, None);
+        assert_eq!(store.get(Bytes::from("k")).await, Some(Bytes::from("v")));
+
+        let handle = store.clone();
+        tokio::spawn(async move { handle.remove(Bytes::from("k")).await })
+            .await
+            .unwrap();
+        assert!(store.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn shares_records_with_blocking_store() {
+        let store = Store::new();
+        let handle = AsyncStore::from(store.clone());
+        store.set(Bytes::from("sync"), Bytes::from("1"));
+        handle.set(Bytes::from("async"), Bytes::from("2")).await;
+
+        assert_eq!(
+            handle.get(Bytes::from("sync")).await,
+            Some(Bytes::from("1"))
+        );
+        assert_eq!(store.get(Bytes::from("async")), Some(Bytes::from("2")));
+        assert_eq!(handle.into_blocking(), store);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_updates_are_not_lost() {
+        let store = AsyncStore::new();
+        let tasks: Vec<_> = (0..64)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for _ in 0..100 {
+                        store
+                            .update(Bytes::from("n"), |v| {
+                                let n = v.map_or(0, |v| v.len());
+                                Some(Bytes::from(vec![0; n + 1]))
+                            })
+                            .await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(store.get(Bytes::from("n")).await.unwrap().len(), 6400);
+    }
+
+    #[tokio::test]
+    async fn long_write_does_not_stall_other_tasks() {
+        let store = Store::new();
+        let (locked, wait) = mpsc::channel();
+        let writer = {
+            let store = store.clone();
+            thread::spawn(move || {
+                store.transaction(|txn| {
+                    txn.set(Bytes::from("k"), Bytes::from("v"));
+                    locked.send(()).unwrap();
+                    thread::sleep(Duration::from_millis(200));
+                    Ok::<_, StoreError>(())
+                })
+            })
+        };
+        wait.recv().unwrap();
+
+        // On this single-threaded runtime, the ticker only gets to run if the
+        // reader isn't blocking the executor
+        let handle = AsyncStore::from(store);
+        let reader = tokio::spawn(async move { handle.get(Bytes::from("k")).await });
+        let ticker = tokio::spawn(async {
+            for _ in 0..100 {
+                tokio::task::yield_now().await;
+            }
+        });
+        ticker.await.unwrap();
+        assert!(!reader.is_finished());
+
+        assert_eq!(reader.await.unwrap(), Some(Bytes::from("v")));
+        writer.join().unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn watch_delivers_on_tokio_channel() {
+        let store = AsyncStore::new();
+        let mut events = store.watch(Bytes::from("k")).await;
+
+        store.set(Bytes::from("k"), Bytes::from("v")).await;
+        store.into_blocking().remove(Bytes::from("k"));
+        assert_eq!(events.recv().await, Some(WatchEvent::Set(Bytes::from("v"))));
+        assert_eq!(events.recv().await, Some(WatchEvent::Removed));
+    }
+}
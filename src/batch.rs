@@ -0,0 +1,157 @@
+use bytes::Bytes;
+
+use crate::{Store, StoreError};
+
+/// A sequence of sets and removes that [`Store::apply`] applies under a single
+/// lock acquisition, so no other handle observes it half-applied.
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    ops: Vec<BatchOp>,
+}
+
+#[derive(Debug, Clone)]
+enum BatchOp {
+    Set(Bytes, Bytes),
+    Remove(Bytes),
+}
+
+/// What a call to [`Store::apply`] changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    /// The number of sets applied.
+    pub inserted: usize,
+    /// The number of removes that found a live entry to remove.
+    pub removed: usize,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues an insert. Like [`Store::set`], it replaces any TTL on the key
+    /// with the store's default TTL, or clears it if there is none.
+    pub fn set(&mut self, k: Bytes, v: Bytes) -> &mut Self {
+        self.ops.push(BatchOp::Set(k, v));
+        self
+    }
+
+    pub fn remove(&mut self, k: Bytes) -> &mut Self {
+        self.ops.push(BatchOp::Remove(k));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl Store {
+    /// Applies every operation in `batch`, in order, under a single lock
+    /// acquisition. An empty batch returns without taking the lock.
+    ///
+    /// The batch is all or nothing: operations that would break the store's
+    /// [`StoreOptions`] fail it before anything is applied, and the batch
+    /// reaches the write-ahead log as a single record, so a failing append
+    /// applies none of it either.
+    ///
+    /// [`StoreOptions`]: crate::StoreOptions
+    pub fn apply(&self, batch: Batch) -> Result<BatchStats, StoreError> {
+        if batch.is_empty() {
+            return Ok(BatchStats::default());
+        }
+
+        let writes: Vec<_> = batch
+            .ops
+            .into_iter()
+            .map(|op| match op {
+                BatchOp::Set(k, v) => (k, Some(v)),
+                BatchOp::Remove(k) => (k, None),
+            })
+            .collect();
+        let removals: Vec<bool> = writes.iter().map(|(_, v)| v.is_none()).collect();
+        self.mutate(|state| {
+            let replaced = state.write_all(writes)?;
+            let removed = replaced
+                .iter()
+                .zip(&removals)
+                .filter(|(prev, removal)| **removal && prev.is_some())
+                .count();
+            Ok(BatchStats {
+                inserted: removals.len() - removals.iter().filter(|r| **r).count(),
+                removed,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Batch, BatchStats};
+    use crate::Store;
+    use bytes::Bytes;
+    use std::thread;
+
+    #[test]
+    fn applies_in_order() {
+        let store = Store::new();
+        store.set(Bytes::from("stale"), Bytes::from("v"));
+
+        let mut batch = Batch::new();
+        batch
+            .set(Bytes::from("a"), Bytes::from("1"))
+            .set(Bytes::from("b"), Bytes::from("2"))
+            .remove(Bytes::from("b"))
+            .remove(Bytes::from("stale"))
+            .remove(Bytes::from("missing"));
+
+        let stats = store.apply(batch).unwrap();
+        assert_eq!(
+            stats,
+            BatchStats {
+                inserted: 2,
+                removed: 2
+            }
+        );
+        assert_eq!(store.get(Bytes::from("a")), Some(Bytes::from("1")));
+        assert_eq!(store.get(Bytes::from("b")), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn empty_batch_is_a_no_op() {
+        let store = Store::new();
+        assert_eq!(store.apply(Batch::new()), Ok(BatchStats::default()));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn readers_never_see_half_a_batch() {
+        let store = Store::new();
+        let keys = [Bytes::from("x"), Bytes::from("y"), Bytes::from("z")];
+
+        let writer = {
+            let store = store.clone();
+            let keys = keys.clone();
+            thread::spawn(move || {
+                for n in 0..500 {
+                    let mut batch = Batch::new();
+                    for k in &keys {
+                        batch.set(k.clone(), Bytes::from(n.to_string()));
+                    }
+                    store.apply(batch).unwrap();
+                }
+            })
+        };
+
+        while !writer.is_finished() {
+            let values = store.get_many(&keys);
+            assert!(values.windows(2).all(|pair| pair[0] == pair[1]));
+        }
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+//! Inspects and edits a store saved with `Store::save_to_path`.
+//!
+//! ```text
+//! kvs [--hex] get <file> <key>
+//! kvs [--hex] set <file> <key> <value>
+//! kvs [--hex] del <file> <key>
+//! kvs [--hex] keys <file> [prefix]
+//! kvs [--hex] dump <file>
+//! ```
+//!
+//! Keys and values are read and printed as UTF-8, or as hex with `--hex`.
+//! `set` creates the file if it doesn't exist yet, and `dump` prints one JSON
+//! object per record. Exits with `1` if a key wasn't found and `2` on any
+//! other failure.
+
+use std::{
+    env,
+    io::{self, Write},
+    path::Path,
+    process::ExitCode,
+};
+
+use bytes::Bytes;
+use kvs::Store;
+use serde_json::json;
+
+const USAGE: &str = "usage: kvs [--hex] <get|set|del|keys|dump> <file> [args...]";
+
+enum Failure {
+    NotFound,
+    Error(String),
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        Self::Error(err.to_string())
+    }
+}
+
+// How keys and values are written on the command line
+#[derive(Clone, Copy)]
+enum Format {
+    Utf8,
+    Hex,
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let format = match args.iter().position(|arg| arg == "--hex") {
+        Some(i) => {
+            args.remove(i);
+            Format::Hex
+        }
+        None => Format::Utf8,
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match run(&args, format) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::NotFound) => ExitCode::from(1),
+        Err(Failure::Error(message)) => {
+            eprintln!("kvs: {message}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run(args: &[&str], format: Format) -> Result<(), Failure> {
+    let mut out = io::stdout().lock();
+    match args {
+        ["get", file, k] => {
+            let v = load(file)?.get(format.parse(k)?).ok_or(Failure::NotFound)?;
+            out.write_all(&format.print(&v))?;
+            writeln!(out)?;
+        }
+        ["set", file, k, v] => {
+            let store = match Path::new(file).exists() {
+                true => load(file)?,
+                false => Store::new(),
+            };
+            store.set(format.parse(k)?, format.parse(v)?);
+            store.save_to_path(file)?;
+        }
+        ["del", file, k] => {
+            let store = load(file)?;
+            store.remove(format.parse(k)?).ok_or(Failure::NotFound)?;
+            store.save_to_path(file)?;
+        }
+        ["keys", file, prefix @ ..] if prefix.len() <= 1 => {
+            let prefix = match prefix.first() {
+                Some(prefix) => format.parse(prefix)?,
+                None => Bytes::new(),
+            };
+            let mut keys: Vec<Bytes> = load(file)?
+                .scan_prefix(&prefix)
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect();
+            keys.sort();
+            for k in keys {
+                out.write_all(&format.print(&k))?;
+                writeln!(out)?;
+            }
+        }
+        ["dump", file] => {
+            let mut records = load(file)?.snapshot();
+            records.sort();
+            for (k, v) in records {
+                let line = json!({ "key": format.text(&k)?, "value": format.text(&v)? });
+                writeln!(out, "{line}")?;
+            }
+        }
+        _ => return Err(Failure::Error(USAGE.to_string())),
+    }
+    Ok(())
+}
+
+fn load(file: &str) -> Result<Store, Failure> {
+    Store::load_from_path(file).map_err(|err| Failure::Error(format!("{file}: {err}")))
+}
+
+impl Format {
+    fn parse(self, arg: &str) -> Result<Bytes, Failure> {
+        match self {
+            Self::Utf8 => Ok(Bytes::copy_from_slice(arg.as_bytes())),
+            Self::Hex => {
+                // An odd length leaves the last pair out of bounds
+                (0..arg.len())
+                    .step_by(2)
+                    .map(|i| {
+                        arg.get(i..i + 2)
+                            .filter(|pair| pair.bytes().all(|b| b.is_ascii_hexdigit()))
+                            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    })
+                    .collect::<Option<Vec<u8>>>()
+                    .map(Bytes::from)
+                    .ok_or_else(|| Failure::Error(format!("{arg:?} is not valid hex")))
+            }
+        }
+    }
+
+    // The bytes to print for `v`, which are `v` itself unless printing hex
+    fn print(self, v: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Utf8 => v.to_vec(),
+            Self::Hex => v
+                .iter()
+                .flat_map(|b| format!("{b:02x}").into_bytes())
+                .collect(),
+        }
+    }
+
+    // `v` as a string, for formats that can't hold raw bytes
+    fn text(self, v: &[u8]) -> Result<String, Failure> {
+        String::from_utf8(self.print(v))
+            .map_err(|_| Failure::Error("record is not valid UTF-8, try --hex".to_string()))
+    }
+}
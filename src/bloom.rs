@@ -0,0 +1,317 @@
+use std::{
+    f64::consts::LN_2,
+    fmt::{self, Debug},
+    hash::{BuildHasher, DefaultHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bytes::Bytes;
+
+use crate::{State, Store};
+
+// A Bloom filter over every key ever set, kept outside the store's lock so
+// that lookups of keys it has never seen don't need to take it
+pub(crate) struct Bloom {
+    bits: Box<[AtomicU64]>,
+    hashes: u32,
+    // What the lookups it answered turned out to be, behind `BloomStats`
+    skipped: AtomicU64,
+    hits: AtomicU64,
+    false_positives: AtomicU64,
+}
+
+/// How well a store's Bloom filter is working, as returned by
+/// [`Store::bloom_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BloomStats {
+    /// Lookups the filter answered alone, without taking the lock.
+    pub skipped: u64,
+    /// Lookups the filter let through that found the key.
+    pub hits: u64,
+    /// Lookups the filter let through that found nothing, either because the
+    /// key was removed or because its bits collided with other keys'. A rising
+    /// share of these is the cue to call [`Store::rebuild_filter`].
+    pub false_positives: u64,
+}
+
+impl Store {
+    /// Creates a store that keeps a Bloom filter of its keys, sized for
+    /// `expected_items` keys with a false positive rate of `fp_rate`. A `get`
+    /// of a key the filter has never seen returns `None` without taking the
+    /// lock, which speeds up stores that see many lookups of missing keys.
+    ///
+    /// `contains_key` takes the same shortcut. Removing a key can't take it
+    /// out of the filter, so removed keys, like false positives, still take
+    /// the lock to find out they're absent until [`Store::rebuild_filter`]
+    /// runs. Going well past `expected_items` raises the false positive rate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fp_rate` isn't strictly between `0` and `1`.
+    pub fn with_bloom(expected_items: usize, fp_rate: f64) -> Self {
+        assert!(
+            fp_rate > 0.0 && fp_rate < 1.0,
+            "a false positive rate must be between 0 and 1"
+        );
+        Self::from_state(State {
+            bloom: Some(Arc::new(Bloom::new(expected_items.max(1), fp_rate))),
+            ..State::new(Default::default(), Default::default())
+        })
+    }
+
+    /// How the lookups the Bloom filter saw have turned out, or `None` if the
+    /// store doesn't keep one. Like [`Store::stats`], the counters are read
+    /// one at a time.
+    pub fn bloom_stats(&self) -> Option<BloomStats> {
+        let bloom = self.0.bloom.as_ref()?;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Some(BloomStats {
+            skipped: load(&bloom.skipped),
+            hits: load(&bloom.hits),
+            false_positives: load(&bloom.false_positives),
+        })
+    }
+}
+
+impl<S: BuildHasher> Store<S> {
+    /// Rebuilds the Bloom filter from the keys currently stored, so that keys
+    /// removed since it was built no longer get past it. Writes wait for a pass
+    /// over every key, though lookups carry on. Does nothing if the store doesn't keep
+    /// a filter or the lock can't be taken.
+    pub fn rebuild_filter(&self) {
+        let _ = self.read_state(|state| {
+            if let Some(bloom) = &state.bloom {
+                bloom.rebuild(state.records.keys());
+            }
+        });
+    }
+
+    // `true` if the filter knows `k` is absent, in which case the lookup
+    // needn't go any further
+    pub(crate) fn filtered_out(&self, k: &Bytes) -> bool {
+        let Some(bloom) = &self.0.bloom else {
+            return false;
+        };
+        let absent = !bloom.may_contain(k);
+        if absent {
+            bloom.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        absent
+    }
+
+    // Counts how a lookup the filter let through turned out
+    pub(crate) fn filter_passed(&self, found: bool) {
+        if let Some(bloom) = &self.0.bloom {
+            let counter = match found {
+                true => &bloom.hits,
+                false => &bloom.false_positives,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Bloom {
+    fn new(expected_items: usize, fp_rate: f64) -> Self {
+        let n = expected_items as f64;
+        let bits = (-n * fp_rate.ln() / (LN_2 * LN_2)).ceil().max(64.0);
+        let hashes = (bits / n * LN_2).round().clamp(1.0, 32.0) as u32;
+        let words = (bits as usize).div_ceil(64);
+        Self {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            hashes,
+            skipped: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            false_positives: AtomicU64::new(0),
+        }
+    }
+
+    // Clears every bit but those of `keys`. The words are replaced one at a
+    // time while lookups carry on outside the lock, which is safe as long as
+    // `keys` are all the filter's keys that are still present: their bits are
+    // set both before and after each word is replaced
+    fn rebuild<'a>(&self, keys: impl Iterator<Item = &'a Bytes>) {
+        let mut bits = vec![0; self.bits.len()];
+        for k in keys {
+            for (word, mask) in self.positions(k) {
+                bits[word] |= mask;
+            }
+        }
+        for (word, bits) in self.bits.iter().zip(bits) {
+            word.store(bits, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn insert(&self, k: &Bytes) {
+        for (word, mask) in self.positions(k) {
+            self.bits[word].fetch_or(mask, Ordering::Relaxed);
+        }
+    }
+
+    // `false` only if `k` was never inserted
+    pub(crate) fn may_contain(&self, k: &Bytes) -> bool {
+        self.positions(k)
+            .all(|(word, mask)| self.bits[word].load(Ordering::Relaxed) & mask != 0)
+    }
+
+    // The word and bit of each of `k`'s hashes, derived from a single hash by
+    // double hashing
+    fn positions(&self, k: &Bytes) -> impl Iterator<Item = (usize, u64)> {
+        let mut hasher = DefaultHasher::new();
+        k.hash(&mut hasher);
+        let h1 = hasher.finish();
+        let h2 = h1.rotate_left(32) | 1;
+        let len = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % len;
+            ((bit / 64) as usize, 1 << (bit % 64))
+        })
+    }
+}
+
+impl Debug for Bloom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bloom")
+            .field("bits", &(self.bits.len() * 64))
+            .field("hashes", &self.hashes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bloom, BloomStats};
+    use crate::Store;
+    use bytes::Bytes;
+    use std::{collections::HashMap, sync::mpsc, thread, time::Duration};
+
+    #[test]
+    fn finds_inserted_keys_and_misses_others() {
+        let store = Store::with_bloom(1000, 0.01);
+        for i in 0..1000 {
+            store.set(Bytes::from(format!("key{i}")), Bytes::from("v"));
+        }
+        store.remove(Bytes::from("key0"));
+
+        for i in 1..1000 {
+            assert!(store.contains_key(&Bytes::from(format!("key{i}"))));
+            assert_eq!(
+                store.get(Bytes::from(format!("key{i}"))),
+                Some(Bytes::from("v"))
+            );
+        }
+        assert_eq!(store.get(Bytes::from("key0")), None);
+        assert_eq!(store.get(Bytes::from("never")), None);
+    }
+
+    #[test]
+    fn false_positives_stay_near_the_rate() {
+        let bloom = Bloom::new(1000, 0.01);
+        for i in 0..1000 {
+            bloom.insert(&Bytes::from(format!("key{i}")));
+        }
+        let positives = (0..10_000)
+            .filter(|i| bloom.may_contain(&Bytes::from(format!("other{i}"))))
+            .count();
+        assert!(positives < 300, "{positives} false positives");
+    }
+
+    #[test]
+    fn definite_misses_skip_the_lock() {
+        let store = Store::with_bloom(100, 0.01);
+        store.set(Bytes::from("k"), Bytes::from("v"));
+
+        let (locked, wait) = mpsc::channel();
+        let holder = {
+            let store = store.clone();
+            thread::spawn(move || {
+                store
+                    .with_write(|_| {
+                        locked.send(()).unwrap();
+                        thread::sleep(Duration::from_millis(100));
+                    })
+                    .unwrap();
+            })
+        };
+        wait.recv().unwrap();
+
+        assert_eq!(
+            store.try_get_for(Bytes::from("never"), Duration::ZERO),
+            Ok(None)
+        );
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn counts_how_lookups_turn_out() {
+        let store = Store::with_bloom(100, 0.01);
+        assert_eq!(Store::new().bloom_stats(), None);
+        store.set(Bytes::from("k"), Bytes::from("v"));
+        store.set(Bytes::from("gone"), Bytes::from("v"));
+        store.remove(Bytes::from("gone"));
+
+        store.get(Bytes::from("k"));
+        store.get(Bytes::from("gone"));
+        store.contains_key(&Bytes::from("never"));
+        assert_eq!(
+            store.bloom_stats(),
+            Some(BloomStats {
+                skipped: 1,
+                hits: 1,
+                false_positives: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn rebuilding_forgets_removed_keys() {
+        let store = Store::with_bloom(100, 0.01);
+        store.set(Bytes::from("k"), Bytes::from("v"));
+        store.set(Bytes::from("gone"), Bytes::from("v"));
+        store.remove(Bytes::from("gone"));
+
+        store.rebuild_filter();
+        assert!(!store.contains_key(&Bytes::from("gone")));
+        assert!(store.contains_key(&Bytes::from("k")));
+        assert_eq!(store.bloom_stats().unwrap().skipped, 1);
+    }
+
+    #[test]
+    fn never_a_false_negative() {
+        // A tiny filter over a small key space, so that collisions, removals
+        // and rebuilds all happen often
+        let store = Store::with_bloom(8, 0.1);
+        let mut model = HashMap::new();
+        // xorshift64, seeded so that failures reproduce
+        let mut seed = 0x2545f4914f6cdd1d_u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for i in 0..20_000 {
+            let r = next();
+            let k = Bytes::from(format!("key{}", r % 64));
+            match (r >> 32) % 10 {
+                0..=3 => {
+                    let v = Bytes::from(i.to_string());
+                    store.set(k.clone(), v.clone());
+                    model.insert(k, v);
+                }
+                4..=5 => {
+                    assert_eq!(store.remove(k.clone()), model.remove(&k));
+                }
+                6 if i % 100 == 0 => store.rebuild_filter(),
+                _ => {
+                    assert_eq!(store.contains_key(&k), model.contains_key(&k));
+                    assert_eq!(store.get(k.clone()), model.get(&k).cloned());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeSet;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{Store, StoreError};
+
+// Bytes taken by the namespace length at the front of every bucketed key
+const LEN_PREFIX: usize = 8;
+
+/// A namespace within a [`Store`], returned by [`Store::bucket`].
+///
+/// Every key is stored prefixed by the bucket's name and that name's length,
+/// so two buckets never see each other's keys, even when one name is a prefix
+/// of the other. The prefixed keys share the store with any written to it
+/// directly, so a store used through buckets shouldn't be written to
+/// otherwise.
+///
+/// Like `Store`, cloning a `Bucket` shares the same records.
+#[derive(Debug, Clone)]
+pub struct Bucket {
+    store: Store,
+    name: Bytes,
+    prefix: Bytes,
+}
+
+/// A keyspace of its own within a [`Store`], e.g. for one tenant, returned
+/// by [`Store::namespace`]. It is a [`Bucket`] named after the prefix.
+///
+/// Keys are kept apart by the bucket's length-prefixed name rather than by
+/// `prefix + 0x00`, since prefixes and keys may hold zero bytes themselves:
+/// with a separator, key `\0b` in namespace `a` and key `b` in namespace
+/// `a\0` would both be stored as `a\0\0b`.
+pub type Namespace = Bucket;
+
+impl Store {
+    /// Returns the namespace for `prefix`, which sees only the keys written
+    /// through it.
+    pub fn namespace(&self, prefix: Bytes) -> Namespace {
+        self.bucket(&prefix)
+    }
+
+    /// Returns the bucket called `name`. Buckets need no creating: one exists
+    /// for as long as it holds a key.
+    pub fn bucket(&self, name: &[u8]) -> Bucket {
+        let mut prefix = BytesMut::with_capacity(LEN_PREFIX + name.len());
+        prefix.put_u64(name.len() as u64);
+        prefix.put_slice(name);
+        Bucket {
+            store: self.clone(),
+            name: Bytes::copy_from_slice(name),
+            prefix: prefix.freeze(),
+        }
+    }
+
+    /// The names of the buckets holding at least one live key, sorted by
+    /// their raw bytes.
+    pub fn buckets(&self) -> Vec<Bytes> {
+        let names: BTreeSet<Bytes> = self
+            .keys()
+            .into_iter()
+            .filter_map(|k| {
+                let len = u64::from_be_bytes(k.get(..LEN_PREFIX)?.try_into().ok()?);
+                let end = LEN_PREFIX.checked_add(len.try_into().ok()?)?;
+                (end <= k.len()).then(|| k.slice(LEN_PREFIX..end))
+            })
+            .collect();
+        names.into_iter().collect()
+    }
+}
+
+impl Bucket {
+    pub fn name(&self) -> &Bytes {
+        &self.name
+    }
+
+    pub fn get(&self, k: Bytes) -> Option<Bytes> {
+        self.store.get(self.key(&k))
+    }
+
+    /// Inserts a value, returning the one it replaced.
+    pub fn set(&self, k: Bytes, v: Bytes) -> Option<Bytes> {
+        self.store.set(self.key(&k), v)
+    }
+
+    /// Removes a value, returning it if it was present.
+    pub fn remove(&self, k: Bytes) -> Option<Bytes> {
+        self.store.remove(self.key(&k))
+    }
+
+    /// Like [`Bucket::get`], but reports why a lookup failed.
+    pub fn try_get(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.store.try_get(self.key(&k))
+    }
+
+    /// Like [`Bucket::set`], but reports why a write failed.
+    pub fn try_set(&self, k: Bytes, v: Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.store.try_set(self.key(&k), v)
+    }
+
+    /// Like [`Bucket::remove`], but reports why a removal failed.
+    pub fn try_remove(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.store.try_remove(self.key(&k))
+    }
+
+    pub fn contains_key(&self, k: &Bytes) -> bool {
+        self.store.contains_key(&self.key(k))
+    }
+
+    /// The bucket's live entries, with keys stripped of their prefix, in no
+    /// particular order.
+    pub fn scan(&self) -> Vec<(Bytes, Bytes)> {
+        self.store
+            .scan_prefix(&self.prefix)
+            .into_iter()
+            .map(|(k, v)| (k.slice(self.prefix.len()..), v))
+            .collect()
+    }
+
+    /// The keys of a [`Bucket::scan`].
+    pub fn keys(&self) -> Vec<Bytes> {
+        self.scan().into_iter().map(|(k, _)| k).collect()
+    }
+
+    /// Removes every key in the bucket, leaving the rest of the store alone.
+    /// Returns how many were removed.
+    pub fn clear(&self) -> usize {
+        self.store.remove_prefix(&self.prefix)
+    }
+
+    // The key `k` is stored under in the underlying store
+    fn key(&self, k: &[u8]) -> Bytes {
+        let mut key = BytesMut::with_capacity(self.prefix.len() + k.len());
+        key.put_slice(&self.prefix);
+        key.put_slice(k);
+        key.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Store;
+    use bytes::Bytes;
+
+    #[test]
+    fn buckets_do_not_collide() {
+        let store = Store::new();
+        let (users, orders) = (store.bucket(b"users"), store.bucket(b"orders"));
+        users.set(Bytes::from("1"), Bytes::from("ferris"));
+        orders.set(Bytes::from("1"), Bytes::from("crab cakes"));
+
+        assert_eq!(users.get(Bytes::from("1")), Some(Bytes::from("ferris")));
+        assert_eq!(
+            orders.get(Bytes::from("1")),
+            Some(Bytes::from("crab cakes"))
+        );
+        assert_eq!(store.get(Bytes::from("1")), None);
+        assert_eq!(
+            store.buckets(),
+            [Bytes::from("orders"), Bytes::from("users")]
+        );
+
+        assert_eq!(users.clear(), 1);
+        assert!(users.keys().is_empty());
+        assert_eq!(orders.keys(), [Bytes::from("1")]);
+        assert_eq!(store.buckets(), [Bytes::from("orders")]);
+    }
+
+    #[test]
+    fn namespaces_clear_independently() {
+        let store = Store::new();
+        let (a, b) = (
+            store.namespace(Bytes::from("tenant-a")),
+            store.namespace(Bytes::from("tenant-b")),
+        );
+        for ns in [&a, &b] {
+            ns.set(Bytes::from("k"), ns.name().clone());
+        }
+        assert_eq!(a.get(Bytes::from("k")), Some(Bytes::from("tenant-a")));
+
+        assert_eq!(a.clear(), 1);
+        assert_eq!(a.get(Bytes::from("k")), None);
+        assert_eq!(b.scan(), [(Bytes::from("k"), Bytes::from("tenant-b"))]);
+
+        // A zero byte in a prefix can't forge another namespace's keys
+        let (c, c0) = (
+            store.namespace(Bytes::from("c")),
+            store.namespace(Bytes::from("c\0")),
+        );
+        c.set(Bytes::from("\0k"), Bytes::from("1"));
+        assert_eq!(c0.get(Bytes::from("k")), None);
+    }
+
+    #[test]
+    fn prefixed_names_stay_apart() {
+        let store = Store::new();
+        let (a, ab) = (store.bucket(b"a"), store.bucket(b"ab"));
+        ab.set(Bytes::from("c"), Bytes::from("v"));
+        a.set(Bytes::from("bc"), Bytes::from("w"));
+
+        assert_eq!(a.get(Bytes::from("bc")), Some(Bytes::from("w")));
+        assert_eq!(ab.get(Bytes::from("c")), Some(Bytes::from("v")));
+        assert_eq!(a.keys(), [Bytes::from("bc")]);
+        assert_eq!(ab.scan(), [(Bytes::from("c"), Bytes::from("v"))]);
+        assert_eq!(a.clear(), 1);
+        assert!(ab.contains_key(&Bytes::from("c")));
+    }
+}
@@ -0,0 +1,301 @@
+use std::time::Duration;
+
+use crate::{
+    evict::Capacity, Expiries, Limit, LruPolicy, Records, ShardedStore, State, Store, StoreOptions,
+};
+#[cfg(feature = "compression")]
+use crate::{CompressedStore, CompressionKind};
+
+/// Configures a [`Store`] one option at a time, returned by
+/// [`Store::builder`].
+///
+/// Options that need a different kind of store are only honored by the
+/// matching `build_*` method: [`StoreBuilder::shards`] by
+/// [`StoreBuilder::build_sharded`] and `compression` by `build_compressed`.
+/// Every `build_*` method panics if given an option the store it returns
+/// can't honor, rather than quietly dropping it.
+#[derive(Debug, Clone, Default)]
+pub struct StoreBuilder {
+    capacity: Option<usize>,
+    memory_limit: Option<usize>,
+    default_ttl: Option<Duration>,
+    shards: Option<usize>,
+    #[cfg(feature = "compression")]
+    compression: Option<CompressionKind>,
+}
+
+impl Store {
+    /// Starts configuring a store. [`Store::new`] is the same as
+    /// `Store::builder().build()`.
+    pub fn builder() -> StoreBuilder {
+        StoreBuilder::default()
+    }
+}
+
+impl StoreBuilder {
+    /// Holds at most `max_entries` records, evicting the least recently used
+    /// one like [`Store::with_capacity`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_entries` is zero.
+    pub fn capacity(mut self, max_entries: usize) -> Self {
+        assert!(max_entries > 0, "a store needs room for at least one entry");
+        self.capacity = Some(max_entries);
+        self
+    }
+
+    /// Fails writes with [`StoreError::Full`](crate::StoreError::Full) rather
+    /// than grow past `max_bytes`, like [`Store::with_memory_limit`]. This
+    /// can be combined with a capacity, in which case a write that would go
+    /// past `max_bytes` first evicts records as the capacity's policy picks
+    /// them, and only fails if the value couldn't fit even then.
+    pub fn memory_limit(mut self, max_bytes: usize) -> Self {
+        self.memory_limit = Some(max_bytes);
+        self
+    }
+
+    /// Makes records written without a TTL of their own expire after `ttl`,
+    /// as if they had been written with [`Store::set_with_ttl`]. This covers
+    /// [`Store::set`] and every write documented as behaving like it, while
+    /// writes that modify a value in place, such as [`Store::append`], keep
+    /// the TTL the key already had.
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Spreads the records across `n` shards. Only
+    /// [`StoreBuilder::build_sharded`] honors this.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn shards(mut self, n: usize) -> Self {
+        assert!(n > 0, "a sharded store needs at least one shard");
+        self.shards = Some(n);
+        self
+    }
+
+    /// Compresses large values with `kind`. Only
+    /// [`StoreBuilder::build_compressed`] honors this.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, kind: CompressionKind) -> Self {
+        self.compression = Some(kind);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// Panics if shards or compression were chosen, which a plain `Store`
+    /// can't provide.
+    pub fn build(self) -> Store {
+        unsupported(self.shards.is_some(), "shards", "build_sharded");
+        #[cfg(feature = "compression")]
+        unsupported(
+            self.compression.is_some(),
+            "compression",
+            "build_compressed",
+        );
+        self.store()
+    }
+
+    /// Builds a [`ShardedStore`] with the chosen number of shards, or one per
+    /// available CPU if none was chosen.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any other option was chosen, since a sharded store supports
+    /// none of them.
+    pub fn build_sharded(self) -> ShardedStore {
+        let others = [
+            ("capacity", self.capacity.is_some()),
+            ("memory_limit", self.memory_limit.is_some()),
+            ("default_ttl", self.default_ttl.is_some()),
+            #[cfg(feature = "compression")]
+            ("compression", self.compression.is_some()),
+        ];
+        for (option, chosen) in others {
+            assert!(!chosen, "a sharded store doesn't support {option}");
+        }
+        match self.shards {
+            Some(n) => ShardedStore::with_shards(n),
+            None => ShardedStore::new(),
+        }
+    }
+
+    /// Builds a [`CompressedStore`] over a store with every other option
+    /// chosen.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no compression was chosen, or if shards were.
+    #[cfg(feature = "compression")]
+    pub fn build_compressed(self) -> CompressedStore {
+        let kind = self
+            .compression
+            .expect("build_compressed needs a compression kind");
+        unsupported(self.shards.is_some(), "shards", "build_sharded");
+        CompressedStore::from_store(self.store(), kind)
+    }
+
+    // The plain store underneath every kind but a sharded one
+    fn store(&self) -> Store {
+        Store::from_state(State {
+            capacity: self
+                .capacity
+                .map(|n| Capacity::new(Limit::Entries(n), LruPolicy::default())),
+            options: StoreOptions {
+                max_total_bytes: self.memory_limit,
+                ..StoreOptions::default()
+            },
+            default_ttl: self.default_ttl,
+            ..State::new(Records::new(), Expiries::new())
+        })
+    }
+}
+
+// Panics if an `option` that only `build` honors was chosen
+fn unsupported(chosen: bool, option: &str, build: &str) {
+    assert!(!chosen, "{option} can only be built with {build}");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Store, StoreError};
+    use bytes::Bytes;
+    use std::{thread, time::Duration};
+
+    fn kv(i: usize) -> (Bytes, Bytes) {
+        (Bytes::from(format!("k{i}")), Bytes::from("v"))
+    }
+
+    #[test]
+    fn capacity_and_memory_limit_together() {
+        let store = Store::builder().capacity(2).memory_limit(1024).build();
+        for i in 0..3 {
+            let (k, v) = kv(i);
+            store.set(k, v);
+        }
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.evictions(), 1);
+
+        let huge = Bytes::from(vec![0; 2048]);
+        assert_eq!(
+            store.try_set(Bytes::from("huge"), huge),
+            Err(StoreError::Full)
+        );
+    }
+
+    #[test]
+    fn full_store_evicts_to_make_room() {
+        // Each record takes up 2 + 200 bytes plus overhead, so three fit
+        let store = Store::builder().capacity(100).memory_limit(1000).build();
+        for i in 0..5 {
+            let k = Bytes::from(format!("k{i}"));
+            assert_eq!(store.try_set(k, Bytes::from(vec![0; 200])), Ok(None));
+        }
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.evictions(), 2);
+        assert_eq!(store.get(Bytes::from("k0")), None);
+        assert!(store.contains_key(&Bytes::from("k4")));
+
+        // Overwriting with a larger value evicts the others, not the key itself
+        let v = Bytes::from(vec![1; 600]);
+        assert!(store.try_set(Bytes::from("k4"), v.clone()).is_ok());
+        assert_eq!(store.get(Bytes::from("k4")), Some(v));
+        assert_eq!(store.len(), 2);
+
+        // Even when the key overwritten is the one the policy would evict
+        let store = Store::builder().capacity(100).memory_limit(1000).build();
+        for i in 0..3 {
+            let k = Bytes::from(format!("k{i}"));
+            assert_eq!(store.try_set(k, Bytes::from(vec![0; 200])), Ok(None));
+        }
+        let v = Bytes::from(vec![1; 600]);
+        assert!(store.try_set(Bytes::from("k0"), v.clone()).is_ok());
+        assert_eq!(store.get(Bytes::from("k0")), Some(v));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(Bytes::from("k1")), None);
+    }
+
+    #[test]
+    fn default_ttl_applies_only_without_a_ttl() {
+        let store = Store::builder()
+            .default_ttl(Duration::from_millis(20))
+            .build();
+        store.set(Bytes::from("short"), Bytes::from("v"));
+        store.set_with_ttl(
+            Bytes::from("long"),
+            Bytes::from("v"),
+            Duration::from_secs(60),
+        );
+        // A TTL too long to represent never expires, rather than falling back
+        // to the default
+        store.set_with_ttl(Bytes::from("forever"), Bytes::from("v"), Duration::MAX);
+        assert!(store.ttl(&Bytes::from("short")).is_some());
+        assert_eq!(store.ttl(&Bytes::from("forever")), None);
+
+        thread::sleep(Duration::from_millis(40));
+        assert_eq!(store.get(Bytes::from("short")), None);
+        assert_eq!(store.get(Bytes::from("long")), Some(Bytes::from("v")));
+        assert_eq!(store.get(Bytes::from("forever")), Some(Bytes::from("v")));
+
+        // Modifying a value in place keeps it from expiring
+        assert_eq!(store.append(Bytes::from("forever"), b"w"), 2);
+        assert_eq!(store.ttl(&Bytes::from("forever")), None);
+    }
+
+    #[test]
+    fn no_options_is_a_plain_store() {
+        let store = Store::builder().build();
+        let (k, v) = kv(0);
+        store.set(k.clone(), v);
+        assert_eq!(store.ttl(&k), None);
+        assert_eq!(store.evictions(), 0);
+    }
+
+    #[test]
+    fn sharded() {
+        let store = Store::builder().shards(3).build_sharded();
+        assert_eq!(store.shard_count(), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_with_capacity() {
+        use crate::CompressionKind;
+
+        let store = Store::builder()
+            .capacity(1)
+            .compression(CompressionKind::Zstd)
+            .build_compressed();
+        let v = Bytes::from(vec![b'a'; 4096]);
+        store.set(Bytes::from("a"), v.clone());
+        store.set(Bytes::from("b"), v.clone());
+        assert_eq!(store.get(Bytes::from("b")), Some(v));
+        assert_eq!(store.store().len(), 1);
+        assert!(store.store().byte_len() < 4096);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one shard")]
+    fn zero_shards() {
+        Store::builder().shards(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "shards can only be built with build_sharded")]
+    fn shards_need_build_sharded() {
+        Store::builder().shards(2).build();
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't support default_ttl")]
+    fn sharded_rejects_ttl() {
+        Store::builder()
+            .shards(2)
+            .default_ttl(Duration::from_secs(1))
+            .build_sharded();
+    }
+}
@@ -0,0 +1,141 @@
+use std::{collections::VecDeque, hash::BuildHasher};
+
+use bytes::Bytes;
+
+use crate::{Expiries, Records, State, Store, WatchEvent};
+
+/// How many of the latest changes a store made with [`Store::with_changelog`]
+/// keeps.
+pub const CHANGELOG_LEN: usize = 4096;
+
+/// A change recorded by a store's changelog, as returned by
+/// [`Store::changes_since`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// The position of the change in the store's history, starting from `1`
+    /// and going up by one per change.
+    pub seq: u64,
+    pub key: Bytes,
+    pub event: WatchEvent,
+}
+
+// The latest changes, oldest first, in a ring of at most `CHANGELOG_LEN`
+#[derive(Debug, Default)]
+pub(crate) struct Changelog {
+    events: VecDeque<ChangeEvent>,
+    seq: u64,
+}
+
+impl Store {
+    /// Creates a store that records every change, through any write path, in
+    /// a changelog that [`Store::changes_since`] reads, e.g. to replicate the
+    /// store elsewhere.
+    ///
+    /// Only the latest [`CHANGELOG_LEN`] changes are kept, and older ones are
+    /// dropped as new ones come in, so a reader that falls too far behind has
+    /// to start again from a copy of the whole store.
+    pub fn with_changelog() -> Self {
+        Self::from_state(State {
+            changelog: Some(Changelog::default()),
+            ..State::new(Records::new(), Expiries::new())
+        })
+    }
+
+    /// The changes made after the one numbered `seq`, oldest first. Pass `0`
+    /// for every change still kept, and then the `seq` of the last change
+    /// received to resume from there.
+    ///
+    /// If the first change returned isn't numbered `seq + 1`, the ones in
+    /// between were dropped from the changelog. Returns nothing if the store
+    /// has no changelog or the lock can't be taken.
+    pub fn changes_since(&self, seq: u64) -> Vec<ChangeEvent> {
+        self.read_state(|state| {
+            let Some(changelog) = &state.changelog else {
+                return Vec::new();
+            };
+            // Sequence numbers are contiguous, so the first change after
+            // `seq` is found by offset
+            let oldest = changelog.seq + 1 - changelog.events.len() as u64;
+            let skip = seq
+                .saturating_sub(oldest - 1)
+                .min(changelog.events.len() as u64);
+            changelog
+                .events
+                .iter()
+                .skip(skip as usize)
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+}
+
+impl<S: BuildHasher> State<S> {
+    // Records a change in the changelog, if there is one, then tells the
+    // watchers of `k` about it
+    pub(crate) fn changed(&mut self, k: &Bytes, event: WatchEvent) {
+        if let Some(changelog) = &mut self.changelog {
+            if changelog.events.len() == CHANGELOG_LEN {
+                changelog.events.pop_front();
+            }
+            changelog.seq += 1;
+            changelog.events.push_back(ChangeEvent {
+                seq: changelog.seq,
+                key: k.clone(),
+                event: event.clone(),
+            });
+        }
+        self.notify(k, event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChangeEvent, CHANGELOG_LEN};
+    use crate::{Store, WatchEvent};
+    use bytes::Bytes;
+
+    #[test]
+    fn records_changes_in_order() {
+        let store = Store::with_changelog();
+        store.set(Bytes::from("a"), Bytes::from("1"));
+        store.set(Bytes::from("b"), Bytes::from("2"));
+        store.remove(Bytes::from("a"));
+        store.remove(Bytes::from("missing"));
+        store.update(Bytes::from("b"), |_| Some(Bytes::from("3")));
+
+        let change = |seq, key, event| ChangeEvent {
+            seq,
+            key: Bytes::from(key),
+            event,
+        };
+        let changes = store.changes_since(0);
+        assert_eq!(
+            changes,
+            [
+                change(1, "a", WatchEvent::Set(Bytes::from("1"))),
+                change(2, "b", WatchEvent::Set(Bytes::from("2"))),
+                change(3, "a", WatchEvent::Removed),
+                change(4, "b", WatchEvent::Set(Bytes::from("3"))),
+            ]
+        );
+        assert_eq!(store.changes_since(2), changes[2..]);
+        assert!(store.changes_since(4).is_empty());
+        assert!(store.changes_since(100).is_empty());
+        assert!(Store::new().changes_since(0).is_empty());
+    }
+
+    #[test]
+    fn drops_the_oldest_changes() {
+        let store = Store::with_changelog();
+        for n in 0..CHANGELOG_LEN + 10 {
+            store.set(Bytes::from("k"), Bytes::from(n.to_string()));
+        }
+
+        let changes = store.changes_since(0);
+        assert_eq!(changes.len(), CHANGELOG_LEN);
+        assert_eq!(changes[0].seq, 11);
+        assert_eq!(store.changes_since(5)[0].seq, 11);
+        assert_eq!(store.changes_since(20)[0].seq, 21);
+    }
+}
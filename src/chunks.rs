@@ -0,0 +1,119 @@
+use std::{collections::VecDeque, time::Instant};
+
+use bytes::Bytes;
+
+use crate::Store;
+
+/// An iterator over the records of a [`Store`] a chunk at a time, returned by
+/// [`Store::snapshot_chunked`].
+///
+/// The iteration is weakly consistent: the lock is released between chunks,
+/// so a record written or removed meanwhile may or may not be seen. A record
+/// is never yielded twice, and one that is overwritten is yielded with
+/// whichever value it has when its chunk is copied.
+#[derive(Debug)]
+pub struct SnapshotIter {
+    store: Store,
+    chunk_size: usize,
+    // The keys yet to be visited, as of when iteration started
+    keys: VecDeque<Bytes>,
+}
+
+impl Store {
+    /// Copies out the live records `chunk_size` at a time, only taking the
+    /// lock long enough to copy each chunk, so that writers don't wait on a
+    /// copy of the whole store. See [`SnapshotIter`] for what it sees of
+    /// writes made while it runs.
+    ///
+    /// The keys are listed up front, under a single lock acquisition, which
+    /// costs a fraction of copying out the records themselves. Records
+    /// written after that aren't visited. Ends early if the lock can't be
+    /// taken.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn snapshot_chunked(&self, chunk_size: usize) -> SnapshotIter {
+        assert!(chunk_size > 0, "a chunk needs room for at least one record");
+        let keys = self
+            .read_state(|guard| guard.records.keys().cloned().collect())
+            .unwrap_or_default();
+        SnapshotIter {
+            store: self.clone(),
+            chunk_size,
+            keys,
+        }
+    }
+}
+
+impl Iterator for SnapshotIter {
+    type Item = Vec<(Bytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let now = Instant::now();
+        let keys = &mut self.keys;
+        let chunk_size = self.chunk_size;
+        let chunk = self
+            .store
+            .read_state(|guard| {
+                let mut chunk = Vec::with_capacity(chunk_size.min(keys.len()));
+                // Keys removed or expired since they were listed are skipped
+                while chunk.len() < chunk_size {
+                    let Some(k) = keys.pop_front() else {
+                        break;
+                    };
+                    if let Some(v) = guard.get(&k, now).cloned() {
+                        chunk.push((k, v));
+                    }
+                }
+                chunk
+            })
+            .ok()?;
+        match chunk.is_empty() {
+            true => None,
+            false => Some(chunk),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Store;
+    use bytes::Bytes;
+    use std::collections::HashMap;
+
+    #[test]
+    fn visits_every_record_once() {
+        let store: Store = (0..9).map(|i| (format!("k{i}"), format!("v{i}"))).collect();
+
+        let chunks: Vec<_> = store.snapshot_chunked(2).collect();
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 2));
+        let visited: HashMap<Bytes, Bytes> = chunks.into_iter().flatten().collect();
+        assert_eq!(visited, store.snapshot().into_iter().collect());
+    }
+
+    #[test]
+    fn writes_between_chunks() {
+        let store: Store = [("a", "1"), ("b", "1"), ("c", "1")].into_iter().collect();
+        let mut chunks = store.snapshot_chunked(1);
+        let first = chunks.next().unwrap();
+
+        // Of the two not yet visited, one is removed and the other overwritten
+        let rest: Vec<Bytes> = ["a", "b", "c"]
+            .into_iter()
+            .map(Bytes::from)
+            .filter(|k| *k != first[0].0)
+            .collect();
+        store.remove(rest[0].clone());
+        store.set(rest[1].clone(), Bytes::from("2"));
+        store.set(Bytes::from("d"), Bytes::from("1"));
+
+        assert_eq!(
+            chunks.next(),
+            Some(vec![(rest[1].clone(), Bytes::from("2"))])
+        );
+        assert_eq!(chunks.next(), None);
+        assert_eq!(Store::new().snapshot_chunked(4).next(), None);
+    }
+}
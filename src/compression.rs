@@ -0,0 +1,442 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    io::{self, ErrorKind, Read, Write},
+    time::Instant,
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{lz4, persist, Records, State, Store, StoreError};
+
+// The header byte in front of every value, saying how the rest is encoded
+const RAW: u8 = 0;
+const ZSTD: u8 = 1;
+const LZ4: u8 = 2;
+
+/// Values shorter than this are stored uncompressed by default.
+pub const DEFAULT_THRESHOLD: usize = 512;
+
+// Zstd's own default level
+const DEFAULT_LEVEL: i32 = 0;
+
+/// The algorithm a [`CompressedStore`] compresses values with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CompressionKind {
+    Zstd,
+    /// Faster than zstd to compress and decompress, at a lower ratio.
+    Lz4,
+}
+
+/// A [`Store`] that compresses large values on the way in and decompresses
+/// them on the way out, returned by [`Store::with_compression`].
+///
+/// Each stored value starts with a header byte saying whether the rest is
+/// compressed, so values written with different settings can be read back
+/// together. That header means the underlying store's values should only be
+/// read and written through a `CompressedStore`.
+///
+/// Every read, including [`CompressedStore::snapshot`] and
+/// [`CompressedStore::dump`], sees the values as they were written, never
+/// their encoding.
+///
+/// Like `Store`, cloning a `CompressedStore` shares the same records.
+#[derive(Debug, Clone)]
+pub struct CompressedStore {
+    store: Store,
+    kind: CompressionKind,
+    threshold: usize,
+    level: i32,
+}
+
+/// Errors surfaced by the fallible `try_*` methods of a [`CompressedStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CompressionError {
+    /// A stored value has an unknown header or doesn't decompress.
+    Corrupt,
+    /// The underlying store failed.
+    Store(StoreError),
+}
+
+impl Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Corrupt => write!(f, "stored value is not validly compressed"),
+            Self::Store(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl Error for CompressionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Corrupt => None,
+            Self::Store(err) => Some(err),
+        }
+    }
+}
+
+impl From<StoreError> for CompressionError {
+    fn from(err: StoreError) -> Self {
+        Self::Store(err)
+    }
+}
+
+impl Store {
+    /// Creates a store that compresses values of at least
+    /// [`DEFAULT_THRESHOLD`] bytes with `kind`.
+    pub fn with_compression(kind: CompressionKind) -> CompressedStore {
+        CompressedStore::from_store(Store::new(), kind)
+    }
+}
+
+impl CompressedStore {
+    /// Wraps an existing store. Its values must have been written by a
+    /// `CompressedStore` to be readable.
+    pub fn from_store(store: Store, kind: CompressionKind) -> Self {
+        Self {
+            store,
+            kind,
+            threshold: DEFAULT_THRESHOLD,
+            level: DEFAULT_LEVEL,
+        }
+    }
+
+    /// Restores a store from a snapshot of uncompressed values, such as one
+    /// written by [`CompressedStore::dump`] or [`Store::dump`], compressing
+    /// them with `kind` as they're loaded.
+    pub fn load<R: Read>(r: &mut R, kind: CompressionKind) -> io::Result<Self> {
+        let (records, expiries) = persist::read_records(r)?;
+        let mut store = Self::from_store(Store::default(), kind);
+        let records = records
+            .into_iter()
+            .map(|(k, v)| (k, store.encode(&v)))
+            .collect();
+        store.store = Store::from_state(State::new(records, expiries));
+        Ok(store)
+    }
+
+    /// Compresses only values of at least `threshold` bytes,
+    /// since compressing small ones costs more time than it saves space.
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Compresses at `level`, where higher levels trade speed for smaller
+    /// values. Zstd accepts levels from `1` to `22`, and `0` picks its
+    /// default, while LZ4 has no levels and ignores it. Values already stored
+    /// keep the level they were written with.
+    pub fn with_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// The store underneath, holding the encoded values.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Returns `None` if the key is absent or anything fails.
+    pub fn get(&self, k: Bytes) -> Option<Bytes> {
+        self.try_get(k).unwrap_or_default()
+    }
+
+    pub fn set(&self, k: Bytes, v: Bytes) {
+        self.try_set(k, v).unwrap_or_default();
+    }
+
+    pub fn remove(&self, k: Bytes) {
+        self.try_remove(k).unwrap_or_default();
+    }
+
+    /// Like [`CompressedStore::get`], but reports why a lookup failed.
+    pub fn try_get(&self, k: Bytes) -> Result<Option<Bytes>, CompressionError> {
+        self.store.try_get(k)?.map(|v| decode(&v)).transpose()
+    }
+
+    /// Like [`CompressedStore::set`], but reports why a write failed.
+    pub fn try_set(&self, k: Bytes, v: Bytes) -> Result<(), CompressionError> {
+        self.store.try_set(k, self.encode(&v))?;
+        Ok(())
+    }
+
+    /// Like [`CompressedStore::remove`], but reports why a removal failed.
+    pub fn try_remove(&self, k: Bytes) -> Result<(), CompressionError> {
+        self.store.try_remove(k)?;
+        Ok(())
+    }
+
+    /// Like [`Store::append`], atomically appends `extra` to the uncompressed
+    /// value of `k` and returns its new length. Returns `0` if anything fails.
+    pub fn append(&self, k: Bytes, extra: &[u8]) -> usize {
+        self.try_append(k, extra).unwrap_or_default()
+    }
+
+    /// Like [`CompressedStore::append`], but reports why the append failed.
+    pub fn try_append(&self, k: Bytes, extra: &[u8]) -> Result<usize, CompressionError> {
+        self.store.mutate(|state| {
+            let now = Instant::now();
+            let mut v = BytesMut::new();
+            if let Some(current) = state.get(&k, now) {
+                match decode(current) {
+                    Ok(current) => v.extend_from_slice(&current),
+                    Err(err) => return Ok(Err(err)),
+                }
+            }
+            v.extend_from_slice(extra);
+
+            let len = v.len();
+            let expiry = state.kept_expiry(&k, now);
+            state.set(k, self.encode(&v), expiry)?;
+            Ok(Ok(len))
+        })?
+    }
+
+    /// Every live record with its uncompressed value, in no particular order.
+    pub fn snapshot(&self) -> Result<Vec<(Bytes, Bytes)>, CompressionError> {
+        self.store
+            .snapshot()
+            .into_iter()
+            .map(|(k, v)| Ok((k, decode(&v)?)))
+            .collect()
+    }
+
+    /// Like [`Store::dump`], writes a snapshot to `w`, but with every value
+    /// uncompressed, so it can be read back by [`Store::load`] as well as
+    /// [`CompressedStore::load`].
+    pub fn dump<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let (records, expiries) = self
+            .store
+            .read_state(|guard| (guard.records.clone(), guard.expiries.clone()))
+            .map_err(io::Error::other)?;
+        let records = records
+            .into_iter()
+            .map(|(k, v)| Ok((k, decode(&v)?)))
+            .collect::<Result<Records, CompressionError>>()
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        persist::write_records(w, &records, &expiries).map(drop)
+    }
+
+    /// The bytes the live values take up as stored, headers included.
+    /// Compare with [`CompressedStore::logical_size`] to see how much
+    /// compression is saving. Returns `0` if the lock can't be taken.
+    pub fn compressed_size(&self) -> usize {
+        self.store.snapshot().iter().map(|(_, v)| v.len()).sum()
+    }
+
+    /// The bytes the live values would take up uncompressed. This
+    /// decompresses every value, so it is as slow as reading them all.
+    /// Returns `0` if the lock can't be taken or a value is corrupt.
+    pub fn logical_size(&self) -> usize {
+        self.snapshot()
+            .map(|records| records.iter().map(|(_, v)| v.len()).sum())
+            .unwrap_or_default()
+    }
+
+    // Prefixes `v` with its header, compressing it if that's worthwhile
+    fn encode(&self, v: &[u8]) -> Bytes {
+        let compressed = match self.kind {
+            _ if v.len() < self.threshold => None,
+            CompressionKind::Zstd => zstd::bulk::compress(v, self.level).ok().map(|c| (ZSTD, c)),
+            CompressionKind::Lz4 => lz4::compress(v).map(|c| (LZ4, c)),
+        };
+        let (header, body) = match &compressed {
+            Some((header, compressed)) if compressed.len() < v.len() => {
+                (*header, compressed.as_slice())
+            }
+            _ => (RAW, v),
+        };
+        let mut encoded = BytesMut::with_capacity(1 + body.len());
+        encoded.put_u8(header);
+        encoded.put_slice(body);
+        encoded.freeze()
+    }
+}
+
+fn decode(v: &Bytes) -> Result<Bytes, CompressionError> {
+    match v.first() {
+        Some(&RAW) => Ok(v.slice(1..)),
+        Some(&ZSTD) => zstd::decode_all(&v[1..])
+            .map(Bytes::from)
+            .map_err(|_| CompressionError::Corrupt),
+        Some(&LZ4) => lz4::decompress(&v[1..])
+            .map(Bytes::from)
+            .ok_or(CompressionError::Corrupt),
+        _ => Err(CompressionError::Corrupt),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompressedStore, CompressionError, CompressionKind};
+    use crate::Store;
+    use bytes::Bytes;
+    use std::{io::Cursor, time::Duration};
+
+    // Deterministic noise that no compressor can shrink
+    fn noise(len: usize) -> Bytes {
+        let mut seed = 0x9e3779b97f4a7c15_u64;
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn large_values_are_compressed() {
+        let store = Store::with_compression(CompressionKind::Zstd);
+        let v = Bytes::from("abcd".repeat(2560));
+        store.set(Bytes::from("k"), v.clone());
+
+        assert!(store.store().byte_len() < v.len());
+        assert_eq!(store.get(Bytes::from("k")), Some(v));
+    }
+
+    #[test]
+    fn small_values_are_stored_raw() {
+        let store = Store::with_compression(CompressionKind::Zstd).with_threshold(64);
+        store.set(Bytes::from("small"), Bytes::from("a".repeat(63)));
+        store.set(Bytes::from("large"), Bytes::from("a".repeat(64)));
+
+        let raw = |k| store.store().get(Bytes::from(k)).unwrap();
+        assert_eq!(raw("small")[0], super::RAW);
+        assert_eq!(raw("large")[0], super::ZSTD);
+        assert_eq!(
+            store.get(Bytes::from("small")),
+            Some(Bytes::from("a".repeat(63)))
+        );
+    }
+
+    #[test]
+    fn incompressible_values_are_stored_raw() {
+        let store = Store::with_compression(CompressionKind::Zstd).with_threshold(0);
+        let v = Bytes::from_static(b"\x93\x1f\xa4");
+        store.set(Bytes::from("k"), v.clone());
+
+        assert_eq!(store.store().byte_len(), 1 + 1 + v.len());
+        assert_eq!(store.get(Bytes::from("k")), Some(v));
+        assert_eq!(store.get(Bytes::from("missing")), None);
+    }
+
+    #[test]
+    fn corrupt_values_are_errors() {
+        let store = Store::with_compression(CompressionKind::Zstd);
+        store
+            .store()
+            .set(Bytes::from("zstd"), Bytes::from("\x01junk"));
+        store
+            .store()
+            .set(Bytes::from("unknown"), Bytes::from("\x07v"));
+        store
+            .store()
+            .set(Bytes::from("lz4"), Bytes::from("\x02junk"));
+        store.store().set(Bytes::from("empty"), Bytes::new());
+
+        for k in ["zstd", "lz4", "unknown", "empty"] {
+            assert_eq!(
+                store.try_get(Bytes::from(k)),
+                Err(CompressionError::Corrupt)
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_every_kind_of_value() {
+        let store = Store::with_compression(CompressionKind::Zstd)
+            .with_threshold(0)
+            .with_level(19);
+        let values = [
+            ("noise", noise(8192)),
+            ("repetitive", Bytes::from("{\"a\":1}".repeat(1024))),
+            ("empty", Bytes::new()),
+        ];
+        for (k, v) in &values {
+            store.set(Bytes::from(*k), v.clone());
+        }
+        for (k, v) in &values {
+            assert_eq!(store.get(Bytes::from(*k)).as_ref(), Some(v));
+        }
+
+        let raw = |k| store.store().get(Bytes::from(k)).unwrap();
+        assert_eq!(raw("noise")[0], super::RAW);
+        assert_eq!(raw("repetitive")[0], super::ZSTD);
+        assert_eq!(store.logical_size(), 8192 + 7 * 1024);
+        assert!(store.compressed_size() < 8192 + 1024);
+    }
+
+    #[test]
+    fn lz4_round_trips_every_kind_of_value() {
+        let store = Store::with_compression(CompressionKind::Lz4).with_threshold(0);
+        let values = [
+            ("noise", noise(8192)),
+            ("repetitive", Bytes::from("{\"a\":1}".repeat(1024))),
+            ("empty", Bytes::new()),
+        ];
+        for (k, v) in &values {
+            store.set(Bytes::from(*k), v.clone());
+        }
+        for (k, v) in &values {
+            assert_eq!(store.get(Bytes::from(*k)).as_ref(), Some(v));
+        }
+
+        let raw = |k| store.store().get(Bytes::from(k)).unwrap();
+        assert_eq!(raw("noise")[0], super::RAW);
+        assert_eq!(raw("repetitive")[0], super::LZ4);
+        assert!(store.compressed_size() < 8192 + 1024);
+
+        // The header says how each value was compressed, whatever the kind
+        let zstd = CompressedStore::from_store(store.store().clone(), CompressionKind::Zstd);
+        assert_eq!(
+            zstd.get(Bytes::from("repetitive")).as_ref(),
+            Some(&values[1].1)
+        );
+    }
+
+    #[test]
+    fn appends_see_uncompressed_values() {
+        let store = Store::with_compression(CompressionKind::Zstd).with_threshold(16);
+        store.set(Bytes::from("k"), Bytes::from("a".repeat(32)));
+        store.store().set_with_ttl(
+            Bytes::from("k"),
+            store.store().get(Bytes::from("k")).unwrap(),
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(store.append(Bytes::from("k"), b"bc"), 34);
+        assert_eq!(store.append(Bytes::from("new"), b"x"), 1);
+        let mut expected = "a".repeat(32);
+        expected.push_str("bc");
+        assert_eq!(store.get(Bytes::from("k")), Some(Bytes::from(expected)));
+        assert!(store.store().ttl(&Bytes::from("k")).is_some());
+
+        store.store().set(Bytes::from("bad"), Bytes::from("\x07v"));
+        assert_eq!(
+            store.try_append(Bytes::from("bad"), b"x"),
+            Err(CompressionError::Corrupt)
+        );
+    }
+
+    #[test]
+    fn snapshots_hold_uncompressed_values() {
+        let store = Store::with_compression(CompressionKind::Zstd).with_threshold(0);
+        let v = Bytes::from("abcd".repeat(256));
+        store.set(Bytes::from("k"), v.clone());
+        assert_eq!(store.snapshot(), Ok(vec![(Bytes::from("k"), v.clone())]));
+
+        let mut buf = Vec::new();
+        store.dump(&mut buf).unwrap();
+        let plain = Store::load(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(plain.get(Bytes::from("k")), Some(v.clone()));
+
+        let loaded = CompressedStore::load(&mut Cursor::new(&buf), CompressionKind::Zstd).unwrap();
+        assert_eq!(loaded.get(Bytes::from("k")), Some(v.clone()));
+        assert!(loaded.compressed_size() < v.len());
+    }
+}
@@ -0,0 +1,70 @@
+// CRC-32 as used by zlib and PNG (the reflected IEEE polynomial), for
+// catching corruption in persisted files
+
+const POLY: u32 = 0xedb8_8320;
+
+// The CRC of every byte value, computed at compile time
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ POLY,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// A CRC-32 being computed over bytes fed to it a piece at a time
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    pub(crate) fn new() -> Self {
+        Self(!0)
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = TABLE[((self.0 ^ *b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(crate) fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+// The CRC-32 of `parts` one after another
+pub(crate) fn checksum(parts: &[&[u8]]) -> u32 {
+    let mut crc = Crc32::new();
+    for part in parts {
+        crc.update(part);
+    }
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, Crc32};
+
+    #[test]
+    fn matches_the_standard_check_value() {
+        assert_eq!(checksum(&[b"123456789"]), 0xcbf4_3926);
+        assert_eq!(checksum(&[b"1234", b"56789"]), 0xcbf4_3926);
+        assert_eq!(checksum(&[]), 0);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+    }
+}
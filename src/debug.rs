@@ -0,0 +1,170 @@
+use std::{
+    fmt::{self, Debug},
+    str,
+    sync::TryLockError,
+    time::Instant,
+};
+
+use crate::Store;
+
+// How many bytes of each key and value `Debug` shows, unless the formatter
+// asks for a precision, as in `{:.16?}`
+const SHOWN_BYTES: usize = 64;
+
+// How many records `Debug` shows before eliding the rest
+const SHOWN_RECORDS: usize = 32;
+
+/// Shows the live records, with keys and values as strings when they're UTF-8
+/// and as hex otherwise. Long keys and values are cut to 64 bytes, or to the
+/// formatter's precision, and only the first 32 records are shown, so a large
+/// store doesn't flood a log. See [`Store::dump_pretty`] for everything.
+///
+/// Prints `Store { <locked> }` rather than waiting if another thread holds the
+/// store's exclusive lock.
+impl Debug for Store {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.0.state.try_read() {
+            Ok(guard) => guard,
+            // Debug output is no reason to run the poison policy
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return f.write_str("Store { <locked> }"),
+        };
+        let limit = Some(f.precision().unwrap_or(SHOWN_BYTES));
+        let now = Instant::now();
+
+        f.write_str("Store ")?;
+        let mut map = f.debug_map();
+        let live = state
+            .records
+            .iter()
+            .filter(|(k, _)| !state.is_expired(k, now));
+        for (k, v) in live.take(SHOWN_RECORDS) {
+            let v = state.appends.get(k).map_or(&v[..], |v| &v[..]);
+            map.entry(&Shown { bytes: k, limit }, &Shown { bytes: v, limit });
+        }
+        match state.len(now) > SHOWN_RECORDS {
+            true => map.finish_non_exhaustive(),
+            false => map.finish(),
+        }
+    }
+}
+
+impl Store {
+    /// Shows every live record on a line of its own, sorted by key, with keys
+    /// and values formatted as by `Debug` but never cut short. Handy for
+    /// comparing stores in tests.
+    pub fn dump_pretty(&self) -> String {
+        let mut records = self.snapshot();
+        records.sort();
+        records
+            .iter()
+            .map(|(k, v)| {
+                let (k, v) = (Shown::full(k), Shown::full(v));
+                format!("{k:?}: {v:?}\n")
+            })
+            .collect()
+    }
+}
+
+// Bytes shown as a string or hex, cut to `limit` bytes if there is one
+struct Shown<'a> {
+    bytes: &'a [u8],
+    limit: Option<usize>,
+}
+
+impl<'a> Shown<'a> {
+    fn full(bytes: &'a [u8]) -> Self {
+        Self { bytes, limit: None }
+    }
+}
+
+impl Debug for Shown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let shown = match str::from_utf8(self.bytes) {
+            Ok(s) => {
+                // Cut on a character boundary, at or before the limit
+                let end = (0..=limit.min(s.len()))
+                    .rev()
+                    .find(|i| s.is_char_boundary(*i))
+                    .unwrap_or_default();
+                write!(f, "{:?}", &s[..end])?;
+                end
+            }
+            Err(_) => {
+                let end = limit.min(self.bytes.len());
+                f.write_str("0x")?;
+                for b in &self.bytes[..end] {
+                    write!(f, "{b:02x}")?;
+                }
+                end
+            }
+        };
+        if shown < self.bytes.len() {
+            write!(f, "… ({} bytes)", self.bytes.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Store, StoreError};
+    use bytes::Bytes;
+    use std::{sync::mpsc, thread};
+
+    #[test]
+    fn shows_text_and_hex() {
+        let store = Store::new();
+        store.set(Bytes::from("k"), Bytes::from_static(b"\x00\xff"));
+        assert_eq!(format!("{store:?}"), r#"Store {"k": 0x00ff}"#);
+        assert_eq!(store.dump_pretty(), "\"k\": 0x00ff\n");
+    }
+
+    #[test]
+    fn cuts_long_values() {
+        let store = Store::new();
+        store.set(Bytes::from("k"), Bytes::from("é".repeat(50)));
+
+        let cut = format!(r#"Store {{"k": "{}"… (100 bytes)}}"#, "é".repeat(32));
+        assert_eq!(format!("{store:?}"), cut);
+        assert_eq!(format!("{store:.3?}"), r#"Store {"k": "é"… (100 bytes)}"#);
+        assert!(store.dump_pretty().contains(&"é".repeat(50)));
+    }
+
+    #[test]
+    fn output_stays_bounded() {
+        let store: Store = (0..10_000)
+            .map(|i| (format!("key{i}"), vec![0xff; 1000]))
+            .collect();
+
+        let shown = format!("{store:?}");
+        assert!(shown.len() < 32 * 200, "{} bytes shown", shown.len());
+        assert!(shown.ends_with(", ..}"));
+        assert_eq!(shown.matches("(1000 bytes)").count(), 32);
+        assert_eq!(store.dump_pretty().lines().count(), 10_000);
+    }
+
+    #[test]
+    fn never_blocks() {
+        let store = Store::new();
+        let (locked, wait) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let holder = {
+            let store = store.clone();
+            thread::spawn(move || {
+                store.transaction(|_| {
+                    locked.send(()).unwrap();
+                    released.recv().unwrap();
+                    Ok::<_, StoreError>(())
+                })
+            })
+        };
+        wait.recv().unwrap();
+
+        assert_eq!(format!("{store:?}"), "Store { <locked> }");
+        release.send(()).unwrap();
+        holder.join().unwrap().unwrap();
+        assert_eq!(format!("{store:?}"), "Store {}");
+    }
+}
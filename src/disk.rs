@@ -0,0 +1,578 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    crc,
+    wal::{self, Op, HEADER_LEN, MAGIC, TAG_REMOVE, TAG_SET, VERSION},
+    CompactionStats,
+};
+
+// Data files are named after their id, and the newest one is appended to
+const EXTENSION: &str = "data";
+
+/// A key value store that keeps its values on disk, in append-only data files
+/// in a directory, and only the location of each key's latest value in
+/// memory. This suits data sets whose keys fit in memory but whose values
+/// don't.
+///
+/// A write is appended to the newest data file and synced before it returns,
+/// and a removal appends a tombstone. A lookup reads the value back from disk
+/// and checks its checksum. Opening the directory again rebuilds the
+/// locations by scanning every data file, oldest first. Overwritten and
+/// removed values keep taking up disk space until [`DiskStore::compact`]
+/// rewrites the live ones into a fresh file.
+///
+/// Data files are laid out like a write-ahead log, without TTLs. A torn
+/// record at the end of the newest one, as a crash partway through a write
+/// leaves behind, is truncated away on open.
+///
+/// Like [`Store`](crate::Store), cloning a `DiskStore` shares the same files.
+#[derive(Debug, Clone)]
+pub struct DiskStore(Arc<Mutex<Disk>>);
+
+#[derive(Debug)]
+struct Disk {
+    dir: PathBuf,
+    keydir: HashMap<Bytes, Location>,
+    // Every data file by id, the last of which is the one appended to
+    files: BTreeMap<u64, File>,
+    // Length of the newest file
+    end: u64,
+    // Total length of the data files and the number of records in them
+    size: u64,
+    entries: u64,
+}
+
+// Where the latest record of a key lies: its file, its offset in it, and the
+// length of its body after the `u32 length, u32 checksum` frame
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    file: u64,
+    offset: u64,
+    len: u32,
+}
+
+impl DiskStore {
+    /// Opens the store in `dir`, creating the directory if it doesn't exist
+    /// yet and scanning any data files already in it.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                let id = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str()?.parse::<u64>().ok());
+                ids.extend(id);
+            }
+        }
+        ids.sort_unstable();
+
+        let mut disk = Disk {
+            dir: dir.to_owned(),
+            keydir: HashMap::new(),
+            files: BTreeMap::new(),
+            end: 0,
+            size: 0,
+            entries: 0,
+        };
+        for (i, id) in ids.iter().enumerate() {
+            disk.scan(*id, i + 1 == ids.len())?;
+        }
+        if disk.files.is_empty() {
+            let file = disk.create(0)?;
+            disk.files.insert(0, file);
+            disk.end = HEADER_LEN;
+            disk.size = HEADER_LEN;
+        }
+        Ok(Self(Arc::new(Mutex::new(disk))))
+    }
+
+    /// Returns `None` if the key is absent or its value can't be read.
+    pub fn get(&self, k: Bytes) -> Option<Bytes> {
+        self.try_get(k).unwrap_or_default()
+    }
+
+    /// Writes a value, silently dropping it if it can't be written.
+    pub fn set(&self, k: Bytes, v: Bytes) {
+        self.try_set(k, v).unwrap_or_default();
+    }
+
+    /// Removes a value, returning whether it was present. Returns `false` if
+    /// the tombstone can't be written.
+    pub fn remove(&self, k: Bytes) -> bool {
+        self.try_remove(k).unwrap_or_default()
+    }
+
+    /// Like [`DiskStore::get`], but reports why a value couldn't be read, such
+    /// as [`ErrorKind::InvalidData`] if it fails its checksum.
+    pub fn try_get(&self, k: Bytes) -> io::Result<Option<Bytes>> {
+        let mut disk = self.lock()?;
+        let Some(location) = disk.keydir.get(&k).copied() else {
+            return Ok(None);
+        };
+        let mut body = disk.read(location)?;
+        let (_, v) = wal::split_entry(body.split_off(1))?;
+        Ok(Some(v))
+    }
+
+    /// Like [`DiskStore::set`], but reports why the write failed.
+    pub fn try_set(&self, k: Bytes, v: Bytes) -> io::Result<()> {
+        let record = wal::encode(Op::Set(&k, &v, None))?;
+        let mut disk = self.lock()?;
+        let location = disk.append(&record)?;
+        disk.keydir.insert(k, location);
+        Ok(())
+    }
+
+    /// Like [`DiskStore::remove`], but reports why the removal failed.
+    pub fn try_remove(&self, k: Bytes) -> io::Result<bool> {
+        let mut disk = self.lock()?;
+        if !disk.keydir.contains_key(&k) {
+            return Ok(false);
+        }
+        disk.append(&wal::encode(Op::Remove(&k))?)?;
+        disk.keydir.remove(&k);
+        Ok(true)
+    }
+
+    /// Returns `false` if the key is absent or the lock can't be taken.
+    pub fn contains_key(&self, k: &Bytes) -> bool {
+        self.lock()
+            .map(|disk| disk.keydir.contains_key(k))
+            .unwrap_or_default()
+    }
+
+    /// Returns `0` if the lock can't be taken.
+    pub fn len(&self) -> usize {
+        self.lock()
+            .map(|disk| disk.keydir.len())
+            .unwrap_or_default()
+    }
+
+    /// Returns `true` if the lock can't be taken.
+    pub fn is_empty(&self) -> bool {
+        self.lock()
+            .map(|disk| disk.keydir.is_empty())
+            .unwrap_or(true)
+    }
+
+    /// The total length of the data files, live records or not.
+    pub fn disk_size(&self) -> u64 {
+        self.lock().map(|disk| disk.size).unwrap_or_default()
+    }
+
+    /// Rewrites the live values into a fresh data file and deletes the old
+    /// ones, reclaiming the space taken by overwritten and removed values.
+    ///
+    /// Every live value is read and written again while holding the lock, so
+    /// other operations wait until compaction is done. If writing the fresh
+    /// file fails partway, the old files are left as they were. Old files
+    /// that can't be deleted once it is written are kept, still counted by
+    /// [`DiskStore::disk_size`], and the next compaction tries again.
+    pub fn compact(&self) -> io::Result<CompactionStats> {
+        let mut disk = self.lock()?;
+        let id = disk.files.keys().next_back().map_or(0, |id| id + 1);
+        let (file, keydir, end) = match disk.rewrite(id) {
+            Ok(rewritten) => rewritten,
+            Err(err) => {
+                let _ = fs::remove_file(disk.path(id));
+                return Err(err);
+            }
+        };
+        Ok(disk.switch(id, file, keydir, end))
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, Disk>> {
+        self.0.lock().map_err(wal::poisoned)
+    }
+}
+
+impl Disk {
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{id}.{EXTENSION}"))
+    }
+
+    // Creates an empty data file
+    fn create(&self, id: u64) -> io::Result<File> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .open(self.path(id))?;
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION])?;
+        file.sync_all()?;
+        Ok(file)
+    }
+
+    // Applies every record in the data file `id` to the key directory. Only
+    // the newest file may end in a torn record, which is truncated away
+    fn scan(&mut self, id: u64, newest: bool) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(self.path(id))?;
+        let mut r = BufReader::new(&file);
+        let mut header = [0; HEADER_LEN as usize];
+        let complete = wal::read_full(&mut r, &mut header)?;
+        if complete && (header[..MAGIC.len()] != *MAGIC || header[MAGIC.len()] != VERSION) {
+            return Err(self.corrupt(id, 0, "is not a data file"));
+        }
+
+        let offset = if complete {
+            self.records(id, &mut r)?
+        } else {
+            HEADER_LEN
+        };
+        drop(r);
+
+        if !complete || offset < file.metadata()?.len() {
+            if !newest {
+                return Err(self.corrupt(id, offset, "is torn"));
+            }
+            // A file created just before a crash may not have its header yet
+            if !complete {
+                file.set_len(0)?;
+                file.write_all(MAGIC)?;
+                file.write_all(&[VERSION])?;
+            }
+            file.set_len(offset)?;
+        }
+        self.size += offset;
+        self.end = offset;
+        self.files.insert(id, file);
+        Ok(())
+    }
+
+    // Applies the records following the header, returning where the last
+    // whole one ends
+    fn records(&mut self, id: u64, r: &mut impl Read) -> io::Result<u64> {
+        let mut offset = HEADER_LEN;
+        loop {
+            let mut head = [0; 8];
+            if !wal::read_full(r, &mut head)? {
+                return Ok(offset);
+            }
+            let len = u32::from_le_bytes(head[..4].try_into().unwrap());
+            let mut body = Vec::new();
+            r.by_ref().take(len as u64).read_to_end(&mut body)?;
+            if body.len() < len as usize {
+                return Ok(offset);
+            }
+            if crc::checksum(&[&head[..4], &body])
+                != u32::from_le_bytes(head[4..].try_into().unwrap())
+            {
+                return Err(self.corrupt(id, offset, "fails its checksum"));
+            }
+
+            let mut body = Bytes::from(body);
+            match body.first() {
+                Some(&TAG_SET) => {
+                    let (k, _) = wal::split_entry(body.split_off(1))?;
+                    self.keydir.insert(
+                        k,
+                        Location {
+                            file: id,
+                            offset,
+                            len,
+                        },
+                    );
+                }
+                Some(&TAG_REMOVE) => {
+                    self.keydir.remove(&body[1..]);
+                }
+                _ => return Err(self.corrupt(id, offset, "is malformed")),
+            }
+            offset += 8 + len as u64;
+            self.entries += 1;
+        }
+    }
+
+    // Reads back the body of a record, checking it against its checksum
+    fn read(&mut self, location: Location) -> io::Result<Bytes> {
+        let file = self
+            .files
+            .get_mut(&location.file)
+            .expect("data file is open");
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut record = vec![0; 8 + location.len as usize];
+        file.read_exact(&mut record)?;
+        let expected = u32::from_le_bytes(record[4..8].try_into().unwrap());
+        let len = [record[0], record[1], record[2], record[3]];
+        let body = Bytes::from(record).split_off(8);
+        if crc::checksum(&[&len, &body]) != expected {
+            return Err(self.corrupt(location.file, location.offset, "fails its checksum"));
+        }
+        Ok(body)
+    }
+
+    // Durably appends a record to the newest file, returning where it lies
+    fn append(&mut self, record: &[u8]) -> io::Result<Location> {
+        let (&id, file) = self
+            .files
+            .iter_mut()
+            .next_back()
+            .expect("a data file is open");
+        if let Err(err) = file.write_all(record).and_then(|()| file.sync_data()) {
+            // Cut off whatever part of the record made it, so that the next
+            // one lands where it is expected to
+            let _ = file.set_len(self.end);
+            return Err(err);
+        }
+
+        let location = Location {
+            file: id,
+            offset: self.end,
+            len: (record.len() - 8) as u32,
+        };
+        self.end += record.len() as u64;
+        self.size += record.len() as u64;
+        self.entries += 1;
+        Ok(location)
+    }
+
+    // Writes every live value into the new data file `id`, returning it along
+    // with where each value now lies and its length
+    fn rewrite(&mut self, id: u64) -> io::Result<(File, HashMap<Bytes, Location>, u64)> {
+        let file = self.create(id)?;
+        let mut writer = BufWriter::new(&file);
+        let mut keydir = HashMap::with_capacity(self.keydir.len());
+        let mut offset = HEADER_LEN;
+        let live: Vec<(Bytes, Location)> = self
+            .keydir
+            .iter()
+            .map(|(k, location)| (k.clone(), *location))
+            .collect();
+        for (k, location) in live {
+            let mut body = self.read(location)?;
+            let (_, v) = wal::split_entry(body.split_off(1))?;
+            let record = wal::encode(Op::Set(&k, &v, None))?;
+            writer.write_all(&record)?;
+            let len = (record.len() - 8) as u32;
+            keydir.insert(
+                k,
+                Location {
+                    file: id,
+                    offset,
+                    len,
+                },
+            );
+            offset += record.len() as u64;
+        }
+        writer.flush()?;
+        drop(writer);
+        file.sync_all()?;
+        Ok((file, keydir, offset))
+    }
+
+    // Switches over to the rewritten data file `id`, then deletes the old
+    // files oldest first. Deleting stops at the first failure, so the files
+    // left behind are always the newest old ones: replayed before the
+    // rewritten file, they can't bring back a key removed in a deleted one
+    fn switch(
+        &mut self,
+        id: u64,
+        file: File,
+        keydir: HashMap<Bytes, Location>,
+        end: u64,
+    ) -> CompactionStats {
+        let stats = CompactionStats {
+            bytes_before: self.size,
+            bytes_after: end,
+            records_dropped: self.entries.saturating_sub(keydir.len() as u64),
+        };
+        let old = std::mem::replace(&mut self.files, BTreeMap::from([(id, file)]));
+        self.entries = keydir.len() as u64;
+        self.keydir = keydir;
+        self.end = end;
+        self.size = end;
+
+        let mut kept = false;
+        for (old_id, old_file) in old {
+            kept = kept || fs::remove_file(self.path(old_id)).is_err();
+            if kept {
+                self.size += old_file.metadata().map_or(0, |metadata| metadata.len());
+                self.files.insert(old_id, old_file);
+            }
+        }
+        stats
+    }
+
+    fn corrupt(&self, id: u64, offset: u64, what: &str) -> io::Error {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "record at byte {offset} of {} {what}",
+                self.path(id).display()
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiskStore;
+    use bytes::Bytes;
+    use std::{
+        env, fs,
+        io::{ErrorKind, Write},
+        path::PathBuf,
+        process,
+    };
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("kvs-disk-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn data_files(dir: &PathBuf) -> Vec<PathBuf> {
+        let mut files: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn reopen_after_mixed_writes() {
+        let dir = temp_dir("reopen");
+        let store = DiskStore::open(&dir).unwrap();
+        store.set(Bytes::from("a"), Bytes::from("1"));
+        store.set(Bytes::from("b"), Bytes::from("2"));
+        store.set(Bytes::from("large"), Bytes::from(vec![7; 70 * 1024]));
+        store.set(Bytes::from("b"), Bytes::from("3"));
+        assert!(store.remove(Bytes::from("a")));
+        assert!(!store.remove(Bytes::from("never")));
+        assert_eq!(store.get(Bytes::from("b")), Some(Bytes::from("3")));
+        drop(store);
+
+        let store = DiskStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(Bytes::from("a")), None);
+        assert_eq!(store.get(Bytes::from("b")), Some(Bytes::from("3")));
+        assert_eq!(
+            store.get(Bytes::from("large")),
+            Some(Bytes::from(vec![7; 70 * 1024]))
+        );
+        store.set(Bytes::from("a"), Bytes::from("4"));
+        drop(store);
+        assert_eq!(
+            DiskStore::open(&dir).unwrap().get(Bytes::from("a")),
+            Some(Bytes::from("4"))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn torn_and_corrupt_records() {
+        let dir = temp_dir("torn");
+        let store = DiskStore::open(&dir).unwrap();
+        store.set(Bytes::from("k"), Bytes::from("v"));
+        let intact = store.disk_size();
+        drop(store);
+
+        // A crash partway through the next write leaves a torn record
+        let path = data_files(&dir).remove(0);
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[20, 0, 0, 0, 1]).unwrap();
+        drop(file);
+        let store = DiskStore::open(&dir).unwrap();
+        assert_eq!(store.disk_size(), intact);
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+        assert_eq!(store.get(Bytes::from("k")), Some(Bytes::from("v")));
+        drop(store);
+
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        fs::write(&path, data).unwrap();
+        let err = DiskStore::open(&dir).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("fails its checksum"), "{err}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compaction_keeps_old_files_it_cannot_delete() {
+        let dir = temp_dir("undeletable");
+        let store = DiskStore::open(&dir).unwrap();
+        store.set(Bytes::from("a"), Bytes::from("1"));
+        store.set(Bytes::from("b"), Bytes::from("2"));
+        store.compact().unwrap();
+        store.remove(Bytes::from("b"));
+        store.set(Bytes::from("a"), Bytes::from("3"));
+
+        // A directory in place of the old data file can't be removed as one
+        let old = data_files(&dir).remove(0);
+        fs::remove_file(&old).unwrap();
+        fs::create_dir(&old).unwrap();
+        fs::write(old.join("keep"), b"").unwrap();
+
+        let stats = store.compact().unwrap();
+        assert_eq!(stats.records_dropped, 3);
+        assert!(store.disk_size() > stats.bytes_after);
+        assert_eq!(store.get(Bytes::from("a")), Some(Bytes::from("3")));
+        assert_eq!(store.get(Bytes::from("b")), None);
+        store.set(Bytes::from("c"), Bytes::from("4"));
+        assert_eq!(store.get(Bytes::from("c")), Some(Bytes::from("4")));
+        drop(store);
+
+        fs::remove_dir_all(&old).unwrap();
+        let store = DiskStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(Bytes::from("a")), Some(Bytes::from("3")));
+        assert_eq!(store.get(Bytes::from("c")), Some(Bytes::from("4")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compaction_reclaims_overwritten_values() {
+        let dir = temp_dir("compact");
+        let store = DiskStore::open(&dir).unwrap();
+        for n in 0..100 {
+            store.set(Bytes::from("counter"), Bytes::from(vec![n; 100]));
+        }
+        store.set(Bytes::from("gone"), Bytes::from("soon"));
+        store.remove(Bytes::from("gone"));
+        store.set(Bytes::from("kept"), Bytes::from("v"));
+
+        let stats = store.compact().unwrap();
+        assert_eq!(stats.records_dropped, 101);
+        assert_eq!(stats.bytes_after, store.disk_size());
+        assert!(stats.bytes_after * 20 < stats.bytes_before);
+        let files = data_files(&dir);
+        assert_eq!(files.len(), 1);
+        assert_eq!(fs::metadata(&files[0]).unwrap().len(), stats.bytes_after);
+
+        assert_eq!(
+            store.get(Bytes::from("counter")),
+            Some(Bytes::from(vec![99; 100]))
+        );
+        store.set(Bytes::from("after"), Bytes::from("compaction"));
+        drop(store);
+
+        let store = DiskStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(
+            store.get(Bytes::from("counter")),
+            Some(Bytes::from(vec![99; 100]))
+        );
+        assert_eq!(
+            store.get(Bytes::from("after")),
+            Some(Bytes::from("compaction"))
+        );
+        assert!(!store.contains_key(&Bytes::from("gone")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
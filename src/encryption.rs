@@ -0,0 +1,280 @@
+use std::{
+    fmt::{self, Debug},
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::Path,
+};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use bytes::Bytes;
+
+use crate::{persist::ENCRYPTED_MAGIC, Store, StoreError};
+
+// Bytes of random nonce in front of every ciphertext
+const NONCE_LEN: usize = 12;
+
+// An encrypted snapshot is the magic bytes, this scheme version and a random
+// nonce, followed by a plaintext snapshot sealed with AES-256-GCM. The whole
+// header is authenticated along with it
+const SCHEME: u8 = 1;
+const HEADER_LEN: usize = ENCRYPTED_MAGIC.len() + 1 + NONCE_LEN;
+
+/// A [`Store`] whose values are encrypted with AES-256-GCM, returned by
+/// [`Store::with_encryption`]. Keys stay in plaintext.
+///
+/// Every value is sealed under a fresh random nonce, which is stored in front
+/// of its ciphertext, and is bound to its key, so a value copied under another
+/// key fails to decrypt. The underlying store only ever holds ciphertext, so
+/// its snapshots are encrypted too.
+///
+/// Like `Store`, cloning an `EncryptedStore` shares the same records.
+#[derive(Clone)]
+pub struct EncryptedStore {
+    store: Store,
+    cipher: Aes256Gcm,
+}
+
+impl Store {
+    /// Creates a store whose values are encrypted under `key`.
+    pub fn with_encryption(key: [u8; 32]) -> EncryptedStore {
+        EncryptedStore::from_store(Store::new(), key)
+    }
+
+    /// Like [`Store::save_to_path`], but encrypts the whole snapshot under
+    /// `key`, so neither keys nor values can be read from the file without
+    /// it. Restore it with [`Store::load_encrypted`].
+    pub fn save_encrypted(&self, path: impl AsRef<Path>, key: &[u8; 32]) -> io::Result<()> {
+        let mut plaintext = Vec::new();
+        self.dump(&mut plaintext)?;
+
+        let mut header = [0; HEADER_LEN];
+        header[..ENCRYPTED_MAGIC.len()].copy_from_slice(ENCRYPTED_MAGIC);
+        header[ENCRYPTED_MAGIC.len()] = SCHEME;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        header[HEADER_LEN - NONCE_LEN..].copy_from_slice(&nonce);
+        let payload = Payload {
+            msg: &plaintext,
+            aad: &header,
+        };
+        let ciphertext = Aes256Gcm::new(key.into())
+            .encrypt(&nonce, payload)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "snapshot is too large"))?;
+
+        let mut file = File::create(path)?;
+        file.write_all(&header)?;
+        file.write_all(&ciphertext)?;
+        file.sync_all()
+    }
+
+    /// Restores a store from a snapshot written by [`Store::save_encrypted`]
+    /// under the same `key`. A wrong key and a file that was tampered with
+    /// fail the same way, with [`ErrorKind::InvalidData`], since neither can
+    /// be told apart from the other.
+    pub fn load_encrypted(path: impl AsRef<Path>, key: &[u8; 32]) -> io::Result<Self> {
+        let file = fs::read(path)?;
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message);
+        if file.len() < HEADER_LEN || &file[..ENCRYPTED_MAGIC.len()] != ENCRYPTED_MAGIC {
+            return Err(invalid("not an encrypted kvs snapshot"));
+        }
+        let scheme = file[ENCRYPTED_MAGIC.len()];
+        if scheme != SCHEME {
+            return Err(invalid(&format!(
+                "unsupported encryption scheme version {scheme}"
+            )));
+        }
+
+        let (header, ciphertext) = file.split_at(HEADER_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: header,
+        };
+        let plaintext = Aes256Gcm::new(key.into())
+            .decrypt(
+                Nonce::from_slice(&header[HEADER_LEN - NONCE_LEN..]),
+                payload,
+            )
+            .map_err(|_| invalid("wrong key or corrupted file"))?;
+        Self::load(&mut plaintext.as_slice())
+    }
+}
+
+impl EncryptedStore {
+    /// Wraps an existing store, such as one loaded from a snapshot of an
+    /// `EncryptedStore`. Its values must have been encrypted under `key` to be
+    /// readable.
+    pub fn from_store(store: Store, key: [u8; 32]) -> Self {
+        Self {
+            store,
+            cipher: Aes256Gcm::new(&key.into()),
+        }
+    }
+
+    /// The store underneath, holding the encrypted values.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Returns `None` if the key is absent or anything fails.
+    pub fn get(&self, k: Bytes) -> Option<Bytes> {
+        self.try_get(k).unwrap_or_default()
+    }
+
+    pub fn set(&self, k: Bytes, v: Bytes) {
+        self.try_set(k, v).unwrap_or_default();
+    }
+
+    pub fn remove(&self, k: Bytes) {
+        self.try_remove(k).unwrap_or_default();
+    }
+
+    /// Like [`EncryptedStore::get`], but reports why a lookup failed, such as
+    /// [`StoreError::Decryption`] for a value that was tampered with or
+    /// encrypted under another key.
+    pub fn try_get(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        let Some(sealed) = self.store.try_get(k.clone())? else {
+            return Ok(None);
+        };
+        if sealed.len() < NONCE_LEN {
+            return Err(StoreError::Decryption);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &k,
+        };
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map(|v| Some(Bytes::from(v)))
+            .map_err(|_| StoreError::Decryption)
+    }
+
+    /// Like [`EncryptedStore::set`], but reports why a write failed.
+    pub fn try_set(&self, k: Bytes, v: Bytes) -> Result<(), StoreError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload { msg: &v, aad: &k };
+        // Encrypting only fails for messages of 64 GiB or more
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .expect("value is too large to encrypt");
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        self.store.try_set(k, sealed.into())?;
+        Ok(())
+    }
+
+    /// Like [`EncryptedStore::remove`], but reports why a removal failed.
+    pub fn try_remove(&self, k: Bytes) -> Result<(), StoreError> {
+        self.store.try_remove(k)?;
+        Ok(())
+    }
+}
+
+// Leaves the cipher, and with it the key, out of debug output
+impl Debug for EncryptedStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedStore")
+            .field("store", &self.store)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EncryptedStore;
+    use crate::{Store, StoreError};
+    use bytes::Bytes;
+    use std::{env, fs, io::ErrorKind, path::PathBuf, process};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("kvs-encryption-{}-{name}", process::id()))
+    }
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn values_are_encrypted_at_rest() {
+        let store = Store::with_encryption(KEY);
+        let v = Bytes::from("attack at dawn");
+        store.set(Bytes::from("k"), v.clone());
+
+        let raw = store.store().get(Bytes::from("k")).unwrap();
+        assert_ne!(raw, v);
+        assert!(!raw.windows(v.len()).any(|w| w == v));
+        assert_eq!(store.get(Bytes::from("k")), Some(v.clone()));
+
+        // The same value seals differently every time
+        store.set(Bytes::from("k"), v);
+        assert_ne!(store.store().get(Bytes::from("k")).unwrap(), raw);
+    }
+
+    #[test]
+    fn tampering_and_wrong_keys_fail() {
+        let store = Store::with_encryption(KEY);
+        store.set(Bytes::from("k"), Bytes::from("v"));
+        let sealed = store.store().get(Bytes::from("k")).unwrap();
+
+        let other = EncryptedStore::from_store(store.store().clone(), [8; 32]);
+        assert_eq!(other.try_get(Bytes::from("k")), Err(StoreError::Decryption));
+
+        let mut tampered = sealed.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        store.store().set(Bytes::from("k"), tampered.into());
+        assert_eq!(store.try_get(Bytes::from("k")), Err(StoreError::Decryption));
+
+        // A value moved under another key no longer decrypts
+        store.store().set(Bytes::from("moved"), sealed);
+        assert_eq!(
+            store.try_get(Bytes::from("moved")),
+            Err(StoreError::Decryption)
+        );
+        store.store().set(Bytes::from("short"), Bytes::from("x"));
+        assert_eq!(store.get(Bytes::from("short")), None);
+        assert_eq!(store.try_get(Bytes::from("missing")), Ok(None));
+    }
+
+    #[test]
+    fn encrypted_snapshots() {
+        let path = temp_path("snapshot");
+        let store = Store::new();
+        store.set(Bytes::from("token"), Bytes::from("hunter2"));
+        store.save_encrypted(&path, &KEY).unwrap();
+
+        let file = fs::read(&path).unwrap();
+        assert!(!file.windows(7).any(|w| w == b"hunter2"));
+        let loaded = Store::load_encrypted(&path, &KEY).unwrap();
+        assert_eq!(
+            loaded.get(Bytes::from("token")),
+            Some(Bytes::from("hunter2"))
+        );
+
+        let err = Store::load_encrypted(&path, &[8; 32]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "wrong key or corrupted file");
+
+        let err = Store::load_from_path(&path).unwrap_err();
+        assert!(err.to_string().contains("load_encrypted"), "{err}");
+
+        let mut flipped = file.clone();
+        flipped[file.len() / 2 + 10] ^= 1;
+        fs::write(&path, &flipped).unwrap();
+        let err = Store::load_encrypted(&path, &KEY).unwrap_err();
+        assert_eq!(err.to_string(), "wrong key or corrupted file");
+
+        // The header is authenticated too
+        let mut flipped = file;
+        flipped[8] ^= 1;
+        fs::write(&path, &flipped).unwrap();
+        assert!(Store::load_encrypted(&path, &KEY).is_err());
+
+        store.save_to_path(&path).unwrap();
+        let err = Store::load_encrypted(&path, &KEY).unwrap_err();
+        assert_eq!(err.to_string(), "not an encrypted kvs snapshot");
+        fs::remove_file(path).unwrap();
+    }
+}
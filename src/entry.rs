@@ -0,0 +1,151 @@
+use std::{sync::RwLockWriteGuard, time::Instant};
+
+use bytes::Bytes;
+
+use crate::{expiry::Expiry, State, Store, StoreError};
+
+/// A view into a single key that holds the store's lock for as long as it
+/// lives, so checking for the key and acting on it can't be interleaved with
+/// writes from other handles. Created by [`Store::entry`].
+///
+/// Every other operation on the store blocks until the entry is dropped.
+pub struct Entry<'a> {
+    // Writes through the guard don't trigger auto-compaction, as that needs
+    // the lock released; the next write through the store picks it up instead
+    guard: RwLockWriteGuard<'a, State>,
+    key: Bytes,
+}
+
+impl Store {
+    /// Locks the store and returns the entry for `k`. An expired value is
+    /// dropped first, so the entry sees it as vacant.
+    pub fn entry(&self, k: Bytes) -> Result<Entry<'_>, StoreError> {
+        let mut guard = self.write()?;
+        if guard.purge(&k, Instant::now())? {
+            self.0.counters.record_expired(1);
+        }
+        Ok(Entry { guard, key: k })
+    }
+}
+
+impl Entry<'_> {
+    pub fn key(&self) -> &Bytes {
+        &self.key
+    }
+
+    pub fn get(&self) -> Option<&Bytes> {
+        self.guard.records.get(&self.key)
+    }
+
+    /// Inserts `v` if the key is vacant, and returns the value it ends up
+    /// with.
+    pub fn or_insert(self, v: Bytes) -> Result<Bytes, StoreError> {
+        self.or_insert_with(|| v)
+    }
+
+    /// Like [`Entry::or_insert`], but only computes the value if the key is
+    /// vacant.
+    pub fn or_insert_with<F>(mut self, f: F) -> Result<Bytes, StoreError>
+    where
+        F: FnOnce() -> Bytes,
+    {
+        if let Some(v) = self.get() {
+            return Ok(v.clone());
+        }
+        let v = f();
+        self.guard
+            .set(self.key.clone(), v.clone(), Expiry::Default)?;
+        Ok(v)
+    }
+
+    /// Modifies the value in place if the key is occupied, keeping any TTL.
+    pub fn and_modify<F>(mut self, f: F) -> Result<Self, StoreError>
+    where
+        F: FnOnce(&mut Bytes),
+    {
+        if let Some(mut v) = self.get().cloned() {
+            f(&mut v);
+            let expiry = self.guard.kept_expiry(&self.key, Instant::now());
+            self.guard.set(self.key.clone(), v, expiry)?;
+        }
+        Ok(self)
+    }
+
+    /// Removes the value, returning it if the key was occupied.
+    pub fn remove(mut self) -> Result<Option<Bytes>, StoreError> {
+        self.guard.remove(&self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Store;
+    use bytes::Bytes;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn or_insert_and_modify() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+
+        let v = store.entry(k.clone()).unwrap().or_insert(Bytes::from("a"));
+        assert_eq!(v, Ok(Bytes::from("a")));
+        let v = store.entry(k.clone()).unwrap().or_insert(Bytes::from("b"));
+        assert_eq!(v, Ok(Bytes::from("a")));
+
+        let v = store
+            .entry(k.clone())
+            .unwrap()
+            .and_modify(|v| *v = Bytes::from("c"))
+            .unwrap()
+            .or_insert(Bytes::from("d"));
+        assert_eq!(v, Ok(Bytes::from("c")));
+
+        let removed = store.entry(k.clone()).unwrap().remove();
+        assert_eq!(removed, Ok(Some(Bytes::from("c"))));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn expired_value_is_vacant() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+        store.set_with_ttl(k.clone(), Bytes::from("old"), Duration::ZERO);
+
+        let entry = store.entry(k).unwrap();
+        assert_eq!(entry.get(), None);
+        assert_eq!(entry.or_insert(Bytes::from("new")), Ok(Bytes::from("new")));
+    }
+
+    #[test]
+    fn check_and_insert_are_not_interleaved() {
+        let store = Store::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|n| {
+                let (store, calls) = (store.clone(), calls.clone());
+                thread::spawn(move || {
+                    store
+                        .entry(Bytes::from("once"))
+                        .unwrap()
+                        .or_insert_with(|| {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            Bytes::from(n.to_string())
+                        })
+                        .unwrap()
+                })
+            })
+            .collect();
+
+        let values: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(values.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+}
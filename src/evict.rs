@@ -0,0 +1,587 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    fmt::{self, Debug},
+    hash::BuildHasher,
+    time::Instant,
+};
+
+use bytes::Bytes;
+
+use crate::{State, Store, StoreError};
+
+// Rough bookkeeping cost of a record beyond its key and value, counted
+// against a store's byte limit
+pub(crate) const ENTRY_OVERHEAD: usize = 64;
+
+/// Decides which record a bounded store evicts when it is over its [`Limit`].
+///
+/// The policy lives inside the store's lock, so its callbacks never run
+/// concurrently and implementations need no synchronization of their own.
+/// They also run under that lock, so they must not call back into the store.
+pub trait EvictionPolicy: Send + Sync {
+    /// A key that wasn't in the store was written.
+    fn on_insert(&mut self, k: &Bytes);
+
+    /// A key in the store was read or overwritten.
+    fn on_access(&mut self, k: &Bytes);
+
+    /// A key left the store, whether removed, expired, or evicted.
+    fn on_remove(&mut self, k: &Bytes);
+
+    /// Picks the key to evict next, which must be one the policy has been told
+    /// was inserted and not since removed. Eviction stops early if it returns
+    /// `None` or a key that isn't in the store.
+    fn pick_victim(&mut self) -> Option<Bytes>;
+}
+
+/// How much a bounded store may hold before it evicts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// At most this many records.
+    Entries(usize),
+    /// Roughly this many bytes, counting each key and value plus a fixed
+    /// per-entry overhead. A single record larger than the limit is still
+    /// admitted, but only once every other record has been evicted.
+    Bytes(usize),
+}
+
+/// Evicts the least recently read or written key.
+#[derive(Debug, Default)]
+pub struct LruPolicy {
+    // Ticks once per use, so a larger stamp means a more recent one
+    clock: u64,
+    stamps: HashMap<Bytes, u64>,
+    order: BTreeMap<u64, Bytes>,
+}
+
+/// Evicts the key that was inserted first, ignoring reads and overwrites.
+#[derive(Debug, Default)]
+pub struct FifoPolicy(LruPolicy);
+
+/// Evicts a key chosen uniformly at random, which suits scan-heavy workloads
+/// that make recency meaningless.
+#[derive(Debug, Default)]
+pub struct RandomPolicy {
+    keys: Vec<Bytes>,
+    positions: HashMap<Bytes, usize>,
+    seed: RandomState,
+    draws: u64,
+}
+
+// Accounts for what a bounded store holds and asks its policy what to evict
+pub(crate) struct Capacity {
+    limit: Limit,
+    // Approximate size of every record, overhead included, and their total
+    sizes: HashMap<Bytes, usize>,
+    used: usize,
+    evicted: u64,
+    policy: Box<dyn EvictionPolicy>,
+}
+
+impl Store {
+    /// Creates a store that holds at most `max_entries` records, evicting the
+    /// least recently used one when a `set` would exceed that.
+    ///
+    /// Both `get` and `set` count as a use of the key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_entries` is zero.
+    pub fn with_capacity(max_entries: usize) -> Self {
+        Self::with_policy(Limit::Entries(max_entries), LruPolicy::default())
+    }
+
+    /// Creates a store whose records take up roughly `limit` bytes at most,
+    /// evicting the least recently used ones when a `set` would exceed that.
+    /// See [`Limit::Bytes`] for how records are counted.
+    pub fn with_max_bytes(limit: usize) -> Self {
+        Self::with_policy(Limit::Bytes(limit), LruPolicy::default())
+    }
+
+    /// Creates a store bounded by `limit` that lets `policy` pick which
+    /// records to evict. `get` and `set` are reported to the policy as
+    /// accesses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is `Limit::Entries(0)`.
+    pub fn with_policy(limit: Limit, policy: impl EvictionPolicy + 'static) -> Self {
+        Self::from_state(State {
+            capacity: Some(Capacity::new(limit, policy)),
+            ..State::new(Default::default(), Default::default())
+        })
+    }
+
+    /// How many records have been evicted to stay within capacity. Always `0`
+    /// for an unbounded store or if the lock can't be taken.
+    pub fn evictions(&self) -> u64 {
+        self.read_state(|guard| guard.capacity.as_ref().map_or(0, |c| c.evicted))
+            .unwrap_or_default()
+    }
+
+    /// The total length of every live key and value. Returns `0` if the lock
+    /// can't be taken.
+    pub fn byte_len(&self) -> usize {
+        let now = Instant::now();
+        self.read_state(|guard| guard.footprint(0, now))
+            .unwrap_or_default()
+    }
+
+    /// A rough estimate of the memory every record held takes up, kept up to
+    /// date on every write so that reading it is cheap enough to export to
+    /// monitoring. Unlike [`Store::memory_usage`], this includes expired
+    /// records that haven't been dropped yet, since they still take up memory.
+    /// Returns `0` if the lock can't be taken.
+    pub fn approximate_size(&self) -> usize {
+        self.read_state(|guard| guard.size).unwrap_or_default()
+    }
+
+    /// A rough estimate of the memory the live records take up: their
+    /// [`Store::byte_len`] plus a fixed bookkeeping cost per record, the same
+    /// one counted against a [`Limit::Bytes`]. Returns `0` if the lock can't
+    /// be taken.
+    pub fn memory_usage(&self) -> usize {
+        let now = Instant::now();
+        self.read_state(|guard| guard.footprint(ENTRY_OVERHEAD, now))
+            .unwrap_or_default()
+    }
+}
+
+// The size of a record as counted against a `Limit::Bytes`
+pub(crate) fn entry_size(k: &Bytes, v: &Bytes) -> usize {
+    k.len() + v.len() + ENTRY_OVERHEAD
+}
+
+impl Capacity {
+    // Panics on a limit of no entries, which nothing could be stored under
+    pub(crate) fn new(limit: Limit, policy: impl EvictionPolicy + 'static) -> Self {
+        assert!(
+            limit != Limit::Entries(0),
+            "a store needs room for at least one entry"
+        );
+        Self {
+            limit,
+            sizes: HashMap::new(),
+            used: 0,
+            evicted: 0,
+            policy: Box::new(policy),
+        }
+    }
+}
+
+impl<S: BuildHasher> State<S> {
+    // Sums the sizes of the live records, counting `overhead` extra for each
+    fn footprint(&self, overhead: usize, now: Instant) -> usize {
+        self.records
+            .iter()
+            .filter(|(k, _)| !self.is_expired(k, now))
+            .map(|(k, v)| k.len() + v.len() + overhead)
+            .sum()
+    }
+
+    // Reports a read of a record to the eviction policy
+    pub(crate) fn touch(&mut self, k: &Bytes) {
+        if let Some(capacity) = &mut self.capacity {
+            if capacity.sizes.contains_key(k) {
+                capacity.policy.on_access(k);
+            }
+        }
+    }
+
+    // Accounts for a record that was just written
+    pub(crate) fn track(&mut self, k: &Bytes, v: &Bytes) {
+        self.track_size(k, entry_size(k, v));
+    }
+
+    // Like `track`, given the record's size rather than its value
+    pub(crate) fn track_size(&mut self, k: &Bytes, size: usize) {
+        if let Some(capacity) = &mut self.capacity {
+            match capacity.sizes.insert(k.clone(), size) {
+                Some(prev) => {
+                    capacity.used -= prev;
+                    capacity.policy.on_access(k);
+                }
+                None => capacity.policy.on_insert(k),
+            }
+            capacity.used += size;
+        }
+    }
+
+    // Evicts the records the policy picks until setting `k` to `v` fits under
+    // the store's hard byte limit, if it has both. Gives up, leaving
+    // `check_set` to fail the write, if `v` couldn't fit even in an empty store
+    // or the policy runs out of keys to pick. `k` itself is never evicted: if
+    // the policy picks it, it is hidden from the policy until room is made
+    pub(crate) fn make_room(&mut self, k: &Bytes, v: &Bytes) -> Result<(), StoreError> {
+        let (Some(max), Some(_)) = (self.options.max_total_bytes, &self.capacity) else {
+            return Ok(());
+        };
+        if entry_size(k, v) > max {
+            return Ok(());
+        }
+        let mut hidden = None;
+        let made = loop {
+            let replaced = self.records.get(k).map_or(0, |prev| entry_size(k, prev));
+            if self.size - replaced + entry_size(k, v) <= max {
+                break Ok(());
+            }
+            let capacity = self.capacity.as_mut().unwrap();
+            let Some(victim) = capacity
+                .policy
+                .pick_victim()
+                .filter(|victim| capacity.sizes.contains_key(victim))
+            else {
+                break Ok(());
+            };
+            if victim == *k {
+                hidden = capacity.sizes.get(k).copied();
+                capacity.forget(k);
+                continue;
+            }
+            if let Err(err) = self.remove(&victim) {
+                break Err(err);
+            }
+            if let Some(capacity) = &mut self.capacity {
+                capacity.evicted += 1;
+            }
+        };
+        if let Some(size) = hidden {
+            self.track_size(k, size);
+        }
+        made
+    }
+
+    // Evicts the records the policy picks until the store is within capacity
+    pub(crate) fn evict(&mut self) -> Result<(), StoreError> {
+        while let Some(victim) = self.capacity.as_mut().and_then(Capacity::victim) {
+            self.remove(&victim)?;
+            if let Some(capacity) = &mut self.capacity {
+                capacity.evicted += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Capacity {
+    pub(crate) fn forget(&mut self, k: &Bytes) {
+        if let Some(size) = self.sizes.remove(k) {
+            self.used -= size;
+            self.policy.on_remove(k);
+        }
+    }
+
+    // The record the policy picks to evict, if the store is over its limit. A
+    // lone record is never over a byte limit, however large it is
+    fn victim(&mut self) -> Option<Bytes> {
+        let len = self.sizes.len();
+        let over = match self.limit {
+            Limit::Entries(max) => len > max,
+            Limit::Bytes(max) => self.used > max && len > 1,
+        };
+        if !over {
+            return None;
+        }
+        self.policy
+            .pick_victim()
+            .filter(|k| self.sizes.contains_key(k))
+    }
+}
+
+impl Debug for Capacity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Capacity")
+            .field("limit", &self.limit)
+            .field("entries", &self.sizes.len())
+            .field("used", &self.used)
+            .field("evicted", &self.evicted)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EvictionPolicy for LruPolicy {
+    fn on_insert(&mut self, k: &Bytes) {
+        self.on_access(k);
+    }
+
+    fn on_access(&mut self, k: &Bytes) {
+        self.clock += 1;
+        if let Some(stamp) = self.stamps.insert(k.clone(), self.clock) {
+            self.order.remove(&stamp);
+        }
+        self.order.insert(self.clock, k.clone());
+    }
+
+    fn on_remove(&mut self, k: &Bytes) {
+        if let Some(stamp) = self.stamps.remove(k) {
+            self.order.remove(&stamp);
+        }
+    }
+
+    fn pick_victim(&mut self) -> Option<Bytes> {
+        self.order.values().next().cloned()
+    }
+}
+
+impl EvictionPolicy for FifoPolicy {
+    fn on_insert(&mut self, k: &Bytes) {
+        self.0.on_insert(k);
+    }
+
+    fn on_access(&mut self, _: &Bytes) {}
+
+    fn on_remove(&mut self, k: &Bytes) {
+        self.0.on_remove(k);
+    }
+
+    fn pick_victim(&mut self) -> Option<Bytes> {
+        self.0.pick_victim()
+    }
+}
+
+impl EvictionPolicy for RandomPolicy {
+    fn on_insert(&mut self, k: &Bytes) {
+        self.positions.insert(k.clone(), self.keys.len());
+        self.keys.push(k.clone());
+    }
+
+    fn on_access(&mut self, _: &Bytes) {}
+
+    fn on_remove(&mut self, k: &Bytes) {
+        let Some(position) = self.positions.remove(k) else {
+            return;
+        };
+        self.keys.swap_remove(position);
+        if let Some(moved) = self.keys.get(position) {
+            self.positions.insert(moved.clone(), position);
+        }
+    }
+
+    fn pick_victim(&mut self) -> Option<Bytes> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        // Hashing a counter with a randomly seeded hasher is plenty random for
+        // eviction, without pulling in an RNG
+        self.draws += 1;
+        let draw = self.seed.hash_one(self.draws) as usize;
+        Some(self.keys[draw % self.keys.len()].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EvictionPolicy, FifoPolicy, Limit, RandomPolicy, ENTRY_OVERHEAD};
+    use crate::Store;
+    use bytes::Bytes;
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    // Records every callback and evicts whatever it was told about last
+    #[derive(Default)]
+    struct Recorder {
+        calls: Arc<Mutex<Vec<String>>>,
+        last: Option<Bytes>,
+    }
+
+    impl Recorder {
+        fn record(&self, call: &str, k: &Bytes) {
+            let k = String::from_utf8_lossy(k);
+            self.calls.lock().unwrap().push(format!("{call} {k}"));
+        }
+    }
+
+    impl EvictionPolicy for Recorder {
+        fn on_insert(&mut self, k: &Bytes) {
+            self.record("insert", k);
+            self.last = Some(k.clone());
+        }
+
+        fn on_access(&mut self, k: &Bytes) {
+            self.record("access", k);
+        }
+
+        fn on_remove(&mut self, k: &Bytes) {
+            self.record("remove", k);
+        }
+
+        fn pick_victim(&mut self) -> Option<Bytes> {
+            self.calls.lock().unwrap().push("pick".to_string());
+            self.last.take()
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let store = Store::with_capacity(3);
+        for k in ["a", "b", "c"] {
+            store.set(Bytes::from(k), Bytes::from("v"));
+        }
+
+        // Reading "a" makes "b" the oldest
+        assert!(store.get(Bytes::from("a")).is_some());
+        store.set(Bytes::from("d"), Bytes::from("v"));
+
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(Bytes::from("b")), None);
+        for k in ["a", "c", "d"] {
+            assert!(store.contains_key(&Bytes::from(k)));
+        }
+    }
+
+    #[test]
+    fn overwrite_does_not_evict() {
+        let store = Store::with_capacity(2);
+        store.set(Bytes::from("a"), Bytes::from("1"));
+        store.set(Bytes::from("b"), Bytes::from("1"));
+        store.set(Bytes::from("a"), Bytes::from("2"));
+        store.remove(Bytes::from("b"));
+        store.set(Bytes::from("c"), Bytes::from("1"));
+
+        assert_eq!(store.get(Bytes::from("a")), Some(Bytes::from("2")));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.evictions(), 0);
+    }
+
+    #[test]
+    fn byte_limit_evicts_least_recently_used() {
+        let entry = ENTRY_OVERHEAD + 2;
+        let store = Store::with_max_bytes(3 * entry);
+        for k in ["a", "b", "c"] {
+            store.set(Bytes::from(k), Bytes::from("v"));
+        }
+        assert!(store.get(Bytes::from("a")).is_some());
+
+        store.set(Bytes::from("d"), Bytes::from("v"));
+        assert_eq!(store.get(Bytes::from("b")), None);
+        assert_eq!(store.evictions(), 1);
+
+        // Growing a value can push out more than one record
+        store.set(Bytes::from("d"), Bytes::from(vec![0; entry + 1]));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(Bytes::from("c")), None);
+        assert!(store.contains_key(&Bytes::from("a")));
+    }
+
+    #[test]
+    fn oversized_value_is_admitted_alone() {
+        let store = Store::with_max_bytes(ENTRY_OVERHEAD * 4);
+        store.set(Bytes::from("small"), Bytes::from("v"));
+        store.set(
+            Bytes::from("huge"),
+            Bytes::from(vec![0; ENTRY_OVERHEAD * 8]),
+        );
+        assert_eq!(store.keys(), [Bytes::from("huge")]);
+
+        store.set(Bytes::from("small"), Bytes::from("v"));
+        assert_eq!(store.keys(), [Bytes::from("small")]);
+    }
+
+    #[test]
+    fn sizes_count_live_records() {
+        let store = Store::new();
+        for (k, len) in [("a", 10), ("bb", 100), ("ccc", 0)] {
+            store.set(Bytes::from(k), Bytes::from(vec![0; len]));
+        }
+        store.set_with_ttl(
+            Bytes::from("gone"),
+            Bytes::from("v"),
+            Duration::from_millis(10),
+        );
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(store.byte_len(), 1 + 10 + 2 + 100 + 3);
+        assert_eq!(store.memory_usage(), 116 + 3 * ENTRY_OVERHEAD);
+        assert_eq!(Store::new().memory_usage(), 0);
+    }
+
+    #[test]
+    fn approximate_size_tracks_writes() {
+        let size = |k: &str, v: usize| k.len() + v + ENTRY_OVERHEAD;
+        let store = Store::from_iter([("a", "12345")]);
+        assert_eq!(store.approximate_size(), size("a", 5));
+
+        store.set(Bytes::from("bb"), Bytes::from(vec![0; 100]));
+        store.set(Bytes::from("a"), Bytes::from(vec![0; 20]));
+        assert_eq!(store.approximate_size(), size("a", 20) + size("bb", 100));
+
+        store.update(Bytes::from("bb"), |_| Some(Bytes::from("x")));
+        store.remove(Bytes::from("a"));
+        store.remove(Bytes::from("missing"));
+        assert_eq!(store.approximate_size(), size("bb", 1));
+
+        store.clear();
+        assert_eq!(store.approximate_size(), 0);
+    }
+
+    #[test]
+    fn byte_limit_holds_under_churn() {
+        let limit = 10 * 1024;
+        let store = Store::with_max_bytes(limit);
+        for n in 0..2000usize {
+            let k = Bytes::from(format!("key{}", n % 300));
+            store.set(k, Bytes::from(vec![0; n % 200]));
+            if n % 3 == 0 {
+                store.get(Bytes::from(format!("key{}", n % 7)));
+            }
+            assert!(store.read().unwrap().capacity.as_ref().unwrap().used <= limit);
+        }
+        assert!(store.evictions() > 0);
+        assert!(!store.is_empty());
+    }
+
+    #[test]
+    fn policy_sees_every_callback() {
+        let policy = Recorder::default();
+        let calls = policy.calls.clone();
+        let store = Store::with_policy(Limit::Entries(2), policy);
+
+        store.set(Bytes::from("a"), Bytes::from("1"));
+        store.set(Bytes::from("a"), Bytes::from("2"));
+        store.get(Bytes::from("a"));
+        store.get(Bytes::from("missing"));
+        store.set(Bytes::from("b"), Bytes::from("1"));
+        store.remove(Bytes::from("a"));
+        store.set(Bytes::from("c"), Bytes::from("1"));
+        store.set(Bytes::from("d"), Bytes::from("1"));
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "insert a", "access a", "access a", "insert b", "remove a", "insert c", "insert d",
+                "pick", "remove d",
+            ]
+        );
+        assert_eq!(store.evictions(), 1);
+    }
+
+    #[test]
+    fn fifo_ignores_reads() {
+        let store = Store::with_policy(Limit::Entries(2), FifoPolicy::default());
+        store.set(Bytes::from("a"), Bytes::from("v"));
+        store.set(Bytes::from("b"), Bytes::from("v"));
+        store.get(Bytes::from("a"));
+        store.set(Bytes::from("a"), Bytes::from("again"));
+        store.set(Bytes::from("c"), Bytes::from("v"));
+
+        assert_eq!(store.get(Bytes::from("a")), None);
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn random_stays_within_limit() {
+        let store = Store::with_policy(Limit::Entries(10), RandomPolicy::default());
+        for n in 0..100 {
+            store.set(Bytes::from(format!("k{n}")), Bytes::from("v"));
+            if n % 10 == 0 {
+                store.remove(Bytes::from(format!("k{}", n / 2)));
+            }
+            assert!(store.len() <= 10);
+        }
+        assert_eq!(store.len(), 10);
+        assert!(store.evictions() >= 80);
+    }
+}
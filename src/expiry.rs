@@ -0,0 +1,340 @@
+use std::{
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+
+use crate::{State, Store, StoreError};
+
+/// Controls a background sweeper started by [`Store::start_sweeper`].
+///
+/// Dropping the handle stops the sweeper, as does dropping every handle to the
+/// store it sweeps.
+#[derive(Debug)]
+pub struct SweeperHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    evicted: Arc<AtomicU64>,
+}
+
+impl Store {
+    /// Like [`Store::set`], but the entry expires once `ttl` has elapsed.
+    ///
+    /// Expired entries read as absent everywhere. A zero `ttl` expires the
+    /// entry immediately, and one too long to represent never expires, even
+    /// in a store with a default TTL. A later plain `set` of the key replaces
+    /// its TTL as that `set` says.
+    pub fn set_with_ttl(&self, k: Bytes, v: Bytes, ttl: Duration) {
+        self.try_set_with_ttl(k, v, ttl).unwrap_or_default();
+    }
+
+    /// Like [`Store::try_set`], but the entry expires once `ttl` has elapsed.
+    pub fn try_set_with_ttl(
+        &self,
+        k: Bytes,
+        v: Bytes,
+        ttl: Duration,
+    ) -> Result<Option<Bytes>, StoreError> {
+        self.0.options.check_entry(&k, &v)?;
+        // A TTL too long to represent as an `Instant` is as good as none
+        let expiry = Instant::now()
+            .checked_add(ttl)
+            .map_or(Expiry::Never, Expiry::At);
+        self.mutate(|state| state.set(k, v, expiry))
+    }
+
+    /// Returns how long the entry has left to live, or `None` if it is absent
+    /// or has no TTL.
+    pub fn ttl(&self, k: &Bytes) -> Option<Duration> {
+        let now = Instant::now();
+        self.read_state(|guard| {
+            guard.get(k, now)?;
+            guard
+                .expiries
+                .get(k)
+                .map(|deadline| deadline.saturating_duration_since(now))
+        })
+        .unwrap_or_default()
+    }
+
+    /// Starts a thread that drops expired entries every `interval`, so entries
+    /// that are never read again don't linger until their next access.
+    ///
+    /// The thread only holds a weak reference to the store, and exits on its
+    /// own once every `Store` handle has been dropped.
+    pub fn start_sweeper(&self, interval: Duration) -> SweeperHandle {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let evicted = Arc::new(AtomicU64::new(0));
+        let thread = self.spawn_sweeper(evicted.clone(), move || {
+            matches!(
+                stopped.recv_timeout(interval),
+                Err(RecvTimeoutError::Timeout)
+            )
+        });
+
+        SweeperHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+            evicted,
+        }
+    }
+
+    /// Like [`Store::start_sweeper`], but without a way to stop the thread
+    /// early: it runs until every `Store` handle has been dropped, noticing
+    /// within one `interval`.
+    pub fn spawn_expiration_sweeper(&self, interval: Duration) -> JoinHandle<()> {
+        self.spawn_sweeper(Arc::default(), move || {
+            thread::sleep(interval);
+            true
+        })
+    }
+
+    // Spawns a thread that sweeps each time `wait` returns `true`, holding only
+    // a weak reference to the store between sweeps
+    fn spawn_sweeper(
+        &self,
+        evicted: Arc<AtomicU64>,
+        mut wait: impl FnMut() -> bool + Send + 'static,
+    ) -> JoinHandle<()> {
+        let store = Arc::downgrade(&self.0);
+        thread::spawn(move || {
+            while wait() {
+                let Some(store) = store.upgrade().map(Store) else {
+                    return;
+                };
+                if let Ok(n) = store.sweep() {
+                    evicted.fetch_add(n as u64, Ordering::Relaxed);
+                    store.0.counters.record_expired(n as u64);
+                }
+            }
+        })
+    }
+
+    // Drops every expired entry, returning how many there were
+    fn sweep(&self) -> Result<usize, StoreError> {
+        let now = Instant::now();
+        self.mutate(|state| state.sweep(now))
+    }
+}
+
+// When a record written through `State::set` expires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Expiry {
+    // Once the store's default TTL has elapsed, or never if it has none
+    Default,
+    // Never, whatever the store's default TTL
+    Never,
+    At(Instant),
+}
+
+impl<S: BuildHasher> State<S> {
+    // The expiry that keeps whatever TTL `k` has, or the default one if it is
+    // absent, for writes that modify a value in place
+    pub(crate) fn kept_expiry(&self, k: &Bytes, now: Instant) -> Expiry {
+        if self.get(k, now).is_none() {
+            return Expiry::Default;
+        }
+        self.expiries
+            .get(k)
+            .map_or(Expiry::Never, |d| Expiry::At(*d))
+    }
+
+    fn sweep(&mut self, now: Instant) -> Result<usize, StoreError> {
+        let expired: Vec<Bytes> = self
+            .expiries
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for k in &expired {
+            self.remove(k)?;
+        }
+        Ok(expired.len())
+    }
+}
+
+impl SweeperHandle {
+    /// The number of expired entries the sweeper has dropped so far.
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// Stops the sweeper and waits for its thread to exit.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SweeperHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// Converts a deadline to wall-clock milliseconds since the Unix epoch so it
+// survives being persisted
+pub(crate) fn to_unix_millis(deadline: Instant) -> u64 {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    SystemTime::now()
+        .checked_add(remaining)
+        .and_then(|wall| wall.duration_since(UNIX_EPOCH).ok())
+        .map_or(u64::MAX, |since| since.as_millis() as u64)
+}
+
+// Converts a persisted deadline back to an `Instant`, or `None` if it has
+// already passed
+pub(crate) fn from_unix_millis(millis: u64) -> Option<Instant> {
+    let wall = UNIX_EPOCH.checked_add(Duration::from_millis(millis))?;
+    let remaining = wall.duration_since(SystemTime::now()).ok()?;
+    Some(
+        Instant::now()
+            .checked_add(remaining)
+            .unwrap_or_else(far_future),
+    )
+}
+
+// Stands in for a deadline too distant to represent
+fn far_future() -> Instant {
+    Instant::now() + Duration::from_secs(100 * 365 * 24 * 60 * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Store;
+    use bytes::Bytes;
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn expired_entries_read_as_absent() {
+        let store = Store::new();
+        store.set_with_ttl(
+            Bytes::from("session"),
+            Bytes::from("token"),
+            Duration::from_millis(10),
+        );
+        store.set(Bytes::from("forever"), Bytes::from("value"));
+        assert_eq!(
+            store.get(Bytes::from("session")),
+            Some(Bytes::from("token"))
+        );
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(!store.contains_key(&Bytes::from("session")));
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(Bytes::from("session")), None);
+        assert_eq!(store.try_remove(Bytes::from("session")), Ok(None));
+
+        // The read dropped the entry rather than just hiding it
+        assert_eq!(store.read().unwrap().records.len(), 1);
+    }
+
+    #[test]
+    fn zero_ttl_expires_immediately() {
+        let store = Store::new();
+        store.set_with_ttl(Bytes::from("k"), Bytes::from("v"), Duration::ZERO);
+
+        assert_eq!(store.get(Bytes::from("k")), None);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn plain_set_clears_ttl() {
+        let store = Store::new();
+        store.set_with_ttl(
+            Bytes::from("k"),
+            Bytes::from("v1"),
+            Duration::from_millis(10),
+        );
+        store.set(Bytes::from("k"), Bytes::from("v2"));
+        assert_eq!(store.ttl(&Bytes::from("k")), None);
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(store.get(Bytes::from("k")), Some(Bytes::from("v2")));
+    }
+
+    #[test]
+    fn ttl_reports_remaining_lifetime() {
+        let store = Store::new();
+        store.set_with_ttl(Bytes::from("k"), Bytes::from("v"), Duration::from_secs(60));
+
+        let remaining = store.ttl(&Bytes::from("k")).unwrap();
+        assert!(remaining <= Duration::from_secs(60));
+        assert!(remaining > Duration::from_secs(59));
+        assert_eq!(store.ttl(&Bytes::from("missing")), None);
+    }
+
+    #[test]
+    fn sweeper_drops_unread_entries() {
+        let store = Store::new();
+        for n in 0..5 {
+            store.set_with_ttl(
+                Bytes::from(format!("k{n}")),
+                Bytes::from("v"),
+                Duration::from_millis(10),
+            );
+        }
+        store.set(Bytes::from("forever"), Bytes::from("v"));
+        let sweeper = store.start_sweeper(Duration::from_millis(5));
+
+        let start = Instant::now();
+        while sweeper.evicted() < 5 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(sweeper.evicted(), 5);
+        assert_eq!(store.read().unwrap().records.len(), 1);
+        sweeper.stop();
+    }
+
+    #[test]
+    fn sweeper_exits_with_last_store() {
+        let store = Store::new();
+        let mut sweeper = store.start_sweeper(Duration::from_millis(5));
+        drop(store);
+
+        let thread = sweeper.thread.take().unwrap();
+        let start = Instant::now();
+        while !thread.is_finished() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(thread.is_finished());
+    }
+
+    #[test]
+    fn detached_sweeper_empties_store_and_exits() {
+        let store = Store::new();
+        store.set_with_ttl(
+            Bytes::from("k"),
+            Bytes::from("v"),
+            Duration::from_millis(10),
+        );
+        let sweeper = store.spawn_expiration_sweeper(Duration::from_millis(5));
+
+        let start = Instant::now();
+        while !store.read().unwrap().records.is_empty() && start.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(store.read().unwrap().records.is_empty());
+        assert_eq!(store.len(), 0);
+
+        drop(store);
+        sweeper.join().unwrap();
+    }
+}
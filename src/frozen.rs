@@ -0,0 +1,107 @@
+use std::{sync::Arc, time::Instant};
+
+use bytes::Bytes;
+
+use crate::{Records, Store};
+
+/// A read-only copy of a store's live records at one instant, returned by
+/// [`Store::freeze`]. Every read sees the same records however the store
+/// changes afterwards, and none of them take a lock.
+///
+/// Cloning a `FrozenStore` is cheap and shares the same records.
+#[derive(Debug, Clone, Default)]
+pub struct FrozenStore(Arc<Records>);
+
+impl Store {
+    /// Copies the live records under a single lock acquisition into a
+    /// [`FrozenStore`], e.g. so that every read in a report sees the same
+    /// state. Keys and values are reference counted, so this copies the map
+    /// but none of the bytes.
+    ///
+    /// Returns an empty copy if the lock can't be taken.
+    pub fn freeze(&self) -> FrozenStore {
+        let now = Instant::now();
+        let records = self
+            .read_state(|state| {
+                state
+                    .records
+                    .iter()
+                    .filter(|(k, _)| !state.is_expired(k, now))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        FrozenStore(Arc::new(records))
+    }
+}
+
+impl FrozenStore {
+    pub fn get(&self, k: &Bytes) -> Option<&Bytes> {
+        self.0.get(k)
+    }
+
+    pub fn contains_key(&self, k: &Bytes) -> bool {
+        self.0.contains_key(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterates over every record, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Bytes)> {
+        self.0.iter()
+    }
+
+    /// Iterates over the records whose key starts with `prefix`, in no
+    /// particular order. Like [`Store::scan_prefix`], this scans every record.
+    pub fn scan_prefix<'a>(
+        &'a self,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = (&'a Bytes, &'a Bytes)> {
+        self.iter().filter(move |(k, _)| k.starts_with(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrozenStore;
+    use crate::Store;
+    use bytes::Bytes;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn later_writes_are_invisible() {
+        let store: Store = [("user:1", "a"), ("user:2", "b"), ("order:1", "c")]
+            .into_iter()
+            .collect();
+        store.set_with_ttl(Bytes::from("gone"), Bytes::from("v"), Duration::ZERO);
+        let frozen = store.freeze();
+
+        store.set(Bytes::from("user:1"), Bytes::from("changed"));
+        store.remove(Bytes::from("user:2"));
+        store.set(Bytes::from("user:3"), Bytes::from("new"));
+
+        assert_eq!(frozen.len(), 3);
+        assert_eq!(frozen.get(&Bytes::from("user:1")), Some(&Bytes::from("a")));
+        assert!(frozen.contains_key(&Bytes::from("user:2")));
+        assert!(!frozen.contains_key(&Bytes::from("gone")));
+        assert_eq!(frozen.scan_prefix(b"user:").count(), 2);
+    }
+
+    #[test]
+    fn shares_across_threads() {
+        let frozen = Store::from_iter([("k", "v")]).freeze();
+        let handle = frozen.clone();
+        let v = thread::spawn(move || handle.get(&Bytes::from("k")).cloned())
+            .join()
+            .unwrap();
+
+        assert_eq!(v, Some(Bytes::from("v")));
+        assert!(FrozenStore::default().is_empty());
+    }
+}
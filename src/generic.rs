@@ -0,0 +1,221 @@
+use std::{
+    borrow::Borrow,
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash},
+    sync::{Arc, RwLock},
+};
+
+use crate::StoreError;
+
+/// An in-memory store of any key and value types, with no serialization
+/// involved.
+///
+/// This is a plain map behind a lock, not a [`Store`](crate::Store) with
+/// other types plugged in. It has `get`, `set`, `remove` and the like, but
+/// none of what `Store` layers on top: no write-ahead log or snapshots, no
+/// TTLs, no [`StoreOptions`](crate::StoreOptions) limits or eviction, no
+/// watchers, changelog or history, no statistics, and no poison policy, so a
+/// poisoned lock is always reported. Reach for
+/// [`TypedStore`](crate::TypedStore) to get those with typed keys and values.
+///
+/// Keys are hashed with `S`, which defaults to the standard library's
+/// HashDoS-resistant hasher. See [`GenericStore::with_hasher`] to trade that
+/// for speed when keys come from a trusted source, as
+/// [`Store::with_hasher`](crate::Store::with_hasher) does for a `Store`.
+///
+/// Like `Store`, cloning a `GenericStore` shares the same records.
+#[derive(Debug)]
+pub struct GenericStore<K, V, S = RandomState>(Arc<RwLock<HashMap<K, V, S>>>);
+
+impl<K, V> GenericStore<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self(Arc::default())
+    }
+}
+
+impl<K, V, S> GenericStore<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Creates a store whose keys are hashed with `hasher`.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self(Arc::new(RwLock::new(HashMap::with_hasher(hasher))))
+    }
+
+    /// Looks up a key by any borrowed form of it, e.g. `&str` for a `String`
+    /// key.
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.try_get(k).unwrap_or_default()
+    }
+
+    /// Inserts a value, returning the one it replaced.
+    pub fn set(&self, k: K, v: V) -> Option<V> {
+        self.try_set(k, v).unwrap_or_default()
+    }
+
+    /// Removes a value, returning it if it was present.
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.try_remove(k).unwrap_or_default()
+    }
+
+    /// Like [`GenericStore::get`], but reports a poisoned lock instead of
+    /// returning `None`.
+    pub fn try_get<Q>(&self, k: &Q) -> Result<Option<V>, StoreError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Ok(self.0.read()?.get(k).cloned())
+    }
+
+    /// Like [`GenericStore::set`], but reports a poisoned lock instead of
+    /// returning `None`.
+    pub fn try_set(&self, k: K, v: V) -> Result<Option<V>, StoreError> {
+        Ok(self.0.write()?.insert(k, v))
+    }
+
+    /// Like [`GenericStore::remove`], but reports a poisoned lock instead of
+    /// returning `None`.
+    pub fn try_remove<Q>(&self, k: &Q) -> Result<Option<V>, StoreError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Ok(self.0.write()?.remove(k))
+    }
+
+    /// Returns `false` if the key is absent or the lock can't be taken.
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.0
+            .read()
+            .map(|records| records.contains_key(k))
+            .unwrap_or_default()
+    }
+
+    /// Returns `0` if the lock can't be taken.
+    pub fn len(&self) -> usize {
+        self.0
+            .read()
+            .map(|records| records.len())
+            .unwrap_or_default()
+    }
+
+    /// Returns `true` if the lock can't be taken.
+    pub fn is_empty(&self) -> bool {
+        self.0
+            .read()
+            .map(|records| records.is_empty())
+            .unwrap_or(true)
+    }
+}
+
+impl<K, V, S> Clone for GenericStore<K, V, S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K, V, S> Default for GenericStore<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GenericStore;
+    use bytes::Bytes;
+    use std::{
+        hash::{BuildHasherDefault, Hasher},
+        thread,
+    };
+
+    // FNV-1a, which hashes the same way in every process
+    struct Fnv(u64);
+
+    impl Default for Fnv {
+        fn default() -> Self {
+            Self(0xcbf29ce484222325)
+        }
+    }
+
+    impl Hasher for Fnv {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for b in bytes {
+                self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[test]
+    fn integer_keys_and_struct_values() {
+        let store = GenericStore::<u64, Point>::new();
+        assert_eq!(store.set(1, Point { x: 1, y: 2 }), None);
+        assert_eq!(
+            store.set(1, Point { x: 3, y: 4 }),
+            Some(Point { x: 1, y: 2 })
+        );
+        assert_eq!(store.get(&1), Some(Point { x: 3, y: 4 }));
+
+        let handle = store.clone();
+        thread::spawn(move || handle.remove(&1)).join().unwrap();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn borrowed_lookups() {
+        let store = GenericStore::<String, Vec<u8>>::new();
+        store.set("hello".to_string(), b"world".to_vec());
+
+        assert_eq!(store.get("hello"), Some(b"world".to_vec()));
+        assert!(store.contains_key("hello"));
+        assert_eq!(store.remove("hello"), Some(b"world".to_vec()));
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn custom_hasher() {
+        let store = GenericStore::with_hasher(BuildHasherDefault::<Fnv>::default());
+        store.set(Bytes::from("k"), Bytes::from("v"));
+        assert_eq!(store.get(b"k".as_slice()), Some(Bytes::from("v")));
+        assert_eq!(store.remove(b"k".as_slice()), Some(Bytes::from("v")));
+        assert!(!store.contains_key(b"k".as_slice()));
+
+        let defaulted = GenericStore::<u8, u8, BuildHasherDefault<Fnv>>::default();
+        defaulted.set(1, 2);
+        assert_eq!(defaulted.get(&1), Some(2));
+    }
+}
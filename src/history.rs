@@ -0,0 +1,135 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::BuildHasher,
+    time::Instant,
+};
+
+use bytes::Bytes;
+
+use crate::{Expiries, Records, State, Store};
+
+// The earlier values of every key written since the store was created,
+// newest first, with `None` where the key had been removed
+#[derive(Debug)]
+pub(crate) struct History {
+    depth: usize,
+    versions: HashMap<Bytes, VecDeque<Option<Bytes>>>,
+}
+
+impl Store {
+    /// Creates a store that remembers the `depth` values each key held before
+    /// its latest writes, for [`Store::get_version`] and [`Store::history`] to
+    /// look back on.
+    ///
+    /// A removal is remembered too, so a key that was removed can be told
+    /// apart from one that never existed. Earlier values count towards
+    /// [`Store::approximate_size`], and a key's history is kept even after the
+    /// key is removed.
+    pub fn with_history(depth: usize) -> Self {
+        Self::from_state(State {
+            history: Some(History {
+                depth,
+                versions: HashMap::new(),
+            }),
+            ..State::new(Records::new(), Expiries::new())
+        })
+    }
+
+    /// The value `k` held `generations_back` writes ago, where `0` is its
+    /// current value. Returns `None` if the key was absent then, if the store
+    /// doesn't remember that far back, or if the lock can't be taken.
+    pub fn get_version(&self, k: &Bytes, generations_back: usize) -> Option<Bytes> {
+        let now = Instant::now();
+        self.read_state(|state| match generations_back {
+            0 => state.get(k, now).cloned(),
+            n => state.history.as_ref()?.versions.get(k)?.get(n - 1)?.clone(),
+        })
+        .unwrap_or_default()
+    }
+
+    /// The values `k` held before its latest writes, newest first, with `None`
+    /// where it had been removed. Returns nothing if the store has no history
+    /// or the lock can't be taken.
+    pub fn history(&self, k: &Bytes) -> Vec<Option<Bytes>> {
+        self.read_state(|state| {
+            let versions = state.history.as_ref()?.versions.get(k)?;
+            Some(versions.iter().cloned().collect())
+        })
+        .unwrap_or_default()
+        .unwrap_or_default()
+    }
+}
+
+impl<S: BuildHasher> State<S> {
+    // Remembers the live value `k` had before a write, or that it was
+    // removed, if the store keeps a history
+    pub(crate) fn remember(&mut self, k: &Bytes, prev: Option<&Bytes>) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        // A key that never existed has nothing to remember
+        if prev.is_none() && !history.versions.contains_key(k) {
+            return;
+        }
+
+        let versions = history.versions.entry(k.clone()).or_default();
+        versions.push_front(prev.cloned());
+        self.size += prev.map_or(0, Bytes::len);
+        if versions.len() > history.depth {
+            if let Some(Some(dropped)) = versions.pop_back() {
+                self.size -= dropped.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{evict, Store};
+    use bytes::Bytes;
+
+    #[test]
+    fn keeps_the_latest_versions() {
+        let store = Store::with_history(3);
+        let k = Bytes::from("k");
+        for n in 0..10 {
+            store.set(k.clone(), Bytes::from(n.to_string()));
+        }
+
+        let version = |n: u8| Some(Bytes::from(n.to_string()));
+        assert_eq!(store.history(&k), [version(8), version(7), version(6)]);
+        assert_eq!(store.get_version(&k, 0), version(9));
+        assert_eq!(store.get_version(&k, 3), version(6));
+        assert_eq!(store.get_version(&k, 4), None);
+    }
+
+    #[test]
+    fn removals_leave_tombstones() {
+        let store = Store::with_history(4);
+        let k = Bytes::from("k");
+        store.set(k.clone(), Bytes::from("a"));
+        store.remove(k.clone());
+        store.set(k.clone(), Bytes::from("b"));
+
+        assert_eq!(store.history(&k), [None, Some(Bytes::from("a"))]);
+        assert_eq!(store.get_version(&k, 1), None);
+        assert_eq!(store.get_version(&k, 2), Some(Bytes::from("a")));
+        assert!(store.history(&Bytes::from("never")).is_empty());
+        assert!(Store::new().history(&k).is_empty());
+    }
+
+    #[test]
+    fn earlier_versions_count_towards_size() {
+        let store = Store::with_history(2);
+        let k = Bytes::from("k");
+        for v in ["1", "22", "333"] {
+            store.set(k.clone(), Bytes::from(v));
+        }
+
+        let current = evict::entry_size(&k, &Bytes::from("333"));
+        assert_eq!(store.approximate_size(), current + 2 + 1);
+        store.set(k.clone(), Bytes::from("4444"));
+        let current = evict::entry_size(&k, &Bytes::from("4444"));
+        assert_eq!(store.approximate_size(), current + 3 + 2);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::BuildHasher,
+    time::Instant,
+};
+
+use bytes::Bytes;
+
+use crate::{Expiries, Records, State, Store};
+
+// The keys holding each value, expired or not, if the store keeps an index
+// of them
+#[derive(Debug, Default)]
+pub(crate) struct ValueIndex(HashMap<Bytes, HashSet<Bytes>>);
+
+impl Store {
+    /// Creates a store that keeps an index from each value to the keys holding
+    /// it, so [`Store::find_keys`] doesn't need to scan every record. The
+    /// index costs a copy of each key, so a plain store doesn't keep one.
+    pub fn with_value_index() -> Self {
+        Self::from_state(State {
+            index: Some(ValueIndex::default()),
+            ..State::new(Records::new(), Expiries::new())
+        })
+    }
+
+    /// Every live key whose value is exactly `value`, in no particular order.
+    ///
+    /// This scans every record unless the store was created with
+    /// [`Store::with_value_index`]. Returns nothing if the lock can't be
+    /// taken.
+    pub fn find_keys(&self, value: &[u8]) -> Vec<Bytes> {
+        let now = Instant::now();
+        self.read_state(|state| match &state.index {
+            Some(ValueIndex(by_value)) => by_value
+                .get(value)
+                .into_iter()
+                .flatten()
+                .filter(|k| !state.is_expired(k, now))
+                .cloned()
+                .collect(),
+            None => state
+                .records
+                .iter()
+                .filter(|(k, v)| v.as_ref() == value && !state.is_expired(k, now))
+                .map(|(k, _)| k.clone())
+                .collect(),
+        })
+        .unwrap_or_default()
+    }
+}
+
+impl<S: BuildHasher> State<S> {
+    // Moves `k` in the index from the value it held, if any, to `v`, if any
+    pub(crate) fn reindex(&mut self, k: &Bytes, prev: Option<&Bytes>, v: Option<&Bytes>) {
+        let Some(ValueIndex(by_value)) = &mut self.index else {
+            return;
+        };
+        if let Some(prev) = prev {
+            if let Some(keys) = by_value.get_mut(prev) {
+                keys.remove(k);
+                if keys.is_empty() {
+                    by_value.remove(prev);
+                }
+            }
+        }
+        if let Some(v) = v {
+            by_value.entry(v.clone()).or_default().insert(k.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Batch, Store};
+    use bytes::Bytes;
+    use std::{thread, time::Duration};
+
+    fn sorted(mut keys: Vec<Bytes>) -> Vec<Bytes> {
+        keys.sort();
+        keys
+    }
+
+    fn keys(keys: &[&'static str]) -> Vec<Bytes> {
+        keys.iter().copied().map(Bytes::from).collect()
+    }
+
+    #[test]
+    fn overwrites_never_leave_stale_keys() {
+        for store in [Store::new(), Store::with_value_index()] {
+            for k in ["a", "b", "c"] {
+                store.set(Bytes::from(k), Bytes::from("shared"));
+            }
+            assert_eq!(sorted(store.find_keys(b"shared")), keys(&["a", "b", "c"]));
+
+            store.set(Bytes::from("b"), Bytes::from("other"));
+            store.remove(Bytes::from("c"));
+            store.set(Bytes::from("a"), Bytes::from("shared"));
+            assert_eq!(store.find_keys(b"shared"), keys(&["a"]));
+            assert_eq!(store.find_keys(b"other"), keys(&["b"]));
+
+            store.clear();
+            assert!(store.find_keys(b"shared").is_empty());
+            assert!(store.find_keys(b"other").is_empty());
+        }
+    }
+
+    #[test]
+    fn batches_and_transactions_keep_the_index() {
+        let store = Store::with_value_index();
+        let mut batch = Batch::new();
+        batch.set(Bytes::from("a"), Bytes::from("v"));
+        batch.set(Bytes::from("b"), Bytes::from("v"));
+        store.apply(batch).unwrap();
+
+        store
+            .with_write(|txn| {
+                txn.set(Bytes::from("a"), Bytes::from("w"));
+                txn.remove(Bytes::from("b"));
+            })
+            .unwrap();
+        assert!(store.find_keys(b"v").is_empty());
+        assert_eq!(store.find_keys(b"w"), keys(&["a"]));
+    }
+
+    #[test]
+    fn expired_keys_are_not_found() {
+        let store = Store::with_value_index();
+        store.set_with_ttl(
+            Bytes::from("a"),
+            Bytes::from("v"),
+            Duration::from_millis(10),
+        );
+        store.set(Bytes::from("b"), Bytes::from("v"));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(store.find_keys(b"v"), keys(&["b"]));
+    }
+}
@@ -0,0 +1,279 @@
+use std::io::{self, Read, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use crate::{Records, Store, StoreError};
+
+/// How [`StoreSnapshot`] writes keys and values, which are arbitrary bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ByteEncoding {
+    /// Always as `{"base64": "..."}`.
+    #[default]
+    Base64,
+    /// As a plain string when the bytes are valid UTF-8, falling back to
+    /// base64 otherwise.
+    Utf8,
+}
+
+/// A serializable copy of a store's live records, taken under a single lock
+/// acquisition by [`Store::to_snapshot`].
+///
+/// It serializes as a list of `{"key": ..., "value": ...}` entries, whose
+/// bytes are encoded according to its [`ByteEncoding`]. Deserializing accepts
+/// either encoding.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreSnapshot {
+    entries: Vec<(Bytes, Bytes)>,
+    encoding: ByteEncoding,
+}
+
+impl Store {
+    /// Dumps the live records as a JSON object mapping base64-encoded keys to
+    /// base64-encoded values. TTLs aren't included.
+    pub fn export_json(&self) -> String {
+        let object: Map<String, Value> = self
+            .snapshot()
+            .into_iter()
+            .map(|(k, v)| (STANDARD.encode(k), Value::String(STANDARD.encode(v))))
+            .collect();
+        Value::Object(object).to_string()
+    }
+
+    /// Copies the live records into a snapshot that serializes with
+    /// `encoding`. TTLs aren't included.
+    pub fn to_snapshot(&self, encoding: ByteEncoding) -> StoreSnapshot {
+        StoreSnapshot {
+            entries: self.snapshot(),
+            encoding,
+        }
+    }
+
+    /// Writes a [`StoreSnapshot`] of the store to `w` as JSON.
+    pub fn to_json_writer(&self, w: impl Write, encoding: ByteEncoding) -> io::Result<()> {
+        serde_json::to_writer(w, &self.to_snapshot(encoding)).map_err(io::Error::from)
+    }
+
+    /// Builds a fresh store from JSON written by [`Store::to_json_writer`].
+    pub fn from_json_reader(r: impl Read) -> io::Result<Self> {
+        let snapshot: StoreSnapshot = serde_json::from_reader(r).map_err(io::Error::from)?;
+        Ok(snapshot.into())
+    }
+
+    /// Merges a document written by [`Store::export_json`] into the store
+    /// under a single lock acquisition, overwriting any keys it shares.
+    ///
+    /// The import is all or nothing: a malformed document, one that would
+    /// break the store's [`StoreOptions`](crate::StoreOptions), or a failing
+    /// append to the write-ahead log leaves the store untouched, as the
+    /// entries reach the log as a single record.
+    pub fn import_json(&self, json: &str) -> Result<(), StoreError> {
+        let value: Value =
+            serde_json::from_str(json).map_err(|_| StoreError::Import("not valid JSON"))?;
+        let Value::Object(object) = value else {
+            return Err(StoreError::Import("not a JSON object"));
+        };
+
+        let entries = object
+            .iter()
+            .map(|(k, v)| {
+                let k = decode(k).ok_or(StoreError::Import("key is not valid base64"))?;
+                let v = v
+                    .as_str()
+                    .and_then(decode)
+                    .ok_or(StoreError::Import("value is not a base64 string"))?;
+                Ok((k, Some(v)))
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        self.mutate(|state| state.write_all(entries).map(drop))
+    }
+}
+
+impl From<StoreSnapshot> for Store {
+    fn from(snapshot: StoreSnapshot) -> Self {
+        Self::from_records(snapshot.entries.into_iter().collect::<Records>())
+    }
+}
+
+// The JSON form of a key or value, borrowed for serializing
+#[derive(Serialize)]
+#[serde(untagged)]
+enum EncodedRef<'a> {
+    Utf8(&'a str),
+    Base64 { base64: String },
+}
+
+// The JSON form of a key or value, owned for deserializing
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Encoded {
+    Utf8(String),
+    Base64 { base64: String },
+}
+
+impl<'a> EncodedRef<'a> {
+    fn new(bytes: &'a Bytes, encoding: ByteEncoding) -> Self {
+        match (encoding, std::str::from_utf8(bytes)) {
+            (ByteEncoding::Utf8, Ok(s)) => Self::Utf8(s),
+            _ => Self::Base64 {
+                base64: STANDARD.encode(bytes),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    key: EncodedRef<'a>,
+    value: EncodedRef<'a>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    key: Encoded,
+    value: Encoded,
+}
+
+impl Serialize for StoreSnapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.entries.iter().map(|(k, v)| EntryRef {
+            key: EncodedRef::new(k, self.encoding),
+            value: EncodedRef::new(v, self.encoding),
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for StoreSnapshot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let decode = |encoded| match encoded {
+            Encoded::Utf8(s) => Ok(Bytes::from(s)),
+            Encoded::Base64 { base64 } => decode(&base64)
+                .ok_or_else(|| de::Error::custom(format!("invalid base64 {base64:?}"))),
+        };
+        let entries = Vec::<Entry>::deserialize(deserializer)?
+            .into_iter()
+            .map(|entry| Ok((decode(entry.key)?, decode(entry.value)?)))
+            .collect::<Result<_, D::Error>>()?;
+        Ok(Self {
+            entries,
+            encoding: ByteEncoding::default(),
+        })
+    }
+}
+
+fn decode(encoded: &str) -> Option<Bytes> {
+    STANDARD.decode(encoded).ok().map(Bytes::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteEncoding;
+    use crate::{Store, StoreError};
+    use bytes::Bytes;
+
+    fn init_store() -> Store {
+        let store = Store::new();
+        store.set(Bytes::from("hello"), Bytes::from("world"));
+        store.set(Bytes::from_static(b"\xff\x00"), Bytes::from("binary key"));
+        store.set(Bytes::from("empty"), Bytes::new());
+        store
+    }
+
+    #[test]
+    fn round_trip() {
+        let store = Store::new();
+        store.set(Bytes::from("hello"), Bytes::from("world"));
+        store.set(Bytes::from_static(b"\xff\x00"), Bytes::new());
+
+        let imported = Store::new();
+        imported.set(Bytes::from("kept"), Bytes::from("v"));
+        imported.import_json(&store.export_json()).unwrap();
+
+        assert_eq!(imported.len(), 3);
+        assert_eq!(
+            imported.get(Bytes::from("hello")),
+            Some(Bytes::from("world"))
+        );
+        assert_eq!(
+            imported.get(Bytes::from_static(b"\xff\x00")),
+            Some(Bytes::new())
+        );
+        assert_eq!(Store::new().export_json(), "{}");
+    }
+
+    #[test]
+    fn invalid_documents_are_rejected_whole() {
+        let store = Store::new();
+        for (json, reason) in [
+            ("{", "not valid JSON"),
+            ("[]", "not a JSON object"),
+            (
+                r#"{"aGk=": "dg==", "not base64!": "dg=="}"#,
+                "key is not valid base64",
+            ),
+            (
+                r#"{"aGk=": "dg==", "aGV5": 1}"#,
+                "value is not a base64 string",
+            ),
+        ] {
+            assert_eq!(store.import_json(json), Err(StoreError::Import(reason)));
+        }
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn oversized_entries_are_rejected_whole() {
+        let store = Store::with_limits(4, 4);
+        // "aGk=" and "dg==" are "hi" and "v", "dG9vIGxvbmc=" is "too long"
+        for (json, error) in [
+            (
+                r#"{"aGk=": "dg==", "dG9vIGxvbmc=": "dg=="}"#,
+                StoreError::KeyTooLong,
+            ),
+            (
+                r#"{"aGk=": "dg==", "aGV5": "dG9vIGxvbmc="}"#,
+                StoreError::ValueTooLong,
+            ),
+        ] {
+            assert_eq!(store.import_json(json), Err(error));
+        }
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn json_writer_round_trips_both_encodings() {
+        let store = init_store();
+        for encoding in [ByteEncoding::Base64, ByteEncoding::Utf8] {
+            let mut json = Vec::new();
+            store.to_json_writer(&mut json, encoding).unwrap();
+
+            let loaded = Store::from_json_reader(json.as_slice()).unwrap();
+            let (mut expected, mut actual) = (store.snapshot(), loaded.snapshot());
+            expected.sort();
+            actual.sort();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn utf8_encoding_writes_plain_strings() {
+        let store = init_store();
+        store.remove(Bytes::from("empty"));
+        store.remove(Bytes::from("hello"));
+        let mut json = Vec::new();
+        store.to_json_writer(&mut json, ByteEncoding::Utf8).unwrap();
+
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            r#"[{"key":{"base64":"/wA="},"value":"binary key"}]"#
+        );
+    }
+
+    #[test]
+    fn json_reader_rejects_bad_base64() {
+        let json = r#"[{"key":{"base64":"!!"},"value":""}]"#;
+        assert!(Store::from_json_reader(json.as_bytes()).is_err());
+    }
+}
@@ -1,60 +1,1364 @@
-#![feature(array_zip)]
-
 use std::{
-    collections::HashMap,
-    fmt::Debug,
-    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    error::Error,
+    fmt::{self, Display},
+    hash::BuildHasher,
+    io,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{Duration, Instant},
 };
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+
+#[cfg(feature = "tokio")]
+mod async_store;
+mod batch;
+mod bloom;
+mod bucket;
+mod builder;
+mod changelog;
+mod chunks;
+#[cfg(feature = "compression")]
+mod compression;
+mod crc;
+mod debug;
+mod disk;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod entry;
+mod evict;
+mod expiry;
+mod frozen;
+mod generic;
+mod history;
+mod index;
+#[cfg(feature = "serde")]
+mod json;
+#[cfg(feature = "compression")]
+mod lz4;
+mod merge;
+mod normalize;
+mod options;
+mod ordered;
+mod persist;
+#[cfg(feature = "resp")]
+pub mod resp;
+pub mod server;
+mod sharded;
+mod stats;
+mod timeout;
+mod txn;
+#[cfg(feature = "serde")]
+mod typed;
+mod version;
+mod view;
+mod wal;
+mod watch;
+
+#[cfg(feature = "tokio")]
+pub use async_store::AsyncStore;
+pub use batch::{Batch, BatchStats};
+use bloom::Bloom;
+pub use bloom::BloomStats;
+pub use bucket::{Bucket, Namespace};
+pub use builder::StoreBuilder;
+use changelog::Changelog;
+pub use changelog::{ChangeEvent, CHANGELOG_LEN};
+pub use chunks::SnapshotIter;
+#[cfg(feature = "compression")]
+pub use compression::{CompressedStore, CompressionError, CompressionKind, DEFAULT_THRESHOLD};
+pub use disk::DiskStore;
+#[cfg(feature = "encryption")]
+pub use encryption::EncryptedStore;
+pub use entry::Entry;
+use evict::Capacity;
+pub use evict::{EvictionPolicy, FifoPolicy, Limit, LruPolicy, RandomPolicy};
+use expiry::Expiry;
+pub use expiry::SweeperHandle;
+pub use frozen::FrozenStore;
+pub use generic::GenericStore;
+use history::History;
+use index::ValueIndex;
+#[cfg(feature = "serde")]
+pub use json::{ByteEncoding, StoreSnapshot};
+pub use merge::{MergeStats, MergeStrategy};
+pub use normalize::NormalizedStore;
+pub use options::StoreOptions;
+pub use ordered::OrderedStore;
+pub use persist::BackupSummary;
+pub use sharded::ShardedStore;
+use stats::Counters;
+pub use stats::StoreStats;
+pub use txn::Txn;
+#[cfg(feature = "serde")]
+pub use typed::{TypedError, TypedStore};
+pub use version::VersionError;
+use version::Versions;
+pub use view::View;
+pub use wal::{CompactionStats, RecoveryMode};
+use wal::{Op, Wal};
+pub use watch::WatchEvent;
+use watch::Watchers;
+
+pub type Records<S = RandomState> = HashMap<Bytes, Bytes, S>;
+
+// Deadlines of the records written with a TTL
+type Expiries = HashMap<Bytes, Instant>;
+
+type ReadGuardResult<'a, T> = Result<RwLockReadGuard<'a, T>, PoisonError<RwLockReadGuard<'a, T>>>;
+type WriteGuardResult<'a, T> =
+    Result<RwLockWriteGuard<'a, T>, PoisonError<RwLockWriteGuard<'a, T>>>;
+
+/// Errors surfaced by the fallible methods of a [`Store`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StoreError {
+    /// A thread panicked while holding the lock, so the records can't be trusted.
+    Poisoned,
+    /// The write-ahead log couldn't be appended to, so the write wasn't applied.
+    Log(io::ErrorKind),
+    /// A counter's existing value isn't an 8-byte big-endian integer.
+    NotAnInteger,
+    /// A counter would have overflowed an `i64`, so it was left unchanged.
+    Overflow,
+    /// A document passed to [`Store::import_json`] was malformed, for the
+    /// given reason.
+    Import(&'static str),
+    /// A value read through an `EncryptedStore`, from the `encryption`
+    /// feature, was tampered with or encrypted under another key.
+    Decryption,
+    /// A key was longer than [`StoreOptions::max_key_len`] allows.
+    KeyTooLong,
+    /// A value was longer than [`StoreOptions::max_value_len`] allows.
+    ValueTooLong,
+    /// A write would have grown the store past
+    /// [`StoreOptions::max_total_bytes`].
+    Full,
+    /// The lock couldn't be taken within the timeout given to one of the
+    /// `*_for` methods, such as [`Store::try_get_for`].
+    LockTimeout,
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Poisoned => write!(f, "store lock was poisoned by a panicking thread"),
+            Self::Log(kind) => write!(f, "failed to append to the write-ahead log: {kind}"),
+            Self::NotAnInteger => write!(f, "value is not an 8-byte big-endian integer"),
+            Self::Overflow => write!(f, "counter would overflow"),
+            Self::Import(reason) => write!(f, "invalid import document: {reason}"),
+            Self::Decryption => write!(f, "value failed to decrypt"),
+            Self::KeyTooLong => write!(f, "key is longer than the store accepts"),
+            Self::ValueTooLong => write!(f, "value is longer than the store accepts"),
+            Self::Full => write!(f, "store has no room for the write"),
+            Self::LockTimeout => write!(f, "timed out waiting for the store lock"),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        Self::Log(err.kind())
+    }
+}
+
+impl<T> From<PoisonError<T>> for StoreError {
+    fn from(_: PoisonError<T>) -> Self {
+        Self::Poisoned
+    }
+}
+
+/// Why a [`Store::try_compare_and_swap`] didn't swap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CasError {
+    /// The key held `current` rather than the expected value.
+    Mismatch { current: Option<Bytes> },
+    /// The store itself failed.
+    Store(StoreError),
+}
+
+impl Display for CasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mismatch { .. } => write!(f, "current value did not match the expected value"),
+            Self::Store(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl Error for CasError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Mismatch { .. } => None,
+            Self::Store(err) => Some(err),
+        }
+    }
+}
+
+impl From<StoreError> for CasError {
+    fn from(err: StoreError) -> Self {
+        Self::Store(err)
+    }
+}
+
+/// What a [`Store`] does after a thread panics while holding its lock, which
+/// may have left a write half done, e.g. with a [`Batch`] only partly applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PoisonPolicy {
+    /// Fail every later operation with [`StoreError::Poisoned`], until
+    /// [`Store::clear_poison`] is called.
+    #[default]
+    Propagate,
+    /// Carry on with the records exactly as the panicking thread left them.
+    Recover,
+    /// Remove every record, as logged removals that watchers see, then carry
+    /// on with an empty store.
+    Clear,
+}
+
+/// A key value store of `<bytes, bytes>` which allows you to store any valid
+/// string keys and values as bytes.
+///
+/// `Store` is a handle: cloning it is cheap and the clone shares the same
+/// underlying records, so a write through one handle is visible through every
+/// other. Use [`Store::deep_copy`] for an independent copy.
+pub struct Store<S = RandomState>(Arc<Shared<S>>);
+
+// Not derived, which would only clone stores whose hasher is `Clone`
+impl<S> Clone for Store<S> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+// Everything the handles of a store share
+#[derive(Debug)]
+struct Shared<S = RandomState> {
+    state: RwLock<State<S>>,
+    // The same counters as the state's, read without taking the lock
+    counters: Arc<Counters>,
+    // The same filter as the state's, read without taking the lock
+    bloom: Option<Arc<Bloom>>,
+    // The same options as the state's, which never change
+    options: StoreOptions,
+}
+
+// Everything guarded by the store's lock
+#[derive(Debug)]
+struct State<S = RandomState> {
+    records: Records<S>,
+    expiries: Expiries,
+    // The size of every record held, expired or not, as `approximate_size`
+    // reports it
+    size: usize,
+    versions: Versions,
+    wal: Option<Wal>,
+    capacity: Option<Capacity>,
+    watchers: Watchers,
+    // The latest changes, if the store keeps a changelog
+    changelog: Option<Changelog>,
+    // The earlier values of each key, if the store keeps them
+    history: Option<History>,
+    // Every key ever set, if the store keeps a filter of them
+    bloom: Option<Arc<Bloom>>,
+    index: Option<ValueIndex>,
+    // Hard limits on what a write may store
+    options: StoreOptions,
+    // How long a record written without a TTL of its own lives, if not forever
+    default_ttl: Option<Duration>,
+    // What to do with the records after a thread panicked holding the lock
+    poison: PoisonPolicy,
+    // Values being appended to, kept growable so that an append only copies
+    // what it adds. Their records are out of date until `settle` freezes them
+    // back in, which taking the lock for anything but an append does first
+    appends: HashMap<Bytes, BytesMut>,
+    // Counts every write of a key, whichever call made it
+    counters: Arc<Counters>,
+}
+
+impl<S: BuildHasher> State<S> {
+    fn new(records: Records<S>, expiries: Expiries) -> Self {
+        Self {
+            size: records.iter().map(|(k, v)| evict::entry_size(k, v)).sum(),
+            versions: Versions::new(&records),
+            records,
+            expiries,
+            wal: None,
+            capacity: None,
+            watchers: Watchers::new(),
+            changelog: None,
+            history: None,
+            bloom: None,
+            index: None,
+            options: StoreOptions::default(),
+            default_ttl: None,
+            poison: PoisonPolicy::default(),
+            appends: HashMap::new(),
+            counters: Arc::default(),
+        }
+    }
+
+    // Looks up a record, treating an expired one as absent
+    fn get(&self, k: &Bytes, now: Instant) -> Option<&Bytes> {
+        match self.is_expired(k, now) {
+            true => None,
+            false => self.records.get(k),
+        }
+    }
+
+    fn is_expired(&self, k: &Bytes, now: Instant) -> bool {
+        self.expiries
+            .get(k)
+            .is_some_and(|deadline| *deadline <= now)
+    }
 
-pub type Records = HashMap<Bytes, Bytes>;
+    // Counts the records that haven't expired yet
+    fn len(&self, now: Instant) -> usize {
+        let expired = self.expiries.values().filter(|d| **d <= now).count();
+        self.records.len() - expired
+    }
 
-type MutexGuardResult<'a, T> = Result<MutexGuard<'a, T>, PoisonError<MutexGuard<'a, T>>>;
+    // Logs and applies an insert, returning the live value it replaced
+    fn set(&mut self, k: Bytes, v: Bytes, expiry: Expiry) -> Result<Option<Bytes>, StoreError> {
+        self.apply_set(k, v, expiry, true)
+    }
 
-// A key value store of `<bytes, bytes>` which allows you to store any valid
-// string keys and values as bytes.
-pub struct Store(Arc<Mutex<Records>>);
+    // Like `set`, but only logs the insert if `log` is set
+    fn apply_set(
+        &mut self,
+        k: Bytes,
+        v: Bytes,
+        expiry: Expiry,
+        log: bool,
+    ) -> Result<Option<Bytes>, StoreError> {
+        self.settle_key(&k);
+        self.check_entry(&k, &v)?;
+        self.make_room(&k, &v)?;
+        self.check_set(&k, &v)?;
+        let now = Instant::now();
+        let deadline = self.deadline(expiry, now);
+        if let Some(wal) = self.wal.as_mut().filter(|_| log) {
+            let prev = wal::logged(&self.records, &self.expiries, &k);
+            wal.append(Op::Set(&k, &v, deadline.map(expiry::to_unix_millis)), prev)?;
+        }
+
+        let expired = self.is_expired(&k, now);
+        match deadline {
+            Some(deadline) => self.expiries.insert(k.clone(), deadline),
+            None => self.expiries.remove(&k),
+        };
+        self.track(&k, &v);
+        if let Some(bloom) = &self.bloom {
+            bloom.insert(&k);
+        }
+        self.size += evict::entry_size(&k, &v);
+        let prev = self.records.insert(k.clone(), v.clone());
+        if let Some(prev) = &prev {
+            self.size -= evict::entry_size(&k, prev);
+        }
+        self.reindex(&k, prev.as_ref(), Some(&v));
+        self.versions.bump(&k);
+        self.remember(&k, prev.as_ref().filter(|_| !expired));
+        self.changed(&k, WatchEvent::Set(v));
+        self.counters.record_set();
+        self.evict()?;
+        Ok(prev.filter(|_| !expired))
+    }
+
+    // Logs and applies a removal, returning the live value it removed
+    fn remove(&mut self, k: &Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.apply_remove(k, true)
+    }
+
+    // Like `remove`, but only logs the removal if `log` is set
+    fn apply_remove(&mut self, k: &Bytes, log: bool) -> Result<Option<Bytes>, StoreError> {
+        self.settle_key(k);
+        let now = Instant::now();
+        if let Some(wal) = self.wal.as_mut().filter(|_| log) {
+            let prev = wal::logged(&self.records, &self.expiries, k);
+            wal.append(Op::Remove(k), prev)?;
+        }
+
+        let expired = self.is_expired(k, now);
+        self.expiries.remove(k);
+        if let Some(capacity) = &mut self.capacity {
+            capacity.forget(k);
+        }
+        let prev = self.records.remove(k);
+        self.versions.forget(k);
+        if let Some(prev) = &prev {
+            self.size -= evict::entry_size(k, prev);
+            self.reindex(k, Some(prev), None);
+            if !expired {
+                self.remember(k, Some(prev));
+            }
+        }
+        if prev.is_some() {
+            self.changed(k, WatchEvent::Removed);
+        }
+        Ok(prev.filter(|_| !expired))
+    }
+
+    // Logs and applies every write in order, where a write without a value is
+    // a removal, returning the live value each one replaced. The writes are
+    // checked against the options and logged as a single record before any is
+    // applied, so a full store or a failing log applies none of them
+    fn write_all(
+        &mut self,
+        writes: Vec<(Bytes, Option<Bytes>)>,
+    ) -> Result<Vec<Option<Bytes>>, StoreError> {
+        self.settle();
+        self.check_writes(&writes)?;
+        let deadline = self.deadline(Expiry::Default, Instant::now());
+        if let Some(wal) = &mut self.wal {
+            let millis = deadline.map(expiry::to_unix_millis);
+            let ops: Vec<Op> = writes
+                .iter()
+                .map(|(k, v)| match v {
+                    Some(v) => Op::Set(k, v, millis),
+                    None => Op::Remove(k),
+                })
+                .collect();
+            wal.append_all(&ops, &self.records, &self.expiries)?;
+        }
+
+        let expiry = deadline.map_or(Expiry::Never, Expiry::At);
+        writes
+            .into_iter()
+            .map(|(k, v)| match v {
+                Some(v) => self.apply_set(k, v, expiry, false),
+                None => self.apply_remove(&k, false),
+            })
+            .collect()
+    }
+
+    // When a record written now with `expiry` expires, if ever
+    fn deadline(&self, expiry: Expiry, now: Instant) -> Option<Instant> {
+        match expiry {
+            // A default TTL too long to represent is as good as none
+            Expiry::Default => self.default_ttl.and_then(|ttl| now.checked_add(ttl)),
+            Expiry::Never => None,
+            Expiry::At(deadline) => Some(deadline),
+        }
+    }
+
+    // Appends `extra` to the value of `k`, keeping any TTL, and returns the
+    // new length. Unless something needs to see every new value, such as the
+    // write-ahead log or a watcher, the value is kept growable in `appends`
+    // so that later appends needn't copy it
+    fn append(&mut self, k: Bytes, extra: &[u8]) -> Result<usize, StoreError> {
+        let now = Instant::now();
+        let buffered = self.wal.is_none()
+            && self.watchers.is_empty()
+            && self.changelog.is_none()
+            && self.history.is_none()
+            && self.index.is_none();
+        if buffered && !self.is_expired(&k, now) {
+            if let Some(v) = self.appends.get(&k) {
+                let len = v.len() + extra.len();
+                self.options.check_lens(k.len(), len)?;
+                // Making room under the byte limit is left to `set`
+                let max = self.options.max_total_bytes;
+                if max.is_none_or(|max| self.size + extra.len() <= max) {
+                    self.appends.get_mut(&k).unwrap().extend_from_slice(extra);
+                    self.size += extra.len();
+                    self.track_size(&k, k.len() + len + evict::ENTRY_OVERHEAD);
+                    self.versions.bump(&k);
+                    self.counters.record_set();
+                    self.evict()?;
+                    return Ok(len);
+                }
+            }
+        }
+
+        self.settle_key(&k);
+        let current = self.get(&k, now).map_or(&[][..], |v| v.as_ref());
+        let mut v = BytesMut::with_capacity(current.len() + extra.len());
+        v.extend_from_slice(current);
+        v.extend_from_slice(extra);
+        let len = v.len();
+        let expiry = self.kept_expiry(&k, now);
+        if !buffered {
+            self.set(k, v.freeze(), expiry)?;
+            return Ok(len);
+        }
+        self.set(k.clone(), Bytes::copy_from_slice(&v), expiry)?;
+        // Eviction may have made room by dropping the key itself
+        if self.records.contains_key(&k) {
+            self.appends.insert(k, v);
+        }
+        Ok(len)
+    }
+
+    // Freezes every value being appended to back into its record
+    fn settle(&mut self) {
+        for (k, v) in self.appends.drain() {
+            self.records.insert(k, v.freeze());
+        }
+    }
+
+    fn settle_key(&mut self, k: &Bytes) {
+        if let Some((k, v)) = self.appends.remove_entry(k) {
+            self.records.insert(k, v.freeze());
+        }
+    }
+
+    // Logs and applies the removal of every record, returning the live ones
+    fn drain(&mut self) -> Result<Vec<(Bytes, Bytes)>, StoreError> {
+        let keys: Vec<Bytes> = self.records.keys().cloned().collect();
+        let mut drained = Vec::with_capacity(keys.len());
+        for k in keys {
+            if let Some(v) = self.remove(&k)? {
+                drained.push((k, v));
+            }
+        }
+        Ok(drained)
+    }
+
+    // Drops a record if it has expired, returning whether it had
+    fn purge(&mut self, k: &Bytes, now: Instant) -> Result<bool, StoreError> {
+        let expired = self.is_expired(k, now);
+        if expired {
+            self.remove(k)?;
+        }
+        Ok(expired)
+    }
+
+    fn compaction_due(&self) -> bool {
+        self.wal.as_ref().is_some_and(Wal::compaction_due)
+    }
+}
 
 impl Store {
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(HashMap::new())))
+        Self::from_records(HashMap::new())
+    }
+
+    /// Creates a store that keeps working after a thread panics while holding
+    /// its lock, instead of failing every later operation with
+    /// [`StoreError::Poisoned`]. Shorthand for
+    /// `Store::with_poison_policy(PoisonPolicy::Recover)`.
+    pub fn with_poison_recovery() -> Self {
+        Self::with_poison_policy(PoisonPolicy::Recover)
+    }
+
+    /// Creates a store that handles a thread panicking while holding its lock
+    /// as `policy` says.
+    pub fn with_poison_policy(policy: PoisonPolicy) -> Self {
+        Self::from_state(State {
+            poison: policy,
+            ..State::new(Records::new(), Expiries::new())
+        })
     }
 
+    /// Creates a store with room for `n` records before it needs to grow, so
+    /// that loading them doesn't rehash while holding the lock. Unlike
+    /// [`Store::with_capacity`], this doesn't limit how many it can hold.
+    pub fn preallocated(n: usize) -> Self {
+        Self::from_records(Records::with_capacity(n))
+    }
+
+    fn from_records(records: Records) -> Self {
+        Self::from_state(State::new(records, Expiries::new()))
+    }
+}
+
+impl<S: BuildHasher> Store<S> {
+    /// Creates a store whose records are hashed by `hasher` instead of the
+    /// default [`RandomState`], e.g. a faster one for keys from a trusted
+    /// source. The store lives only in memory, without the log, limits or
+    /// filter the other constructors set up.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::from_state(State::new(HashMap::with_hasher(hasher), Expiries::new()))
+    }
+
+    /// Marks the store as usable again after a thread panicked while holding
+    /// its lock, accepting whatever state that thread left the records in.
+    pub fn clear_poison(&self) {
+        self.0.state.clear_poison();
+    }
+
+    fn from_state(state: State<S>) -> Self {
+        Self(Arc::new(Shared {
+            bloom: state.bloom.clone(),
+            options: state.options,
+            counters: state.counters.clone(),
+            state: RwLock::new(state),
+        }))
+    }
+
+    /// Returns the value of `k`. The value isn't copied: `Bytes` is reference
+    /// counted, so the result shares the stored buffer, and cloning it is
+    /// O(1) whatever its length.
     pub fn get(&self, k: Bytes) -> Option<Bytes> {
-        self.try_run(&|guard| guard.get(&k).map(|byte| byte.clone()))
+        self.try_get(k).unwrap_or_default()
+    }
+
+    /// Inserts a value without a TTL of its own, returning the live value it
+    /// replaced. It never expires unless the store has a default TTL, as set
+    /// by [`StoreBuilder::default_ttl`], after which it expires like any other
+    /// record written this way. See [`Store::set_with_ttl`] for entries that
+    /// should expire.
+    pub fn set(&self, k: Bytes, v: Bytes) -> Option<Bytes> {
+        self.try_set(k, v).unwrap_or_default()
     }
 
-    pub fn set(&self, k: Bytes, v: Bytes) {
-        self.try_run(&|mut guard| guard.insert(k.to_owned(), v.to_owned()));
+    /// Removes a value, returning it if it was present. This takes the value
+    /// out under a single lock acquisition, so no other handle can read or
+    /// replace it in between.
+    pub fn remove(&self, k: Bytes) -> Option<Bytes> {
+        self.try_remove(k).unwrap_or_default()
     }
 
-    pub fn remove(&self, k: Bytes) {
-        self.try_run(&|mut guard| guard.remove(&k));
+    /// Returns the value of `k` as a cheap reference-counted handle onto the
+    /// stored buffer rather than a deep copy. This is what [`Store::get`]
+    /// returns too, spelled out for callers sharing large values.
+    pub fn get_shared(&self, k: &Bytes) -> Option<Bytes> {
+        self.get(k.clone())
     }
 
-    // Applies a closure to the Store if a lock is acquired.
-    // Used for setters and getters
-    fn try_run<V>(&self, callback: &dyn Fn(MutexGuard<Records>) -> Option<V>) -> Option<V> {
-        self.acquire().ok().and_then(callback)
+    /// Removes the value of `k` and returns it, in one locked operation, so no
+    /// other handle can read or replace it in between. Returns `None` if `k`
+    /// isn't present or the lock can't be taken.
+    pub fn take(&self, k: &Bytes) -> Option<Bytes> {
+        self.remove(k.clone())
     }
 
-    // Attempts to acquire a lock
-    fn acquire(&self) -> MutexGuardResult<Records> {
-        self.0.lock()
+    /// Inserts every entry under a single lock acquisition.
+    pub fn set_many(&self, entries: impl IntoIterator<Item = (Bytes, Bytes)>) {
+        // Collected up front so the caller's iterator doesn't run under the lock
+        let entries: Vec<_> = entries.into_iter().collect();
+        self.mutate(|state| {
+            entries
+                .into_iter()
+                .try_for_each(|(k, v)| state.set(k, v, Expiry::Default).map(drop))
+        })
+        .unwrap_or_default();
+    }
+
+    /// Looks up every key under a single lock acquisition. The result is
+    /// aligned with `keys`, with `None` for absent keys.
+    pub fn get_many(&self, keys: &[Bytes]) -> Vec<Option<Bytes>> {
+        let now = Instant::now();
+        self.read_state(|guard| keys.iter().map(|k| guard.get(k, now).cloned()).collect())
+            .unwrap_or_else(|_| vec![None; keys.len()])
+    }
+
+    /// Removes every key under a single lock acquisition. A key given more
+    /// than once is only removed, and logged, once.
+    pub fn remove_many(&self, keys: &[Bytes]) {
+        let keys: HashSet<&Bytes> = keys.iter().collect();
+        self.mutate(|state| keys.into_iter().try_for_each(|k| state.remove(k).map(drop)))
+            .unwrap_or_default();
+    }
+
+    /// Removes every entry under a single lock acquisition.
+    pub fn clear(&self) {
+        self.mutate(|state| state.drain().map(drop))
+            .unwrap_or_default();
+    }
+
+    /// Removes every live entry for which `f` returns `false` under a single
+    /// lock acquisition, returning how many were removed.
+    ///
+    /// `f` runs under the lock, so it must not call back into the store or it
+    /// will deadlock. Returns `0` if the lock can't be taken or a removal
+    /// can't be logged.
+    pub fn retain<F>(&self, mut f: F) -> usize
+    where
+        F: FnMut(&Bytes, &Bytes) -> bool,
+    {
+        self.mutate(|state| {
+            let now = Instant::now();
+            let keys: Vec<Bytes> = state
+                .records
+                .iter()
+                .filter(|(k, v)| !state.is_expired(k, now) && !f(k, v))
+                .map(|(k, _)| k.clone())
+                .collect();
+            let mut removed = 0;
+            for k in keys {
+                removed += state.remove(&k)?.is_some() as usize;
+            }
+            Ok(removed)
+        })
+        .unwrap_or_default()
+    }
+
+    /// Makes room for at least `additional` more records before the store next
+    /// needs to grow. Does nothing if the lock can't be taken.
+    pub fn reserve(&self, additional: usize) {
+        let _ = self.write_state(|state| state.records.reserve(additional));
+    }
+
+    /// Gives back as much memory as possible, e.g. after a [`Store::retain`]
+    /// or [`Store::clear`] dropped most records. Does nothing if the lock can't
+    /// be taken.
+    pub fn shrink_to_fit(&self) {
+        let _ = self.write_state(|state| {
+            state.records.shrink_to_fit();
+            state.expiries.shrink_to_fit();
+        });
+    }
+
+    /// How many records the store can hold before it next needs to grow.
+    /// This is room allocated up front, as by [`Store::reserve`], not the
+    /// eviction limit that [`Store::with_capacity`] sets. Returns `0` if the
+    /// lock can't be taken.
+    pub fn capacity(&self) -> usize {
+        self.read_state(|state| state.records.capacity())
+            .unwrap_or_default()
+    }
+
+    /// Atomically empties the store, returning the live entries it held.
+    pub fn drain(&self) -> Vec<(Bytes, Bytes)> {
+        self.mutate(State::drain).unwrap_or_default()
+    }
+
+    /// Returns every live entry whose key starts with `prefix`, in no
+    /// particular order. An empty prefix matches every key.
+    ///
+    /// This scans every record, so it costs O(n) in the size of the store
+    /// rather than in the number of matches.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Vec<(Bytes, Bytes)> {
+        let now = Instant::now();
+        self.read_state(|guard| {
+            guard
+                .records
+                .iter()
+                .filter(|(k, _)| k.starts_with(prefix) && !guard.is_expired(k, now))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Removes every entry whose key starts with `prefix` under a single lock
+    /// acquisition, returning how many live entries were removed.
+    pub fn remove_prefix(&self, prefix: &[u8]) -> usize {
+        self.mutate(|state| {
+            let keys: Vec<Bytes> = state
+                .records
+                .keys()
+                .filter(|k| k.starts_with(prefix))
+                .cloned()
+                .collect();
+            let mut removed = 0;
+            for k in keys {
+                removed += state.remove(&k)?.is_some() as usize;
+            }
+            Ok(removed)
+        })
+        .unwrap_or_default()
+    }
+
+    /// Calls `f` on the value of `k` without copying it out, returning what `f`
+    /// returns, or `None` if the key is absent or the lock can't be taken.
+    ///
+    /// `f` runs under a shared lock, so it should be quick and must not call
+    /// back into the store.
+    pub fn with_value<F, R>(&self, k: &Bytes, f: F) -> Option<R>
+    where
+        F: FnOnce(&Bytes) -> R,
+    {
+        let now = Instant::now();
+        self.read_state(|state| state.get(k, now).map(f))
+            .unwrap_or_default()
+    }
+
+    /// Returns `false` if the key is absent or the lock can't be taken.
+    pub fn contains_key(&self, k: &Bytes) -> bool {
+        if self.filtered_out(k) {
+            return false;
+        }
+        let now = Instant::now();
+        let found = self
+            .read_state(|guard| guard.get(k, now).is_some())
+            .unwrap_or_default();
+        self.filter_passed(found);
+        found
+    }
+
+    /// Returns `0` if the lock can't be taken.
+    pub fn len(&self) -> usize {
+        let now = Instant::now();
+        self.read_state(|guard| guard.len(now)).unwrap_or_default()
+    }
+
+    /// Returns `true` if the lock can't be taken.
+    pub fn is_empty(&self) -> bool {
+        let now = Instant::now();
+        self.read_state(|guard| guard.len(now) == 0).unwrap_or(true)
+    }
+
+    /// Copies out every live entry under a single lock acquisition, in no
+    /// particular order. Returns an empty vector if the lock can't be taken.
+    /// See [`Store::freeze`] for a copy that can be looked up by key, and
+    /// [`Store::snapshot_chunked`] to copy a large store a little at a time.
+    pub fn snapshot(&self) -> Vec<(Bytes, Bytes)> {
+        let now = Instant::now();
+        self.read_state(|guard| {
+            guard
+                .records
+                .iter()
+                .filter(|(k, _)| !guard.is_expired(k, now))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// The keys of a [`Store::snapshot`].
+    pub fn keys(&self) -> Vec<Bytes> {
+        self.snapshot().into_iter().map(|(k, _)| k).collect()
+    }
+
+    /// The values of a [`Store::snapshot`].
+    pub fn values(&self) -> Vec<Bytes> {
+        self.snapshot().into_iter().map(|(_, v)| v).collect()
+    }
+
+    /// Iterates over a [`Store::snapshot`]. The lock is released before the
+    /// first item is yielded, so the loop body is free to write to the store.
+    pub fn iter(&self) -> impl Iterator<Item = (Bytes, Bytes)> {
+        self.snapshot().into_iter()
+    }
+
+    /// Calls `f` on every live entry without copying them out, holding a shared
+    /// lock throughout.
+    ///
+    /// `f` runs under the lock, so it must not call back into the store.
+    pub fn for_each<F>(&self, mut f: F) -> Result<(), StoreError>
+    where
+        F: FnMut(&Bytes, &Bytes),
+    {
+        self.with_read(|view| view.iter().for_each(|(k, v)| f(k, v)))
+    }
+
+    /// Like [`Store::get`], but reports a poisoned lock instead of returning `None`.
+    ///
+    /// An expired entry reads as absent and is dropped from the store.
+    pub fn try_get(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.get_by(k, None)
+    }
+
+    // Looks up a value, giving up with `StoreError::LockTimeout` if the lock
+    // can't be taken by `deadline`
+    fn get_by(&self, k: Bytes, deadline: Option<Instant>) -> Result<Option<Bytes>, StoreError> {
+        if self.filtered_out(&k) {
+            self.0.counters.record_get(false);
+            return Ok(None);
+        }
+        let now = Instant::now();
+        let (value, expired, tracked) = self.read_state_by(deadline, |guard| {
+            let value = guard.get(&k, now).cloned();
+            (value, guard.is_expired(&k, now), guard.capacity.is_some())
+        })?;
+        // The read already succeeded, so failing to drop a stale entry or
+        // record the access only means a less accurate eviction order
+        if expired {
+            if let Ok(true) = self.mutate_by(deadline, |state| state.purge(&k, now)) {
+                self.0.counters.record_expired(1);
+            }
+        } else if tracked && value.is_some() {
+            let _ = self.write_state_by(deadline, |guard| guard.touch(&k));
+        }
+        self.filter_passed(value.is_some());
+        self.0.counters.record_get(value.is_some());
+        Ok(value)
+    }
+
+    /// Like [`Store::set`], but reports a poisoned lock or failed log write
+    /// instead of returning `None`. Any TTL the key had is replaced by the
+    /// store's default TTL, or cleared if it has none.
+    pub fn try_set(&self, k: Bytes, v: Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.set_by(k, v, None)
+    }
+
+    // Inserts a value, giving up with `StoreError::LockTimeout` if the lock
+    // can't be taken by `deadline`
+    fn set_by(
+        &self,
+        k: Bytes,
+        v: Bytes,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Bytes>, StoreError> {
+        self.0.options.check_entry(&k, &v)?;
+        self.mutate_by(deadline, |state| state.set(k, v, Expiry::Default))
+    }
+
+    /// Like [`Store::remove`], but reports a poisoned lock or failed log write
+    /// instead of returning `None`.
+    pub fn try_remove(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.remove_by(k, None)
+    }
+
+    // Removes a value, giving up with `StoreError::LockTimeout` if the lock
+    // can't be taken by `deadline`
+    fn remove_by(&self, k: Bytes, deadline: Option<Instant>) -> Result<Option<Bytes>, StoreError> {
+        let prev = self.mutate_by(deadline, |state| state.remove(&k))?;
+        self.0.counters.record_remove();
+        Ok(prev)
+    }
+
+    /// Atomically replaces the value of `k` with `f(current)`, or removes the
+    /// key if `f` returns `None`, and returns the new value. A TTL on the key
+    /// is kept.
+    ///
+    /// `f` runs under the lock, so it must not call back into the store or it
+    /// will deadlock.
+    pub fn update<F>(&self, k: Bytes, f: F) -> Option<Bytes>
+    where
+        F: FnOnce(Option<Bytes>) -> Option<Bytes>,
+    {
+        self.try_update(k, f).unwrap_or_default()
+    }
+
+    /// Like [`Store::update`], but reports a poisoned lock or failed log write.
+    pub fn try_update<F>(&self, k: Bytes, f: F) -> Result<Option<Bytes>, StoreError>
+    where
+        F: FnOnce(Option<Bytes>) -> Option<Bytes>,
+    {
+        self.mutate(|state| {
+            let now = Instant::now();
+            let new = f(state.get(&k, now).cloned());
+            match &new {
+                Some(v) => {
+                    let expiry = state.kept_expiry(&k, now);
+                    state.set(k, v.clone(), expiry)?;
+                }
+                None if state.records.contains_key(&k) => {
+                    state.remove(&k)?;
+                }
+                None => {}
+            }
+            Ok(new)
+        })
+    }
+
+    /// Atomically adds `delta` to the counter at `k`, returning its new value.
+    ///
+    /// Counters are stored as 8-byte big-endian integers, and an absent key
+    /// counts as `0`. A TTL on the key is kept.
+    pub fn increment(&self, k: Bytes, delta: i64) -> Result<i64, StoreError> {
+        self.mutate(|state| {
+            let now = Instant::now();
+            let current = match state.get(&k, now) {
+                Some(v) => i64::from_be_bytes(
+                    v.as_ref()
+                        .try_into()
+                        .map_err(|_| StoreError::NotAnInteger)?,
+                ),
+                None => 0,
+            };
+            let new = current.checked_add(delta).ok_or(StoreError::Overflow)?;
+            let expiry = state.kept_expiry(&k, now);
+            state.set(k, Bytes::copy_from_slice(&new.to_be_bytes()), expiry)?;
+            Ok(new)
+        })
+    }
+
+    /// Like [`Store::increment`], but subtracts `delta`.
+    pub fn decrement(&self, k: Bytes, delta: i64) -> Result<i64, StoreError> {
+        let delta = delta.checked_neg().ok_or(StoreError::Overflow)?;
+        self.increment(k, delta)
+    }
+
+    /// Atomically appends `extra` to the value of `k`, creating it if it is
+    /// absent, and returns the new length. A TTL on the key is kept.
+    ///
+    /// Appending again before anything else takes the lock only copies
+    /// `extra`, so a run of appends takes time in proportion to what they
+    /// add. The first append after the value was read copies it once. A store
+    /// with a write-ahead log, watchers, a changelog, history or a value
+    /// index copies the value on every append, since each one hands out the
+    /// new value. Returns `0` if the lock can't be taken, the write can't be
+    /// logged or the value would break the store's limits.
+    pub fn append(&self, k: Bytes, extra: &[u8]) -> usize {
+        // Unlike every other write, this leaves earlier appends unsettled
+        let Ok(mut guard) = self.write_unsettled() else {
+            return 0;
+        };
+        let len = guard.append(k, extra).unwrap_or_default();
+        let due = guard.compaction_due();
+        drop(guard);
+        if due {
+            let _ = self.compact();
+        }
+        len
+    }
+
+    /// Atomically shortens the value of `k` to its first `len` bytes, keeping
+    /// any TTL. Does nothing if the key is absent or the value is no longer
+    /// than `len`.
+    pub fn truncate_value(&self, k: Bytes, len: usize) {
+        let _ = self.mutate(|state| {
+            let now = Instant::now();
+            let Some(v) = state.get(&k, now).filter(|v| v.len() > len) else {
+                return Ok(());
+            };
+            let v = v.slice(..len);
+            let expiry = state.kept_expiry(&k, now);
+            state.set(k, v, expiry).map(drop)
+        });
+    }
+
+    /// Returns the value of `k`, first setting it to `f()` if it is absent.
+    /// Racing callers on the same missing key see a single call to `f`.
+    ///
+    /// `f` runs under the lock, so it must not call back into the store. If the
+    /// lock is poisoned or `f()` can't be stored, because it is over a limit
+    /// or can't be logged, `f()` is still returned but the store is left
+    /// without it. Use [`Store::try_get_or_insert_with`] to tell the two apart.
+    pub fn get_or_insert_with<F>(&self, k: Bytes, f: F) -> Bytes
+    where
+        F: FnOnce() -> Bytes,
+    {
+        let mut f = Some(f);
+        let mut made = None;
+        self.try_get_or_insert_with(k, || {
+            made.insert(f.take().map_or_else(Bytes::new, |f| f()))
+                .clone()
+        })
+        .unwrap_or_else(|_| made.or_else(|| f.map(|f| f())).unwrap_or_default())
+    }
+
+    /// Like [`Store::get_or_insert_with`], but reports why `f()` couldn't be
+    /// stored instead of returning it anyway.
+    pub fn try_get_or_insert_with<F>(&self, k: Bytes, f: F) -> Result<Bytes, StoreError>
+    where
+        F: FnOnce() -> Bytes,
+    {
+        self.mutate(|state| {
+            if let Some(v) = state.get(&k, Instant::now()) {
+                return Ok(v.clone());
+            }
+            let v = f();
+            state.set(k, v.clone(), Expiry::Default)?;
+            Ok(v)
+        })
+    }
+
+    /// Inserts `v` only if `k` is absent, under a single lock acquisition, and
+    /// returns whether it did. A live value is left untouched, while an
+    /// expired one counts as absent. Returns `false` if the lock can't be
+    /// taken or the write can't be logged.
+    pub fn set_if_absent(&self, k: Bytes, v: Bytes) -> bool {
+        self.try_set_if_absent(k, v).unwrap_or_default()
+    }
+
+    /// Like [`Store::set_if_absent`], but reports a poisoned lock or failed
+    /// write instead of returning `false`.
+    pub fn try_set_if_absent(&self, k: Bytes, v: Bytes) -> Result<bool, StoreError> {
+        self.mutate(|state| {
+            if state.get(&k, Instant::now()).is_some() {
+                return Ok(false);
+            }
+            state.set(k, v, Expiry::Default)?;
+            Ok(true)
+        })
+    }
+
+    /// Atomically sets `k` to `new` (removing it if `None`) only if its current
+    /// value equals `expected`, where `None` means the key is absent. Returns
+    /// whether the swap happened.
+    ///
+    /// A successful swap behaves like `set`/`remove`, so it replaces any TTL
+    /// with the store's default TTL, or clears it if there is none.
+    pub fn compare_and_swap(&self, k: Bytes, expected: Option<Bytes>, new: Option<Bytes>) -> bool {
+        self.try_compare_and_swap(k, expected, new).is_ok()
+    }
+
+    /// Like [`Store::compare_and_swap`], but on a mismatch returns the value
+    /// actually found, so callers can retry without another `get`.
+    pub fn try_compare_and_swap(
+        &self,
+        k: Bytes,
+        expected: Option<Bytes>,
+        new: Option<Bytes>,
+    ) -> Result<(), CasError> {
+        self.mutate(|state| {
+            let current = state.get(&k, Instant::now());
+            if current != expected.as_ref() {
+                return Ok(Err(CasError::Mismatch {
+                    current: current.cloned(),
+                }));
+            }
+            match new {
+                Some(v) => state.set(k, v, Expiry::Default)?,
+                None => state.remove(&k)?,
+            };
+            Ok(Ok(()))
+        })?
+    }
+
+    // Runs `f` under a shared lock, surfacing a poisoned lock as an error. Used
+    // for getters
+    fn read_state<R>(&self, f: impl FnOnce(&State<S>) -> R) -> Result<R, StoreError> {
+        self.read_state_by(None, f)
+    }
+
+    fn read_state_by<R>(
+        &self,
+        deadline: Option<Instant>,
+        f: impl FnOnce(&State<S>) -> R,
+    ) -> Result<R, StoreError> {
+        let guard = self.read_by(deadline)?;
+        Ok(f(&guard))
+    }
+
+    // Runs `f` under an exclusive lock, surfacing a poisoned lock as an error.
+    // Used for setters
+    fn write_state<R>(&self, f: impl FnOnce(&mut State<S>) -> R) -> Result<R, StoreError> {
+        self.write_state_by(None, f)
+    }
+
+    fn write_state_by<R>(
+        &self,
+        deadline: Option<Instant>,
+        f: impl FnOnce(&mut State<S>) -> R,
+    ) -> Result<R, StoreError> {
+        let mut guard = self.write_by(deadline)?;
+        Ok(f(&mut guard))
+    }
+
+    // Applies a mutation under the lock, then runs any log compaction it made
+    // due once the lock is released
+    fn mutate<V>(
+        &self,
+        op: impl FnOnce(&mut State<S>) -> Result<V, StoreError>,
+    ) -> Result<V, StoreError> {
+        self.mutate_by(None, op)
+    }
+
+    fn mutate_by<V>(
+        &self,
+        deadline: Option<Instant>,
+        op: impl FnOnce(&mut State<S>) -> Result<V, StoreError>,
+    ) -> Result<V, StoreError> {
+        let (result, due) = self.write_state_by(deadline, |guard| {
+            let result = op(guard)?;
+            Ok::<_, StoreError>((result, guard.compaction_due()))
+        })??;
+        if due {
+            // The write itself has landed, so a failed compaction only means
+            // the log stays larger than it needs to be
+            let _ = self.compact();
+        }
+        Ok(result)
+    }
+
+    // Attempts to acquire a shared lock, handling a panicked thread as the
+    // store's poison policy says
+    fn read(&self) -> ReadGuardResult<'_, State<S>> {
+        loop {
+            let guard = self.0.state.read().or_else(|err| self.recover_read(err))?;
+            if guard.appends.is_empty() {
+                return Ok(guard);
+            }
+            // Settling appends needs the exclusive lock
+            drop(guard);
+            drop(self.write());
+        }
+    }
+
+    fn recover_read<'a>(
+        &'a self,
+        err: PoisonError<RwLockReadGuard<'a, State<S>>>,
+    ) -> ReadGuardResult<'a, State<S>> {
+        match err.get_ref().poison {
+            PoisonPolicy::Propagate => Err(err),
+            PoisonPolicy::Recover => Ok(err.into_inner()),
+            PoisonPolicy::Clear => {
+                // Clearing needs the exclusive lock
+                drop(err);
+                drop(self.write());
+                self.0.state.read()
+            }
+        }
+    }
+
+    // Attempts to acquire an exclusive lock, handling a panicked thread as
+    // the store's poison policy says
+    fn write(&self) -> WriteGuardResult<'_, State<S>> {
+        let mut guard = self.write_unsettled()?;
+        guard.settle();
+        Ok(guard)
+    }
+
+    // Like `write`, but leaves any appends unsettled
+    fn write_unsettled(&self) -> WriteGuardResult<'_, State<S>> {
+        self.0.state.write().or_else(|err| self.recover_write(err))
+    }
+
+    fn recover_write<'a>(
+        &'a self,
+        err: PoisonError<RwLockWriteGuard<'a, State<S>>>,
+    ) -> WriteGuardResult<'a, State<S>> {
+        match err.get_ref().poison {
+            PoisonPolicy::Propagate => Err(err),
+            PoisonPolicy::Recover => Ok(err.into_inner()),
+            PoisonPolicy::Clear => {
+                let mut guard = err.into_inner();
+                let _ = guard.drain();
+                self.0.state.clear_poison();
+                Ok(guard)
+            }
+        }
+    }
+}
+
+impl Store {
+    /// Copies the live records and their TTLs into an independent store under
+    /// a single lock acquisition. Unlike `clone`, writes to either store are
+    /// not seen by the other, which makes the copy a consistent point-in-time
+    /// checkpoint, e.g. to back up while writers carry on.
+    ///
+    /// The copy lives only in memory: it has no write-ahead log, capacity
+    /// limit, Bloom filter, or watchers, whatever this store has.
+    pub fn deep_copy(&self) -> Store {
+        let now = Instant::now();
+        self.read_state(|guard| {
+            let records: Records = guard
+                .records
+                .iter()
+                .filter(|(k, _)| !guard.is_expired(k, now))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            let expiries = guard
+                .expiries
+                .iter()
+                .filter(|(_, deadline)| **deadline > now)
+                .map(|(k, deadline)| (k.clone(), *deadline))
+                .collect();
+            Store::from_state(State::new(records, expiries))
+        })
+        .unwrap_or_default()
+    }
+
+    /// Turns the store into its live records. They are moved out if this is
+    /// the last handle to the store, and copied as by [`Store::snapshot`]
+    /// otherwise. Returns no records if the lock is poisoned, unless the store
+    /// recovers from poisoning.
+    pub fn into_records(self) -> Records {
+        let shared = match Arc::try_unwrap(self.0) {
+            Ok(shared) => shared,
+            Err(shared) => return Self(shared).snapshot().into_iter().collect(),
+        };
+        let mut state = match shared.state.into_inner() {
+            Ok(state) => state,
+            Err(err) if err.get_ref().poison == PoisonPolicy::Recover => err.into_inner(),
+            Err(_) => return Records::new(),
+        };
+
+        state.settle();
+        let now = Instant::now();
+        let expiries = state.expiries;
+        state
+            .records
+            .retain(|k, _| expiries.get(k).is_none_or(|deadline| *deadline > now));
+        state.records
+    }
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl Debug for Store {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.0)
+impl From<Records> for Store {
+    fn from(records: Records) -> Self {
+        Self::from_records(records)
+    }
+}
+
+/// Collects pairs of anything that converts into [`Bytes`], such as
+/// `(&'static str, &'static str)` or `(String, Vec<u8>)`.
+impl<K, V> FromIterator<(K, V)> for Store
+where
+    K: Into<Bytes>,
+    V: Into<Bytes>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> Self {
+        Self::from_records(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        )
+    }
+}
+
+/// Inserts every entry under a single lock acquisition, as
+/// [`Store::set_many`] does.
+impl<K, V> Extend<(K, V)> for Store
+where
+    K: Into<Bytes>,
+    V: Into<Bytes>,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) {
+        self.set_many(entries.into_iter().map(|(k, v)| (k.into(), v.into())));
+    }
+}
+
+/// Yields the live records, in no particular order, as
+/// [`Store::into_records`] returns them.
+impl IntoIterator for Store {
+    type Item = (Bytes, Bytes);
+    type IntoIter = std::collections::hash_map::IntoIter<Bytes, Bytes>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_records().into_iter()
+    }
+}
+
+/// Two stores are equal if they hold the same live records, regardless of
+/// TTLs, logs or limits. A store whose lock can't be taken equals only its own
+/// handles.
+impl PartialEq for Store {
+    fn eq(&self, other: &Self) -> bool {
+        if Arc::ptr_eq(&self.0, &other.0) {
+            return true;
+        }
+
+        // Lock in address order, so comparing `a == b` and `b == a` at once
+        // can't deadlock behind a waiting writer
+        let (first, second) = match Arc::as_ptr(&self.0) < Arc::as_ptr(&other.0) {
+            true => (self, other),
+            false => (other, self),
+        };
+        let (Ok(first), Ok(second)) = (first.read(), second.read()) else {
+            return false;
+        };
+
+        let now = Instant::now();
+        first.len(now) == second.len(now)
+            && first
+                .records
+                .iter()
+                .filter(|(k, _)| !first.is_expired(k, now))
+                .all(|(k, v)| second.get(k, now) == Some(v))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Store;
+    use super::{CasError, PoisonPolicy, Store, StoreError, WatchEvent};
     use bytes::Bytes;
+    use std::{
+        collections::HashSet,
+        hash::{BuildHasherDefault, Hasher},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
 
     const KEYS: [&str; 5] = ["hello1", "hello2", "hello3", "hello4", "hello5"];
     const VALS: [&str; 5] = ["world1", "world2", "world3", "world4", "world5"];
@@ -87,11 +1391,1014 @@ mod tests {
         assert_eq!(results, expected);
     }
 
-    fn init_store() -> Store {
+    #[test]
+    fn take_returns_the_value_and_removes_it() {
+        let store = init_store();
+        let k = Bytes::from("hello1");
+        let v = store.get_shared(&k).unwrap();
+        assert_eq!(v.as_ptr(), store.get(k.clone()).unwrap().as_ptr());
+
+        assert_eq!(store.take(&k), Some(v));
+        assert!(!store.contains_key(&k));
+        assert_eq!(store.take(&k), None);
+        assert_eq!(store.len(), 4);
+    }
+
+    // FNV-1a, which hashes the same way in every process
+    struct Fnv(u64);
+
+    impl Default for Fnv {
+        fn default() -> Self {
+            Self(0xcbf29ce484222325)
+        }
+    }
+
+    impl Hasher for Fnv {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for b in bytes {
+                self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+
+    #[test]
+    fn stores_with_a_custom_hasher() {
+        let store = Store::with_hasher(BuildHasherDefault::<Fnv>::default());
+        for (k, v) in KEYS.into_iter().zip(VALS) {
+            assert_eq!(store.set(Bytes::from(k), Bytes::from(v)), None);
+        }
+
+        assert_eq!(store.len(), 5);
+        assert_eq!(
+            store.get(Bytes::from("hello2")),
+            Some(Bytes::from("world2"))
+        );
+        assert_eq!(
+            store.set(Bytes::from("hello2"), Bytes::from("again")),
+            Some(Bytes::from("world2"))
+        );
+        assert_eq!(
+            store.remove(Bytes::from("hello3")),
+            Some(Bytes::from("world3"))
+        );
+        assert_eq!(store.get(Bytes::from("hello3")), None);
+        assert_eq!(store.len(), 4);
+
+        let handle = store.clone();
+        handle.clear();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn len_and_contains_key() {
+        let store = init_store();
+
+        assert_eq!(store.len(), 5);
+        assert!(!store.is_empty());
+        assert!(store.contains_key(&Bytes::from("hello3")));
+        assert!(!store.contains_key(&Bytes::from("hello6")));
+        assert!(Store::new().is_empty());
+    }
+
+    #[test]
+    fn clones_share_records() {
+        let store = Store::default();
+        let handle = store.clone();
+
+        handle.set(Bytes::from("hello"), Bytes::from("world"));
+        assert_eq!(store.get(Bytes::from("hello")), Some(Bytes::from("world")));
+
+        let worker = store.clone();
+        thread::spawn(move || worker.remove(Bytes::from("hello")))
+            .join()
+            .unwrap();
+        assert!(handle.is_empty());
+    }
+
+    #[test]
+    fn deep_copy_is_independent() {
+        let store = init_store();
+        store.set_with_ttl(
+            Bytes::from("ttl"),
+            Bytes::from("v"),
+            Duration::from_secs(60),
+        );
+        let copy = store.deep_copy();
+
+        store.set(Bytes::from("hello1"), Bytes::from("changed"));
+        store.remove(Bytes::from("hello2"));
+        copy.set(Bytes::from("copy-only"), Bytes::from("v"));
+
+        assert_eq!(copy.get(Bytes::from("hello1")), Some(Bytes::from("world1")));
+        assert!(copy.contains_key(&Bytes::from("hello2")));
+        assert!(copy.ttl(&Bytes::from("ttl")).is_some());
+        assert!(!store.contains_key(&Bytes::from("copy-only")));
+    }
+
+    #[test]
+    fn build_from_collections() {
+        let entries = || {
+            KEYS.iter()
+                .zip(VALS.iter())
+                .map(|(k, v)| (Bytes::from(*k), Bytes::from(*v)))
+        };
+        let from_map = Store::from(entries().collect::<super::Records>());
+        let collected: Store = entries().collect();
+
+        assert_eq!(from_map, init_store());
+        assert_eq!(collected, init_store());
+    }
+
+    #[test]
+    fn into_iter_moves_or_copies() {
+        let mut expected = init_store().snapshot();
+        expected.sort();
+
+        let store = init_store();
+        store.set_with_ttl(
+            Bytes::from("gone"),
+            Bytes::from("v"),
+            Duration::from_millis(10),
+        );
+        thread::sleep(Duration::from_millis(20));
+        let mut owned: Vec<_> = store.into_iter().collect();
+        owned.sort();
+        assert_eq!(owned, expected);
+
+        let store = init_store();
+        let handle = store.clone();
+        let mut shared: Vec<_> = store.into_iter().collect();
+        shared.sort();
+        assert_eq!(shared, expected);
+        assert_eq!(handle.len(), KEYS.len());
+    }
+
+    #[test]
+    fn collect_and_extend_in_bulk() {
+        let collected: Store = (0..10_000)
+            .map(|i| (format!("k{i}"), i.to_string()))
+            .collect();
+        assert_eq!(collected.len(), 10_000);
+        assert_eq!(collected.get(Bytes::from("k42")), Some(Bytes::from("42")));
+
+        // Readers never see the extension half applied
+        let mut store = Store::new();
+        let reader = {
+            let store = store.clone();
+            thread::spawn(move || loop {
+                match store.len() {
+                    0 => continue,
+                    n => break n,
+                }
+            })
+        };
+        store.extend(collected.into_records());
+        assert_eq!(reader.join().unwrap(), 10_000);
+    }
+
+    #[test]
+    fn into_records_keeps_live_records() {
+        let store = init_store();
+        store.set_with_ttl(Bytes::from("gone"), Bytes::from("v"), Duration::ZERO);
+        let handle = store.clone();
+
+        let records = store.into_records();
+        assert_eq!(records.len(), KEYS.len());
+        assert_eq!(handle.into_records(), records);
+    }
+
+    #[test]
+    fn preallocate_reserve_and_shrink() {
+        let store = Store::preallocated(1000);
+        assert!(store.capacity() >= 1000);
+
+        store.set_many((0..5000).map(|i| (Bytes::from(format!("k{i}")), Bytes::new())));
+        store.reserve(10_000);
+        assert!(store.capacity() >= 15_000);
+
+        store.retain(|k, _| k.as_ref() == b"k0");
+        let before = store.capacity();
+        store.shrink_to_fit();
+        assert!(store.capacity() < before);
+        assert_eq!(store.get(Bytes::from("k0")), Some(Bytes::new()));
+    }
+
+    #[test]
+    fn stores_compare_by_contents() {
+        let (store, other) = (Store::new(), Store::new());
+        for (k, v) in KEYS.iter().zip(VALS.iter()) {
+            store.set(Bytes::from(*k), Bytes::from(*v));
+        }
+        for (k, v) in KEYS.iter().zip(VALS.iter()).rev() {
+            other.set(Bytes::from(*k), Bytes::from(*v));
+        }
+        assert_eq!(store, other);
+        assert_eq!(store, store.clone());
+
+        other.remove(Bytes::from(KEYS[0]));
+        assert_ne!(store, other);
+        assert_ne!(other, store);
+    }
+
+    #[test]
+    fn batch_set_and_get() {
+        let store = Store::new();
+        store.set_many(
+            KEYS.iter()
+                .zip(VALS.iter())
+                .map(|(k, v)| (Bytes::from(*k), Bytes::from(*v))),
+        );
+
+        let keys: Vec<Bytes> = KEYS.iter().map(|k| Bytes::from(*k)).collect();
+        let expected: Vec<Option<Bytes>> = VALS.iter().map(|v| Some(Bytes::from(*v))).collect();
+        assert_eq!(store.get_many(&keys), expected);
+
+        store.remove_many(&keys[..2]);
+        let results = store.get_many(&[keys[0].clone(), Bytes::from("missing"), keys[4].clone()]);
+        assert_eq!(results, [None, None, Some(Bytes::from("world5"))]);
+    }
+
+    #[test]
+    fn update_is_atomic() {
+        let store = init_store();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        store.update(Bytes::from("hello1"), |v| {
+                            let mut v = v.unwrap().to_vec();
+                            v.push(b'!');
+                            Some(Bytes::from(v))
+                        });
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        let value = store.get(Bytes::from("hello1")).unwrap();
+        assert_eq!(value.len(), "world1".len() + 800);
+
+        store.update(Bytes::from("hello2"), |_| None);
+        assert!(!store.contains_key(&Bytes::from("hello2")));
+        let new = store.update(Bytes::from("fresh"), |v| {
+            assert_eq!(v, None);
+            Some(Bytes::from("value"))
+        });
+        assert_eq!(new, Some(Bytes::from("value")));
+        assert_eq!(store.get(Bytes::from("fresh")), Some(Bytes::from("value")));
+    }
+
+    #[test]
+    fn update_counts_without_lost_increments() {
         let store = Store::new();
-        KEYS.zip(VALS) // Populate the store
+        let increment = |v: Option<Bytes>| {
+            let n: u64 = v.map_or(0, |v| std::str::from_utf8(&v).unwrap().parse().unwrap());
+            Some(Bytes::from((n + 1).to_string()))
+        };
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        store.update(Bytes::from("counter"), increment);
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        let last = store.update(Bytes::from("counter"), increment);
+        assert_eq!(last, Some(Bytes::from("801")));
+    }
+
+    #[test]
+    fn set_if_absent() {
+        let store = Store::new();
+        assert!(store.set_if_absent(Bytes::from("k"), Bytes::from("first")));
+        assert!(!store.set_if_absent(Bytes::from("k"), Bytes::from("second")));
+        assert_eq!(store.get(Bytes::from("k")), Some(Bytes::from("first")));
+
+        store.set_with_ttl(Bytes::from("k"), Bytes::from("v"), Duration::ZERO);
+        assert_eq!(
+            store.try_set_if_absent(Bytes::from("k"), Bytes::from("fresh")),
+            Ok(true)
+        );
+        assert_eq!(store.ttl(&Bytes::from("k")), None);
+    }
+
+    #[test]
+    fn get_or_insert_with_fills_once() {
+        let store = Store::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (store, calls) = (store.clone(), calls.clone());
+                thread::spawn(move || {
+                    (0..10)
+                        .map(|_| {
+                            store.get_or_insert_with(Bytes::from("k"), || {
+                                calls.fetch_add(1, Ordering::SeqCst);
+                                Bytes::from("computed")
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert!(handle
+                .join()
+                .unwrap()
+                .iter()
+                .all(|v| v == &Bytes::from("computed")));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn get_or_insert_with_reports_failed_writes() {
+        let store = Store::with_limits(8, 4);
+        let k = Bytes::from("k");
+        assert_eq!(
+            store.try_get_or_insert_with(k.clone(), || Bytes::from("too long")),
+            Err(StoreError::ValueTooLong)
+        );
+        assert_eq!(
+            store.get_or_insert_with(k.clone(), || Bytes::from("too long")),
+            Bytes::from("too long")
+        );
+        assert!(!store.contains_key(&k));
+
+        assert_eq!(
+            store.try_get_or_insert_with(k.clone(), || Bytes::from("fits")),
+            Ok(Bytes::from("fits"))
+        );
+        assert_eq!(store.get(k), Some(Bytes::from("fits")));
+    }
+
+    #[test]
+    fn increment_counts_from_absent() {
+        let store = Store::new();
+        let k = Bytes::from("hits");
+
+        assert_eq!(store.increment(k.clone(), 1), Ok(1));
+        assert_eq!(store.increment(k.clone(), 5), Ok(6));
+        assert_eq!(store.decrement(k.clone(), 10), Ok(-4));
+        assert_eq!(
+            store.get(k.clone()),
+            Some(Bytes::copy_from_slice(&(-4i64).to_be_bytes()))
+        );
+
+        store.set(k.clone(), Bytes::copy_from_slice(&i64::MAX.to_be_bytes()));
+        assert_eq!(store.increment(k.clone(), 1), Err(StoreError::Overflow));
+        assert_eq!(store.decrement(k, i64::MIN), Err(StoreError::Overflow));
+    }
+
+    #[test]
+    fn increment_is_atomic_across_threads() {
+        let store = Store::new();
+        let k = Bytes::from("hits");
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (store, k) = (store.clone(), k.clone());
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        store.increment(k.clone(), 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.increment(k, 0), Ok(80_000));
+    }
+
+    #[test]
+    fn increment_rejects_malformed_value() {
+        let store = init_store();
+        assert_eq!(
+            store.increment(Bytes::from("hello1"), 1),
+            Err(StoreError::NotAnInteger)
+        );
+        assert_eq!(
+            store.get(Bytes::from("hello1")),
+            Some(Bytes::from("world1"))
+        );
+    }
+
+    #[test]
+    fn append_and_truncate() {
+        let store = Store::new();
+        let k = Bytes::from("log");
+        store.set_with_ttl(k.clone(), Bytes::from("ab"), Duration::from_secs(60));
+
+        assert_eq!(store.append(k.clone(), b"cd"), 4);
+        assert_eq!(store.append(Bytes::from("new"), b"x"), 1);
+        store.truncate_value(k.clone(), 3);
+        store.truncate_value(k.clone(), 10);
+        store.truncate_value(Bytes::from("missing"), 0);
+
+        assert_eq!(store.get(k.clone()), Some(Bytes::from("abc")));
+        assert!(store.ttl(&k).is_some());
+        assert!(!store.contains_key(&Bytes::from("missing")));
+    }
+
+    #[test]
+    fn appends_concatenate_chunks() {
+        let store = Store::new();
+        let k = Bytes::from("log");
+        for chunk in ["one,", "two,", "three"] {
+            store.append(k.clone(), chunk.as_bytes());
+        }
+        assert_eq!(store.get(k), Some(Bytes::from("one,two,three")));
+    }
+
+    #[test]
+    fn appends_grow_a_buffer_until_read() {
+        let store = Store::with_limits(8, 4096);
+        let k = Bytes::from("log");
+        store.set_with_ttl(k.clone(), Bytes::from("start:"), Duration::from_secs(60));
+        let mut allocations = HashSet::new();
+        for _ in 0..1000 {
+            store.append(k.clone(), b"ab");
+            let state = store.0.state.read().unwrap();
+            allocations.insert(state.appends[&k].as_ptr());
+        }
+        // Each append writes into spare room, so only a doubling reallocates
+        assert!(allocations.len() < 20, "{} allocations", allocations.len());
+
+        // Too long a value is refused without changing what was appended
+        assert_eq!(store.append(k.clone(), &[b'x'; 4096]), 0);
+        assert_eq!(store.byte_len(), 3 + 2006);
+        let v = store.get(k.clone()).unwrap();
+        assert_eq!(v.len(), 2006);
+        assert!(v.starts_with(b"start:abab"));
+        assert!(store.0.state.read().unwrap().appends.is_empty());
+        assert!(store.ttl(&k).is_some());
+
+        // The first append after a read starts a new buffer
+        assert_eq!(store.append(k.clone(), b"!"), 2007);
+        assert_eq!(store.append(Bytes::from("short"), b"xy"), 2);
+        assert_eq!(store.append(Bytes::from("short"), b"z"), 3);
+        assert!(format!("{store:?}").contains("xyz"));
+        let records = store.into_records();
+        assert!(records[&k].ends_with(b"ab!"));
+    }
+
+    #[test]
+    fn appended_values_can_be_evicted() {
+        let store = Store::with_capacity(1);
+        for k in ["a", "a", "b", "b"] {
+            store.append(Bytes::from(k), k.as_bytes());
+        }
+        assert_eq!(store.get(Bytes::from("a")), None);
+        assert_eq!(store.get(Bytes::from("b")), Some(Bytes::from("bb")));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn appends_to_a_watched_key_are_each_seen() {
+        let store = Store::new();
+        let k = Bytes::from("log");
+        let events = store.watch(k.clone());
+        for chunk in ["a", "b", "c"] {
+            store.append(k.clone(), chunk.as_bytes());
+        }
+        for expected in ["a", "ab", "abc"] {
+            assert_eq!(events.recv(), Ok(WatchEvent::Set(Bytes::from(expected))));
+        }
+    }
+
+    #[test]
+    fn concurrent_appends_are_not_lost() {
+        let store = Store::new();
+        let k = Bytes::from("log");
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let (store, k) = (store.clone(), k.clone());
+                thread::spawn(move || {
+                    for n in 0..100 {
+                        store.append(k.clone(), format!("[{t}:{n}]").as_bytes());
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let log = String::from_utf8(store.get(k).unwrap().to_vec()).unwrap();
+        for t in 0..8 {
+            for n in 0..100 {
+                assert_eq!(log.matches(&format!("[{t}:{n}]")).count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let store = init_store();
+        let (k, old, new) = (
+            Bytes::from("hello1"),
+            Bytes::from("world1"),
+            Bytes::from("new"),
+        );
+
+        assert!(store.compare_and_swap(k.clone(), Some(old.clone()), Some(new.clone())));
+        assert_eq!(store.get(k.clone()), Some(new.clone()));
+
+        assert!(!store.compare_and_swap(k.clone(), Some(old), None));
+        assert_eq!(store.get(k.clone()), Some(new.clone()));
+
+        assert!(store.compare_and_swap(k.clone(), Some(new), None));
+        assert!(!store.contains_key(&k));
+    }
+
+    #[test]
+    fn compare_and_swap_absent_to_present() {
+        let store = Store::new();
+        let k = Bytes::from("lock");
+
+        assert!(store.compare_and_swap(k.clone(), None, Some(Bytes::from("owner1"))));
+        assert!(!store.compare_and_swap(k.clone(), None, Some(Bytes::from("owner2"))));
+        assert_eq!(store.get(k), Some(Bytes::from("owner1")));
+    }
+
+    #[test]
+    fn cas_mismatch_reports_current_value() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+        store.set(k.clone(), Bytes::from("actual"));
+
+        assert_eq!(
+            store.try_compare_and_swap(k.clone(), None, Some(Bytes::from("v"))),
+            Err(CasError::Mismatch {
+                current: Some(Bytes::from("actual"))
+            })
+        );
+        assert_eq!(
+            store.try_compare_and_swap(k.clone(), Some(Bytes::from("actual")), None),
+            Ok(())
+        );
+        assert_eq!(
+            store.try_compare_and_swap(k, Some(Bytes::from("actual")), None),
+            Err(CasError::Mismatch { current: None })
+        );
+    }
+
+    #[test]
+    fn one_cas_wins_per_round() {
+        let store = Store::new();
+        let k = Bytes::from("leader");
+
+        for round in 0..50 {
+            let expected = (round > 0).then(|| Bytes::from((round - 1).to_string()));
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let (store, k, expected) = (store.clone(), k.clone(), expected.clone());
+                    let new = Bytes::from(round.to_string());
+                    thread::spawn(move || store.try_compare_and_swap(k, expected, Some(new)))
+                })
+                .collect();
+
+            let wins = handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(Result::is_ok)
+                .count();
+            assert_eq!(wins, 1);
+        }
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let store = init_store();
+        let (tx, rx) = mpsc::channel();
+
+        // Readers must make progress while another reader holds the lock
+        let _held = store.read().unwrap();
+        for _ in 0..16 {
+            let (store, tx) = (store.clone(), tx.clone());
+            thread::spawn(move || {
+                for k in KEYS {
+                    assert!(store.get(Bytes::from(k)).is_some());
+                }
+                tx.send(()).unwrap();
+            });
+        }
+        for _ in 0..16 {
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+    }
+
+    #[test]
+    fn readers_see_consistent_batches() {
+        let store = Store::new();
+        let keys = [Bytes::from("a"), Bytes::from("b")];
+        store.set_many(keys.clone().map(|k| (k, Bytes::from("0"))));
+
+        let writer = {
+            let (store, keys) = (store.clone(), keys.clone());
+            thread::spawn(move || {
+                for n in 1..=1000 {
+                    let v = Bytes::from(n.to_string());
+                    store.set_many(keys.clone().map(|k| (k, v.clone())));
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let values = store.get_many(&keys);
+            assert_eq!(values[0], values[1]);
+        }
+        writer.join().unwrap();
+        assert_eq!(
+            store.get_many(&keys),
+            [Some(Bytes::from("1000")), Some(Bytes::from("1000"))]
+        );
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture` to compare read
+    // throughput as reader threads are added
+    #[test]
+    #[ignore]
+    fn read_throughput() {
+        let store = init_store();
+        for threads in [1, 2, 4, 8] {
+            let start = Instant::now();
+            let handles: Vec<_> = (0..threads)
+                .map(|_| {
+                    let store = store.clone();
+                    thread::spawn(move || {
+                        for _ in 0..200_000 {
+                            store.get(Bytes::from_static(b"hello3"));
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().for_each(|h| h.join().unwrap());
+
+            let reads = threads as f64 * 200_000.0;
+            let rate = reads / start.elapsed().as_secs_f64();
+            println!("{threads} reader threads: {rate:.0} gets/s");
+        }
+    }
+
+    #[test]
+    fn snapshot_is_detached_from_store() {
+        let store = init_store();
+        let mut snapshot = store.snapshot();
+        store.remove(Bytes::from("hello1"));
+        store.set(Bytes::from("hello6"), Bytes::from("world6"));
+
+        snapshot.sort();
+        let expected: Vec<_> = KEYS
             .iter()
-            .for_each(|(k, v)| store.set(Bytes::from(*k), Bytes::from(*v)));
-        store
+            .zip(VALS.iter())
+            .map(|(k, v)| (Bytes::from(*k), Bytes::from(*v)))
+            .collect();
+        assert_eq!(snapshot, expected);
+
+        let mut keys = store.keys();
+        keys.sort();
+        assert_eq!(keys[0], Bytes::from("hello2"));
+        assert_eq!(store.values().len(), 5);
+    }
+
+    #[test]
+    fn iteration_sees_whole_writes() {
+        let store = Store::new();
+        store.set_many(KEYS.map(|k| (Bytes::from(k), Bytes::from("start"))));
+        let writer = {
+            let store = store.clone();
+            thread::spawn(move || {
+                for n in 0..200 {
+                    let v = Bytes::from(n.to_string());
+                    store.set_many(KEYS.map(|k| (Bytes::from(k), v.clone())));
+                }
+            })
+        };
+
+        while !writer.is_finished() {
+            let values: Vec<_> = store.iter().map(|(_, v)| v).collect();
+            assert_eq!(values.len(), 5);
+            assert!(values.windows(2).all(|pair| pair[0] == pair[1]));
+
+            let mut seen = Vec::new();
+            store.for_each(|_, v| seen.push(v.clone())).unwrap();
+            assert!(seen.windows(2).all(|pair| pair[0] == pair[1]));
+        }
+        writer.join().unwrap();
+
+        // Writing from inside the loop doesn't deadlock
+        for (k, _) in store.iter() {
+            store.remove(k);
+        }
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn with_value_borrows_in_place() {
+        let store = init_store();
+        let k = Bytes::from("hello1");
+        assert_eq!(store.with_value(&k, Bytes::len), Some(6));
+        assert_eq!(
+            store.with_value(&k, |v| v.starts_with(b"world")),
+            Some(true)
+        );
+        assert_eq!(store.with_value(&Bytes::from("missing"), Bytes::len), None);
+    }
+
+    #[test]
+    fn clear_empties_store() {
+        let store = init_store();
+        store.clear();
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.get(Bytes::from("hello1")), None);
+    }
+
+    #[test]
+    fn drain_returns_prior_entries() {
+        let store = init_store();
+        let mut drained = store.drain();
+        drained.sort();
+
+        let expected: Vec<_> = KEYS
+            .iter()
+            .zip(VALS.iter())
+            .map(|(k, v)| (Bytes::from(*k), Bytes::from(*v)))
+            .collect();
+        assert_eq!(drained, expected);
+        assert!(store.is_empty());
+        assert!(store.drain().is_empty());
+    }
+
+    #[test]
+    fn drain_moves_entries_to_another_store() {
+        let store = init_store();
+        let other: Store = store.drain().into_iter().collect();
+
+        assert!(store.is_empty());
+        assert_eq!(other.len(), KEYS.len());
+        assert_eq!(
+            other.get(Bytes::from("hello1")),
+            Some(Bytes::from("world1"))
+        );
+    }
+
+    #[test]
+    fn retain_keeps_matching_entries() {
+        let store = Store::new();
+        for k in ["keep:a", "keep:b", "drop:a", "drop:b", "drop:c"] {
+            store.set(Bytes::from(k), Bytes::from("v"));
+        }
+
+        assert_eq!(store.retain(|k, _| k.starts_with(b"keep:")), 3);
+        let mut keys = store.keys();
+        keys.sort();
+        assert_eq!(keys, vec![Bytes::from("keep:a"), Bytes::from("keep:b")]);
+        assert_eq!(store.retain(|_, _| true), 0);
+    }
+
+    #[test]
+    fn retain_by_key_pattern() {
+        let store = init_store();
+        let even = |k: &Bytes| k.last().is_some_and(|d| (d - b'0').is_multiple_of(2));
+
+        assert_eq!(store.retain(|k, _| even(k)), 3);
+        let mut keys = store.keys();
+        keys.sort();
+        assert_eq!(keys, [Bytes::from("hello2"), Bytes::from("hello4")]);
+    }
+
+    #[test]
+    fn readers_see_clear_all_at_once() {
+        let store = Store::new();
+        store.set_many((0..100).map(|i| (Bytes::from(format!("k{i}")), Bytes::from("v"))));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    while let n @ 1.. = store.len() {
+                        assert_eq!(n, 100);
+                        assert_eq!(store.snapshot().len() % 100, 0);
+                    }
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(10));
+        store.clear();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn prefix_scan_and_remove() {
+        let store = Store::new();
+        for k in [
+            "user:42:session:a",
+            "user:42:session:b",
+            "user:420:session:c",
+        ] {
+            store.set(Bytes::from(k), Bytes::from("v"));
+        }
+        store.set(Bytes::from(vec![0xff, 0x00]), Bytes::from("binary"));
+
+        let mut sessions = store.scan_prefix(b"user:42:");
+        sessions.sort();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].0, Bytes::from("user:42:session:a"));
+        assert_eq!(store.scan_prefix(&[0xff]).len(), 1);
+        assert_eq!(store.scan_prefix(b"").len(), 4);
+
+        assert_eq!(store.remove_prefix(b"user:42:"), 2);
+        assert_eq!(store.remove_prefix(b"user:42:"), 0);
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn set_and_remove_return_previous_value() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+
+        assert_eq!(store.set(k.clone(), Bytes::from("v1")), None);
+        assert_eq!(
+            store.set(k.clone(), Bytes::from("v2")),
+            Some(Bytes::from("v1"))
+        );
+        assert_eq!(store.remove(k.clone()), Some(Bytes::from("v2")));
+        assert_eq!(store.remove(k), None);
+    }
+
+    #[test]
+    fn scan_prefix_matches_exactly() {
+        let store = Store::new();
+        for k in ["user:1", "user:2", "admin:1"] {
+            store.set(Bytes::from(k), Bytes::from(k.to_uppercase()));
+        }
+
+        let mut users = store.scan_prefix(b"user:");
+        users.sort();
+        assert_eq!(
+            users,
+            [
+                (Bytes::from("user:1"), Bytes::from("USER:1")),
+                (Bytes::from("user:2"), Bytes::from("USER:2")),
+            ]
+        );
+    }
+
+    #[test]
+    fn poisoned_lock_surfaces_error() {
+        let store = init_store();
+        poison(&store);
+
+        assert_eq!(
+            store.try_get(Bytes::from("hello1")),
+            Err(StoreError::Poisoned)
+        );
+        assert_eq!(
+            store.try_set(Bytes::from("hello1"), Bytes::from("world")),
+            Err(StoreError::Poisoned)
+        );
+        assert_eq!(
+            store.try_remove(Bytes::from("hello1")),
+            Err(StoreError::Poisoned)
+        );
+    }
+
+    #[test]
+    fn poison_recovery_keeps_store_usable() {
+        let store = Store::with_poison_recovery();
+        store.set(Bytes::from("before"), Bytes::from("v"));
+
+        // Panic partway through a write, while the lock is held
+        let inner = store.clone();
+        let result = thread::spawn(move || {
+            inner.update(Bytes::from("before"), |_| panic!("mid-write"));
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(store.0.state.is_poisoned());
+
+        assert_eq!(store.get(Bytes::from("before")), Some(Bytes::from("v")));
+        assert_eq!(
+            store.try_set(Bytes::from("after"), Bytes::from("v")),
+            Ok(None)
+        );
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn poison_policies_decide_next_access() {
+        for policy in [
+            PoisonPolicy::Propagate,
+            PoisonPolicy::Recover,
+            PoisonPolicy::Clear,
+        ] {
+            let store = Store::with_poison_policy(policy);
+            store.set(Bytes::from("before"), Bytes::from("v"));
+            let events = store.watch(Bytes::from("before"));
+
+            let inner = store.clone();
+            let result = thread::spawn(move || {
+                inner.update(Bytes::from("before"), |_| panic!("mid-write"));
+            })
+            .join();
+            assert!(result.is_err());
+
+            let (get, set) = (
+                store.try_get(Bytes::from("before")),
+                store.try_set(Bytes::from("after"), Bytes::from("v")),
+            );
+            match policy {
+                PoisonPolicy::Propagate => {
+                    assert_eq!(get, Err(StoreError::Poisoned));
+                    assert_eq!(set, Err(StoreError::Poisoned));
+                }
+                PoisonPolicy::Recover => {
+                    assert_eq!(get, Ok(Some(Bytes::from("v"))));
+                    assert_eq!(set, Ok(None));
+                    assert_eq!(store.len(), 2);
+                    assert!(store.0.state.is_poisoned());
+                }
+                PoisonPolicy::Clear => {
+                    assert_eq!(get, Ok(None));
+                    assert_eq!(set, Ok(None));
+                    assert_eq!(store.keys(), [Bytes::from("after")]);
+                    assert_eq!(events.try_recv(), Ok(WatchEvent::Removed));
+                    assert!(!store.0.state.is_poisoned());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clear_poison_restores_store() {
+        let store = init_store();
+        poison(&store);
+        assert_eq!(
+            store.try_get(Bytes::from("hello1")),
+            Err(StoreError::Poisoned)
+        );
+
+        store.clear_poison();
+        assert_eq!(
+            store.try_get(Bytes::from("hello1")),
+            Ok(Some(Bytes::from("world1")))
+        );
+    }
+
+    #[test]
+    fn try_set_and_try_remove_return_previous() {
+        let store = init_store();
+
+        let replaced = store.try_set(Bytes::from("hello1"), Bytes::from("again"));
+        assert_eq!(replaced, Ok(Some(Bytes::from("world1"))));
+        assert_eq!(
+            store.try_remove(Bytes::from("hello1")),
+            Ok(Some(Bytes::from("again")))
+        );
+        assert_eq!(store.try_remove(Bytes::from("hello1")), Ok(None));
+    }
+
+    #[test]
+    fn infallible_writes_are_noops_when_poisoned() {
+        let store = init_store();
+        poison(&store);
+
+        store.set(Bytes::from("hello6"), Bytes::from("world6"));
+        store.remove(Bytes::from("hello1"));
+
+        assert_eq!(store.get(Bytes::from("hello6")), None);
+        assert_eq!(
+            store.try_get(Bytes::from("hello6")),
+            Err(StoreError::Poisoned)
+        );
+    }
+
+    // Panics a helper thread while it holds the store's lock
+    fn poison(store: &Store) {
+        let inner = store.0.clone();
+        let result = thread::spawn(move || {
+            let _guard = inner.state.write().unwrap();
+            panic!("poisoning the store");
+        })
+        .join();
+        assert!(result.is_err());
+    }
+
+    fn init_store() -> Store {
+        KEYS.into_iter().zip(VALS).collect()
     }
 }
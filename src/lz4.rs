@@ -0,0 +1,200 @@
+// The LZ4 block format, for compressing values quickly at a lower ratio than
+// zstd. A compressed value is its `u32` little-endian length followed by one
+// LZ4 block, the same framing as lz4_flex's `compress_prepend_size`.
+//
+// A block is a run of sequences, each a token whose high and low nibbles
+// count the literals and the match length past `MIN_MATCH`, either nibble
+// saturating at 15 with the rest spelled out in following bytes, then the
+// literals, then a `u16` little-endian offset back to the match. The last
+// sequence stops after its literals.
+
+const MIN_MATCH: usize = 4;
+
+// Every block ends with at least this many literals, and no match starts
+// within `MF_LIMIT` bytes of its end, as decoders may rely on
+const LAST_LITERALS: usize = 5;
+const MF_LIMIT: usize = 12;
+
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+// Positions are looked up by a hash of the four bytes there, taking this
+// many bits
+const HASH_LOG: u32 = 12;
+
+// Compresses `input`, or returns `None` if it is too long for its length to
+// be framed
+pub(crate) fn compress(input: &[u8]) -> Option<Vec<u8>> {
+    let len = u32::try_from(input.len()).ok()?;
+    let mut out = Vec::with_capacity(4 + input.len() / 2);
+    out.extend_from_slice(&len.to_le_bytes());
+
+    // One past the last position each hash was seen at, or zero if never
+    let mut table = vec![0; 1 << HASH_LOG];
+    let (mut anchor, mut pos) = (0, 0);
+    while pos + MF_LIMIT < input.len() {
+        let seq = read_u32(input, pos);
+        let h = hash(seq);
+        let candidate = table[h];
+        table[h] = pos + 1;
+        let Some(candidate) = candidate
+            .checked_sub(1)
+            .filter(|&c| pos - c <= MAX_OFFSET && read_u32(input, c) == seq)
+        else {
+            pos += 1;
+            continue;
+        };
+
+        let max = input.len() - LAST_LITERALS - pos;
+        let mut matched = MIN_MATCH;
+        while matched < max && input[candidate + matched] == input[pos + matched] {
+            matched += 1;
+        }
+        let offset = (pos - candidate) as u16;
+        write_sequence(&mut out, &input[anchor..pos], Some((offset, matched)));
+        pos += matched;
+        anchor = pos;
+    }
+    write_sequence(&mut out, &input[anchor..], None);
+    Some(out)
+}
+
+// Decompresses what `compress` produced, or returns `None` if it isn't valid
+pub(crate) fn decompress(input: &[u8]) -> Option<Vec<u8>> {
+    let (len, block) = input.split_first_chunk::<4>()?;
+    let len = u32::from_le_bytes(*len) as usize;
+    // No byte of a block stands for more than 255 decompressed ones, so a
+    // corrupt length can't make this allocate much more than the input
+    let mut out = Vec::with_capacity(len.min(block.len().saturating_mul(255)));
+    let mut i = 0;
+    loop {
+        let token = *block.get(i)?;
+        i += 1;
+
+        let literals = read_len(block, &mut i, (token >> 4) as usize)?;
+        let literals = block.get(i..i.checked_add(literals)?)?;
+        if out.len() + literals.len() > len {
+            return None;
+        }
+        out.extend_from_slice(literals);
+        i += literals.len();
+        if i == block.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes(*block.get(i..)?.first_chunk::<2>()?) as usize;
+        i += 2;
+        let matched = read_len(block, &mut i, (token & 0xf) as usize)?.checked_add(MIN_MATCH)?;
+        if offset == 0 || offset > out.len() || out.len() + matched > len {
+            return None;
+        }
+        let start = out.len() - offset;
+        if offset >= matched {
+            out.extend_from_within(start..start + matched);
+        } else {
+            // The match overlaps what it produces, repeating the last
+            // `offset` bytes
+            for j in start..start + matched {
+                out.push(out[j]);
+            }
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+// Writes a sequence of `literals` followed by a match of `matched` bytes
+// `offset` back, or by nothing if it is the last
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(u16, usize)>) {
+    let extra = matched.map_or(0, |(_, matched)| matched - MIN_MATCH);
+    out.push((literals.len().min(15) << 4 | extra.min(15)) as u8);
+    write_len(out, literals.len());
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&offset.to_le_bytes());
+        write_len(out, extra);
+    }
+}
+
+// Spells out the part of a length its token's nibble couldn't hold
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    let Some(mut rest) = len.checked_sub(15) else {
+        return;
+    };
+    while rest >= 255 {
+        out.push(255);
+        rest -= 255;
+    }
+    out.push(rest as u8);
+}
+
+// Reads a length whose token nibble was `nibble`, along with any bytes that
+// spell out the rest of it
+fn read_len(block: &[u8], i: &mut usize, nibble: usize) -> Option<usize> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let b = *block.get(*i)?;
+            *i += 1;
+            len = len.checked_add(b as usize)?;
+            if b != 255 {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(input[pos..pos + 4].try_into().unwrap())
+}
+
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress};
+
+    #[test]
+    fn round_trips() {
+        let mut noise = Vec::new();
+        let mut seed = 0x2545_f491_u32;
+        for _ in 0..5000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            noise.push(seed as u8);
+        }
+        let inputs = [
+            Vec::new(),
+            b"short".to_vec(),
+            b"a".repeat(100_000),
+            b"{\"id\":1,\"tags\":[\"x\",\"y\"]}".repeat(300),
+            noise,
+        ];
+        for input in inputs {
+            let compressed = compress(&input).unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), input);
+        }
+        assert!(compress(&b"a".repeat(100_000)).unwrap().len() < 1000);
+    }
+
+    #[test]
+    fn decodes_blocks_from_other_encoders() {
+        // "abcabcabcabc" as a literal run of three, a match of six three
+        // back, and three closing literals
+        let block = [
+            12, 0, 0, 0, 0x32, b'a', b'b', b'c', 3, 0, 0x30, b'a', b'b', b'c',
+        ];
+        assert_eq!(decompress(&block).unwrap(), b"abcabcabcabc");
+    }
+
+    #[test]
+    fn rejects_corrupt_blocks() {
+        let compressed = compress(&b"hello hello hello hello hello".repeat(4)).unwrap();
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_none());
+        assert!(decompress(&[5, 0, 0, 0, 0x10, b'a']).is_none());
+        assert!(decompress(&[4, 0, 0, 0, 0x10, b'a', 2, 0]).is_none());
+        assert!(decompress(&[]).is_none());
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::Instant;
+
+use bytes::Bytes;
+
+use crate::{expiry::Expiry, Store, StoreError};
+
+/// How [`Store::merge_from`] settles a key that both stores hold.
+#[derive(Debug, Clone, Copy)]
+pub enum MergeStrategy {
+    /// Keep this store's value.
+    KeepExisting,
+    /// Replace this store's value with the other store's.
+    TakeOther,
+    /// Store what the function returns, given this store's value and then the
+    /// other store's.
+    Resolve(fn(&Bytes, &Bytes) -> Bytes),
+}
+
+/// What a call to [`Store::merge_from`] changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeStats {
+    /// The number of keys only the other store held, which were copied over.
+    pub inserted: usize,
+    /// The number of keys both stores held, which were settled by the
+    /// strategy.
+    pub conflicts: usize,
+}
+
+impl Store {
+    /// Copies every live entry of `other` into this store, settling keys both
+    /// hold as `strategy` says.
+    ///
+    /// `other` is copied out as by [`Store::snapshot`] before this store is
+    /// locked, and this store is then written under a single lock
+    /// acquisition. Since the two locks are never held together, stores can
+    /// merge from each other concurrently without deadlocking. Entries written
+    /// to this store get its default TTL, or none if it has none, as with
+    /// [`Store::set`].
+    ///
+    /// If appending to the write-ahead log fails or the store fills up
+    /// partway, the entries before the failing one stay written.
+    pub fn merge_from(
+        &self,
+        other: &Store,
+        strategy: MergeStrategy,
+    ) -> Result<MergeStats, StoreError> {
+        let entries = other
+            .snapshot()
+            .into_iter()
+            .map(|(k, v)| (k, v, Expiry::Default));
+        self.merge_entries(entries, strategy)
+    }
+
+    // Writes `entries` under a single lock acquisition with the expiry given
+    // for each, settling keys the store already holds as `strategy` says
+    pub(crate) fn merge_entries(
+        &self,
+        entries: impl IntoIterator<Item = (Bytes, Bytes, Expiry)>,
+        strategy: MergeStrategy,
+    ) -> Result<MergeStats, StoreError> {
+        self.mutate(|state| {
+            let now = Instant::now();
+            let mut stats = MergeStats::default();
+            for (k, v, expiry) in entries {
+                let v = match (state.get(&k, now), strategy) {
+                    (None, _) => {
+                        stats.inserted += 1;
+                        v
+                    }
+                    (Some(_), MergeStrategy::KeepExisting) => {
+                        stats.conflicts += 1;
+                        continue;
+                    }
+                    (Some(_), MergeStrategy::TakeOther) => {
+                        stats.conflicts += 1;
+                        v
+                    }
+                    (Some(existing), MergeStrategy::Resolve(resolve)) => {
+                        stats.conflicts += 1;
+                        resolve(existing, &v)
+                    }
+                };
+                state.set(k, v, expiry)?;
+            }
+            Ok(stats)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MergeStats, MergeStrategy};
+    use crate::Store;
+    use bytes::{Bytes, BytesMut};
+    use std::{sync::mpsc, thread, time::Duration};
+
+    fn stores() -> (Store, Store) {
+        let a: Store = [("shared", "a"), ("only-a", "a")].into_iter().collect();
+        let b: Store = [("shared", "b"), ("only-b", "b")].into_iter().collect();
+        (a, b)
+    }
+
+    fn concat(existing: &Bytes, other: &Bytes) -> Bytes {
+        let mut v = BytesMut::from(existing.as_ref());
+        v.extend_from_slice(other);
+        v.freeze()
+    }
+
+    #[test]
+    fn strategies_settle_conflicts() {
+        let expected = MergeStats {
+            inserted: 1,
+            conflicts: 1,
+        };
+        for (strategy, merged) in [
+            (MergeStrategy::KeepExisting, "a"),
+            (MergeStrategy::TakeOther, "b"),
+            (MergeStrategy::Resolve(concat), "ab"),
+        ] {
+            let (a, b) = stores();
+            assert_eq!(a.merge_from(&b, strategy), Ok(expected));
+            assert_eq!(a.get(Bytes::from("shared")), Some(Bytes::from(merged)));
+            assert_eq!(a.get(Bytes::from("only-b")), Some(Bytes::from("b")));
+            assert_eq!(a.len(), 3);
+            assert_eq!(b.len(), 2);
+        }
+    }
+
+    #[test]
+    fn cross_merges_do_not_deadlock() {
+        let (a, b) = stores();
+        let (done, finished) = mpsc::channel();
+        for (to, from) in [(a.clone(), b.clone()), (b, a)] {
+            let done = done.clone();
+            thread::spawn(move || {
+                for _ in 0..500 {
+                    to.merge_from(&from, MergeStrategy::TakeOther).unwrap();
+                }
+                done.send(()).unwrap();
+            });
+        }
+        for _ in 0..2 {
+            finished
+                .recv_timeout(Duration::from_secs(10))
+                .expect("merges deadlocked");
+        }
+    }
+
+    #[test]
+    fn merging_from_itself_changes_nothing() {
+        let (a, _) = stores();
+        let stats = a.merge_from(&a.clone(), MergeStrategy::KeepExisting);
+        assert_eq!(stats.map(|s| s.conflicts), Ok(2));
+        assert_eq!(a, stores().0);
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    fmt::{self, Debug},
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::Bytes;
+
+use crate::{Store, StoreError};
+
+/// A [`Store`] that passes every key through a normalizer before using it,
+/// returned by [`Store::with_key_normalizer`].
+///
+/// The normalized key is the only one stored, so keys that normalize the same
+/// way, such as `HELLO` and `hello` under a lowercasing normalizer, are the
+/// same key. Only go through the underlying store with keys that are already
+/// normalized. The normalizer should be idempotent, since keys read back from
+/// the store are normalized again when they're passed in.
+///
+/// Like `Store`, cloning a `NormalizedStore` shares the same records.
+#[derive(Clone)]
+pub struct NormalizedStore {
+    store: Store,
+    normalize: Arc<dyn Fn(&Bytes) -> Bytes + Send + Sync>,
+}
+
+impl Store {
+    /// Creates a store that looks up, writes and removes every key as `f`
+    /// normalizes it, e.g. ASCII-lowercased for keys whose case shouldn't
+    /// matter.
+    pub fn with_key_normalizer<F>(f: F) -> NormalizedStore
+    where
+        F: Fn(&Bytes) -> Bytes + Send + Sync + 'static,
+    {
+        NormalizedStore::from_store(Store::new(), f)
+    }
+}
+
+impl NormalizedStore {
+    /// Wraps an existing store, whose keys must already be normalized by `f`
+    /// to be found.
+    pub fn from_store<F>(store: Store, f: F) -> Self
+    where
+        F: Fn(&Bytes) -> Bytes + Send + Sync + 'static,
+    {
+        Self {
+            store,
+            normalize: Arc::new(f),
+        }
+    }
+
+    /// The store underneath, holding the normalized keys.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// The key `k` is stored under.
+    pub fn normalize(&self, k: &Bytes) -> Bytes {
+        (self.normalize)(k)
+    }
+
+    pub fn get(&self, k: Bytes) -> Option<Bytes> {
+        self.store.get(self.normalize(&k))
+    }
+
+    /// Inserts a value without a TTL of its own, returning the live value it
+    /// replaced. See [`Store::set`] for how a default TTL applies.
+    pub fn set(&self, k: Bytes, v: Bytes) -> Option<Bytes> {
+        self.store.set(self.normalize(&k), v)
+    }
+
+    /// Like [`Store::set_with_ttl`], inserts a value that expires after `ttl`.
+    pub fn set_with_ttl(&self, k: Bytes, v: Bytes, ttl: Duration) {
+        self.store.set_with_ttl(self.normalize(&k), v, ttl);
+    }
+
+    /// Removes a value, returning it if it was present.
+    pub fn remove(&self, k: Bytes) -> Option<Bytes> {
+        self.store.remove(self.normalize(&k))
+    }
+
+    pub fn contains_key(&self, k: &Bytes) -> bool {
+        self.store.contains_key(&self.normalize(k))
+    }
+
+    /// Like [`Store::update`], atomically replaces the value of `k` with
+    /// `f(current)` and returns the new value.
+    pub fn update<F>(&self, k: Bytes, f: F) -> Option<Bytes>
+    where
+        F: FnOnce(Option<Bytes>) -> Option<Bytes>,
+    {
+        self.store.update(self.normalize(&k), f)
+    }
+
+    /// Like [`NormalizedStore::get`], but reports a poisoned lock instead of
+    /// returning `None`.
+    pub fn try_get(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.store.try_get(self.normalize(&k))
+    }
+
+    /// Like [`NormalizedStore::set`], but reports why a write failed.
+    pub fn try_set(&self, k: Bytes, v: Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.store.try_set(self.normalize(&k), v)
+    }
+
+    /// Like [`NormalizedStore::remove`], but reports why a removal failed.
+    pub fn try_remove(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        self.store.try_remove(self.normalize(&k))
+    }
+}
+
+// Leaves out the normalizer, which closures can't print
+impl Debug for NormalizedStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NormalizedStore")
+            .field("store", &self.store)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Store;
+    use bytes::Bytes;
+
+    fn lowercase(k: &Bytes) -> Bytes {
+        Bytes::from(k.to_ascii_lowercase())
+    }
+
+    #[test]
+    fn keys_differing_in_case_are_the_same() {
+        let store = Store::with_key_normalizer(lowercase);
+        store.set(Bytes::from("hello"), Bytes::from("world"));
+
+        assert_eq!(store.get(Bytes::from("HELLO")), Some(Bytes::from("world")));
+        assert!(store.contains_key(&Bytes::from("Hello")));
+        assert_eq!(
+            store.set(Bytes::from("HeLLo"), Bytes::from("again")),
+            Some(Bytes::from("world"))
+        );
+        assert_eq!(store.store().len(), 1);
+        assert_eq!(store.store().snapshot()[0].0, Bytes::from("hello"));
+
+        let n = store.update(Bytes::from("COUNT"), |_| Some(Bytes::from("1")));
+        assert_eq!(store.get(Bytes::from("count")), n);
+        assert_eq!(
+            store.remove(Bytes::from("HELLO")),
+            Some(Bytes::from("again"))
+        );
+        assert!(!store.contains_key(&Bytes::from("hello")));
+    }
+}
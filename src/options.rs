@@ -0,0 +1,313 @@
+use std::{collections::HashMap, hash::BuildHasher};
+
+use bytes::Bytes;
+
+use crate::{evict, Expiries, Records, State, Store, StoreError};
+
+/// Hard limits on what a [`Store`] accepts, passed to [`Store::with_options`].
+/// `None` means unlimited, which is the default for every limit.
+///
+/// A write that would break a limit fails instead of being stored, whether it
+/// comes from `set`, `append`, a batch or a transaction. Nothing already
+/// stored is dropped to make room unless the store also has a capacity, as
+/// [`StoreBuilder::capacity`](crate::StoreBuilder::capacity) gives it. Then a
+/// write over `max_total_bytes` first evicts what the eviction policy picks,
+/// and only fails if it still wouldn't fit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreOptions {
+    /// The longest key accepted, in bytes.
+    pub max_key_len: Option<usize>,
+    /// The longest value accepted, in bytes.
+    pub max_value_len: Option<usize>,
+    /// The most the records may take up, as [`Store::approximate_size`]
+    /// counts them.
+    pub max_total_bytes: Option<usize>,
+}
+
+impl Store {
+    /// Creates a store that rejects writes breaking the limits in `options`.
+    pub fn with_options(options: StoreOptions) -> Self {
+        Self::from_state(State {
+            options,
+            ..State::new(Records::new(), Expiries::new())
+        })
+    }
+
+    /// Creates a store that fails writes of keys longer than `max_key_len` or
+    /// values longer than `max_value_len` bytes, with
+    /// [`StoreError::KeyTooLong`] or [`StoreError::ValueTooLong`]. `try_set`
+    /// fails them before taking the lock, and `set` drops them.
+    ///
+    /// Shorthand for setting only [`StoreOptions::max_key_len`] and
+    /// [`StoreOptions::max_value_len`].
+    pub fn with_limits(max_key_len: usize, max_value_len: usize) -> Self {
+        Self::with_options(StoreOptions {
+            max_key_len: Some(max_key_len),
+            max_value_len: Some(max_value_len),
+            ..StoreOptions::default()
+        })
+    }
+
+    /// Creates a store that fails writes with [`StoreError::Full`] rather than
+    /// grow past `max_bytes`. Shorthand for setting only
+    /// [`StoreOptions::max_total_bytes`].
+    pub fn with_memory_limit(max_bytes: usize) -> Self {
+        Self::with_options(StoreOptions {
+            max_total_bytes: Some(max_bytes),
+            ..StoreOptions::default()
+        })
+    }
+}
+
+impl StoreOptions {
+    // Fails if a key or value is longer than the options allow. This needs no
+    // lock, so single-key writes check it before taking one
+    pub(crate) fn check_entry(&self, k: &Bytes, v: &Bytes) -> Result<(), StoreError> {
+        self.check_lens(k.len(), v.len())
+    }
+
+    // Like `check_entry`, given only how long the key and value are
+    pub(crate) fn check_lens(&self, key_len: usize, value_len: usize) -> Result<(), StoreError> {
+        let exceeds = |len: usize, max: Option<usize>| max.is_some_and(|max| len > max);
+        if exceeds(key_len, self.max_key_len) {
+            return Err(StoreError::KeyTooLong);
+        }
+        if exceeds(value_len, self.max_value_len) {
+            return Err(StoreError::ValueTooLong);
+        }
+        Ok(())
+    }
+}
+
+impl<S: BuildHasher> State<S> {
+    pub(crate) fn check_entry(&self, k: &Bytes, v: &Bytes) -> Result<(), StoreError> {
+        self.options.check_entry(k, v)
+    }
+
+    // Fails if setting `k` to `v` would break any of the options
+    pub(crate) fn check_set(&self, k: &Bytes, v: &Bytes) -> Result<(), StoreError> {
+        self.check_entry(k, v)?;
+        if let Some(max) = self.options.max_total_bytes {
+            let replaced = self
+                .records
+                .get(k)
+                .map_or(0, |prev| evict::entry_size(k, prev));
+            if self.size - replaced + evict::entry_size(k, v) > max {
+                return Err(StoreError::Full);
+            }
+        }
+        Ok(())
+    }
+
+    // Fails if applying `writes` in order, as `write_all` does, would break
+    // any of the options partway through
+    pub(crate) fn check_writes(&self, writes: &[(Bytes, Option<Bytes>)]) -> Result<(), StoreError> {
+        for (k, v) in writes {
+            if let Some(v) = v {
+                self.check_entry(k, v)?;
+            }
+        }
+        let Some(max) = self.options.max_total_bytes else {
+            return Ok(());
+        };
+        let added =
+            |k: &Bytes, v: &Option<Bytes>| v.as_ref().map_or(0, |v| evict::entry_size(k, v));
+        if self.capacity.is_some() {
+            // Eviction makes room for any record that fits on its own
+            return match writes.iter().any(|(k, v)| added(k, v) > max) {
+                true => Err(StoreError::Full),
+                false => Ok(()),
+            };
+        }
+        let mut sizes = HashMap::new();
+        let mut size = self.size;
+        for (k, v) in writes {
+            let replaced = sizes.get(k).copied().unwrap_or_else(|| {
+                self.records
+                    .get(k)
+                    .map_or(0, |prev| evict::entry_size(k, prev))
+            });
+            size = size - replaced + added(k, v);
+            // A removal never fails, even in a store that is already too full
+            if v.is_some() && size > max {
+                return Err(StoreError::Full);
+            }
+            sizes.insert(k, added(k, v));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StoreOptions;
+    use crate::{evict, Batch, Store, StoreError};
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    fn store(options: StoreOptions) -> Store {
+        Store::with_options(options)
+    }
+
+    #[test]
+    fn key_and_value_limits_are_inclusive() {
+        let store = store(StoreOptions {
+            max_key_len: Some(3),
+            max_value_len: Some(5),
+            ..StoreOptions::default()
+        });
+
+        assert_eq!(
+            store.try_set(Bytes::from("abc"), Bytes::from("12345")),
+            Ok(None)
+        );
+        assert_eq!(
+            store.try_set(Bytes::from("abcd"), Bytes::from("1")),
+            Err(StoreError::KeyTooLong)
+        );
+        assert_eq!(
+            store.try_set(Bytes::from("a"), Bytes::from("123456")),
+            Err(StoreError::ValueTooLong)
+        );
+
+        // Appending may not grow a value past the limit either
+        assert_eq!(store.append(Bytes::from("abc"), b"6"), 0);
+        assert_eq!(store.get(Bytes::from("abc")), Some(Bytes::from("12345")));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn limits_are_checked_before_locking() {
+        let store = Store::with_limits(3, 5);
+        store.set(Bytes::from("abcd"), Bytes::from("1"));
+        store.set(Bytes::from("a"), Bytes::from("123456"));
+        store.set(Bytes::from("abc"), Bytes::from("12345"));
+        assert_eq!(
+            store.snapshot(),
+            [(Bytes::from("abc"), Bytes::from("12345"))]
+        );
+
+        // With the lock held, an over-limit write fails on its limit rather than
+        // on waiting for the lock
+        let results = store
+            .with_write(|_| {
+                [
+                    store.try_set_for(Bytes::from("abcd"), Bytes::new(), Duration::ZERO),
+                    store.try_set_for(Bytes::from("a"), Bytes::from("123456"), Duration::ZERO),
+                    store.try_set_for(Bytes::from("a"), Bytes::from("1"), Duration::ZERO),
+                ]
+            })
+            .unwrap();
+        assert_eq!(
+            results,
+            [
+                Err(StoreError::KeyTooLong),
+                Err(StoreError::ValueTooLong),
+                Err(StoreError::LockTimeout),
+            ]
+        );
+    }
+
+    #[test]
+    fn total_limit_counts_overwrites_by_their_delta() {
+        let (k, v) = (Bytes::from("k"), Bytes::from("12345"));
+        let max = evict::entry_size(&k, &v);
+        let store = store(StoreOptions {
+            max_total_bytes: Some(max),
+            ..StoreOptions::default()
+        });
+
+        assert_eq!(store.try_set(k.clone(), v.clone()), Ok(None));
+        assert_eq!(store.try_set(k.clone(), Bytes::from("abcde")), Ok(Some(v)));
+        assert_eq!(
+            store.try_set(k.clone(), Bytes::from("123456")),
+            Err(StoreError::Full)
+        );
+        assert_eq!(
+            store.try_set(Bytes::from("k2"), Bytes::new()),
+            Err(StoreError::Full)
+        );
+
+        store.remove(k.clone());
+        assert_eq!(store.try_set(k, Bytes::from("12345")), Ok(None));
+        assert_eq!(store.approximate_size(), max);
+    }
+
+    #[test]
+    fn shrinking_overwrite_fits_where_a_new_key_does_not() {
+        let (a, big) = (Bytes::from("a"), Bytes::from(vec![0; 100]));
+        let store = Store::with_memory_limit(evict::entry_size(&a, &big) + 10);
+        assert_eq!(store.try_set(a.clone(), big), Ok(None));
+
+        let small = Bytes::from(vec![0; 50]);
+        assert_eq!(
+            store.try_set(Bytes::from("b"), small.clone()),
+            Err(StoreError::Full)
+        );
+        assert!(store.try_set(a.clone(), small.clone()).is_ok());
+        assert_eq!(store.get(a), Some(small));
+    }
+
+    #[test]
+    fn batches_and_transactions_are_checked_up_front() {
+        let store = store(StoreOptions {
+            max_value_len: Some(1),
+            ..StoreOptions::default()
+        });
+
+        let mut batch = Batch::new();
+        batch
+            .set(Bytes::from("a"), Bytes::from("1"))
+            .set(Bytes::from("b"), Bytes::from("22"));
+        assert_eq!(store.apply(batch), Err(StoreError::ValueTooLong));
+
+        let committed = store.transaction(|txn| {
+            txn.set(Bytes::from("a"), Bytes::from("1"));
+            txn.set(Bytes::from("b"), Bytes::from("22"));
+            Ok::<_, StoreError>(())
+        });
+        assert_eq!(committed, Err(StoreError::ValueTooLong));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn batches_and_transactions_fit_whole_or_not_at_all() {
+        let (k, v) = (Bytes::from("a"), Bytes::from("1"));
+        let store = store(StoreOptions {
+            max_total_bytes: Some(2 * evict::entry_size(&k, &v)),
+            ..StoreOptions::default()
+        });
+
+        let mut batch = Batch::new();
+        batch
+            .set(Bytes::from("a"), Bytes::from("1"))
+            .set(Bytes::from("b"), Bytes::from("2"))
+            .set(Bytes::from("c"), Bytes::from("3"));
+        assert_eq!(store.apply(batch), Err(StoreError::Full));
+        let committed = store.transaction(|txn| {
+            for k in ["a", "b", "c"] {
+                txn.set(Bytes::from(k), Bytes::from("1"));
+            }
+            Ok::<_, StoreError>(())
+        });
+        assert_eq!(committed, Err(StoreError::Full));
+        assert!(store.is_empty());
+
+        // Removing a key midway makes room for the ones after it
+        let mut batch = Batch::new();
+        batch
+            .set(Bytes::from("a"), Bytes::from("1"))
+            .set(Bytes::from("b"), Bytes::from("2"))
+            .remove(Bytes::from("a"))
+            .set(Bytes::from("c"), Bytes::from("3"));
+        assert!(store.apply(batch).is_ok());
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn no_limits_by_default() {
+        let store = store(StoreOptions::default());
+        store.set(Bytes::from(vec![0; 1 << 16]), Bytes::from(vec![0; 1 << 20]));
+        assert_eq!(store.len(), 1);
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::BTreeMap,
+    ops::{Bound, RangeBounds},
+    sync::{Arc, RwLock},
+};
+
+use bytes::Bytes;
+
+use crate::StoreError;
+
+/// A key value store that keeps its keys sorted by their raw bytes, so it can
+/// answer range queries.
+///
+/// Like [`Store`](crate::Store), cloning an `OrderedStore` shares the same
+/// records.
+#[derive(Debug, Clone, Default)]
+pub struct OrderedStore(Arc<RwLock<BTreeMap<Bytes, Bytes>>>);
+
+impl OrderedStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, k: Bytes) -> Option<Bytes> {
+        self.try_get(k).unwrap_or_default()
+    }
+
+    /// Inserts a value, returning the one it replaced.
+    pub fn set(&self, k: Bytes, v: Bytes) -> Option<Bytes> {
+        self.try_set(k, v).unwrap_or_default()
+    }
+
+    /// Removes a value, returning it if it was present.
+    pub fn remove(&self, k: Bytes) -> Option<Bytes> {
+        self.try_remove(k).unwrap_or_default()
+    }
+
+    /// Like [`OrderedStore::get`], but reports a poisoned lock instead of
+    /// returning `None`.
+    pub fn try_get(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        Ok(self.0.read()?.get(&k).cloned())
+    }
+
+    /// Like [`OrderedStore::set`], but reports a poisoned lock instead of
+    /// returning `None`.
+    pub fn try_set(&self, k: Bytes, v: Bytes) -> Result<Option<Bytes>, StoreError> {
+        Ok(self.0.write()?.insert(k, v))
+    }
+
+    /// Like [`OrderedStore::remove`], but reports a poisoned lock instead of
+    /// returning `None`.
+    pub fn try_remove(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        Ok(self.0.write()?.remove(&k))
+    }
+
+    /// Returns `false` if the key is absent or the lock can't be taken.
+    pub fn contains_key(&self, k: &Bytes) -> bool {
+        self.0
+            .read()
+            .map(|records| records.contains_key(k))
+            .unwrap_or_default()
+    }
+
+    /// Returns `0` if the lock can't be taken.
+    pub fn len(&self) -> usize {
+        self.0
+            .read()
+            .map(|records| records.len())
+            .unwrap_or_default()
+    }
+
+    /// Returns `true` if the lock can't be taken.
+    pub fn is_empty(&self) -> bool {
+        self.0
+            .read()
+            .map(|records| records.is_empty())
+            .unwrap_or(true)
+    }
+
+    /// Returns the entries whose keys fall within `range`, in key order. A
+    /// range whose start is after its end is empty.
+    pub fn range(&self, range: impl RangeBounds<Bytes>) -> Vec<(Bytes, Bytes)> {
+        if is_empty_range(&range) {
+            return Vec::new();
+        }
+        self.0
+            .read()
+            .map(|records| {
+                records
+                    .range(range)
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The entry with the smallest key.
+    pub fn first(&self) -> Option<(Bytes, Bytes)> {
+        let records = self.0.read().ok()?;
+        records
+            .first_key_value()
+            .map(|(k, v)| (k.clone(), v.clone()))
+    }
+
+    /// The entry with the largest key.
+    pub fn last(&self) -> Option<(Bytes, Bytes)> {
+        let records = self.0.read().ok()?;
+        records
+            .last_key_value()
+            .map(|(k, v)| (k.clone(), v.clone()))
+    }
+}
+
+// Whether `range` can't contain any key. `BTreeMap::range` panics on these
+// rather than returning nothing
+fn is_empty_range(range: &impl RangeBounds<Bytes>) -> bool {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) => start > end,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderedStore;
+    use bytes::Bytes;
+
+    fn init_store() -> OrderedStore {
+        let store = OrderedStore::new();
+        for k in [
+            "log:2023-01-01",
+            "log:2023-01-15",
+            "log:2023-02-01",
+            "log:2023-03-01",
+        ] {
+            store.set(Bytes::from(k), Bytes::from("entry"));
+        }
+        store
+    }
+
+    fn keys(entries: Vec<(Bytes, Bytes)>) -> Vec<Bytes> {
+        entries.into_iter().map(|(k, _)| k).collect()
+    }
+
+    #[test]
+    fn get_set_remove() {
+        let store = OrderedStore::new();
+        store.set(Bytes::from("hello"), Bytes::from("world"));
+        assert_eq!(store.get(Bytes::from("hello")), Some(Bytes::from("world")));
+
+        store.remove(Bytes::from("hello"));
+        assert!(!store.contains_key(&Bytes::from("hello")));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn range_bounds() {
+        let store = init_store();
+        let (jan, feb) = (Bytes::from("log:2023-01-01"), Bytes::from("log:2023-02-01"));
+
+        assert_eq!(
+            keys(store.range(jan.clone()..feb.clone())),
+            [Bytes::from("log:2023-01-01"), Bytes::from("log:2023-01-15")]
+        );
+        assert_eq!(store.range(jan.clone()..=feb.clone()).len(), 3);
+        assert_eq!(store.range(feb.clone()..).len(), 2);
+        assert_eq!(store.range(..feb.clone()).len(), 2);
+        assert_eq!(store.range(..).len(), 4);
+        assert!(store.range(feb..jan).is_empty());
+    }
+
+    #[test]
+    fn orders_raw_bytes() {
+        let store = OrderedStore::new();
+        for k in [&b"ab"[..], b"a", b"\xff", b"", b"a\x00"] {
+            store.set(Bytes::copy_from_slice(k), Bytes::new());
+        }
+
+        assert_eq!(
+            keys(store.range(..)),
+            [&b""[..], b"a", b"a\x00", b"ab", b"\xff"].map(Bytes::from_static)
+        );
+        assert_eq!(store.first().unwrap().0, Bytes::new());
+        assert_eq!(store.last().unwrap().0, Bytes::from_static(b"\xff"));
+        assert_eq!(OrderedStore::new().first(), None);
+    }
+}
@@ -0,0 +1,630 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    time::Instant,
+};
+
+use bytes::Bytes;
+
+use crate::{
+    crc::Crc32,
+    expiry::{self, Expiry},
+    Expiries, MergeStats, MergeStrategy, Records, State, Store,
+};
+
+// Every snapshot starts with the magic bytes followed by a format version.
+// Version 1 records are `u32 key length, key, u32 value length, value`;
+// version 2 follows each with a `u64` deadline in Unix milliseconds, or zero
+// if the record doesn't expire. Version 3 puts a `u64` record count before
+// the records, follows each with a `u32` CRC-32 of the record, and ends with a
+// `u32` CRC-32 of everything before it.
+const MAGIC: &[u8; 4] = b"KVS\0";
+const VERSION: u8 = 3;
+
+// Encrypted snapshots start with their own magic bytes and scheme version
+// instead, so that the plaintext loader can tell them apart
+pub(crate) const ENCRYPTED_MAGIC: &[u8; 4] = b"KVSE";
+
+/// What a call to [`Store::backup_to`] wrote.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackupSummary {
+    /// The number of records written.
+    pub entries: u64,
+    /// The length of the backup, headers and checksums included.
+    pub bytes: u64,
+}
+
+impl Store {
+    /// Writes every record to `path` so it can be restored by [`Store::load_from_path`].
+    ///
+    /// The records are copied out under the lock and written afterwards, so
+    /// writers aren't blocked on disk I/O. Expired entries are skipped and
+    /// TTLs are kept.
+    pub fn save_to_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.dump(&mut writer)?;
+        writer.into_inner()?.sync_all()
+    }
+
+    /// Restores a store from a snapshot written by [`Store::save_to_path`].
+    pub fn load_from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::load(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Writes a snapshot to `w` in the same format as [`Store::save_to_path`],
+    /// e.g. to stream a backup over a socket. `w` isn't buffered here, so wrap
+    /// it in a [`BufWriter`] if it makes a system call per write.
+    ///
+    /// As with `save_to_path`, the records are copied out under the lock and
+    /// written afterwards.
+    pub fn dump<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let (records, expiries) = self
+            .read_state(|guard| (guard.records.clone(), guard.expiries.clone()))
+            .map_err(io::Error::other)?;
+        write_records(w, &records, &expiries).map(drop)
+    }
+
+    /// Like [`Store::dump`], but takes the writer by value and reports what
+    /// was written, e.g. to stream a backup into an upload.
+    ///
+    /// As with `dump`, the records are copied out under the lock and streamed
+    /// to `w` once it is released, so a slow `w` never holds up writers.
+    pub fn backup_to(&self, mut w: impl Write) -> io::Result<BackupSummary> {
+        let (records, expiries) = self
+            .read_state(|guard| (guard.records.clone(), guard.expiries.clone()))
+            .map_err(io::Error::other)?;
+        write_records(&mut w, &records, &expiries)
+    }
+
+    /// Like [`Store::load`], but takes the reader by value, e.g. to restore
+    /// a backup written by [`Store::backup_to`] straight from a download.
+    pub fn restore_from(mut r: impl Read) -> io::Result<Self> {
+        Self::load(&mut r)
+    }
+
+    /// Copies every record of a backup into this store, settling keys the
+    /// store already holds as `strategy` says, like [`Store::merge_from`].
+    /// The backup is read in full before the store is locked, so a corrupt
+    /// one leaves the store untouched. Restored records keep what is left of
+    /// their TTLs, and those without one get the store's default TTL, as with
+    /// [`Store::set`].
+    pub fn restore_into(
+        &self,
+        mut r: impl Read,
+        strategy: MergeStrategy,
+    ) -> io::Result<MergeStats> {
+        let (records, expiries) = read_records(&mut r)?;
+        let now = Instant::now();
+        let live = records
+            .into_iter()
+            .filter_map(|(k, v)| match expiries.get(&k) {
+                Some(deadline) if *deadline <= now => None,
+                Some(deadline) => Some((k, v, Expiry::At(*deadline))),
+                None => Some((k, v, Expiry::Default)),
+            });
+        self.merge_entries(live, strategy).map_err(io::Error::other)
+    }
+
+    /// Restores a store from a snapshot written by [`Store::dump`] or
+    /// [`Store::save_to_path`], reading `r` to its end.
+    pub fn load<R: Read>(r: &mut R) -> io::Result<Self> {
+        let (records, expiries) = read_records(r)?;
+        Ok(Self::from_state(State::new(records, expiries)))
+    }
+}
+
+pub(crate) fn write_records(
+    w: &mut impl Write,
+    records: &Records,
+    expiries: &Expiries,
+) -> io::Result<BackupSummary> {
+    // The records are walked twice, to count them for the header and then to
+    // write them, rather than collected
+    let now = Instant::now();
+    let live = || {
+        records
+            .iter()
+            .filter_map(move |(k, v)| match expiries.get(k) {
+                Some(deadline) if *deadline <= now => None,
+                deadline => Some((k, v, deadline.copied().map_or(0, expiry::to_unix_millis))),
+            })
+    };
+
+    let entries = live().count() as u64;
+    let mut w = Checked::new(w);
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])?;
+    w.write_all(&entries.to_le_bytes())?;
+    for (k, v, millis) in live() {
+        w.record = Crc32::new();
+        write_chunk(&mut w, k)?;
+        write_chunk(&mut w, v)?;
+        w.write_all(&millis.to_le_bytes())?;
+        let crc = w.record.finish();
+        w.write_all(&crc.to_le_bytes())?;
+    }
+    let crc = w.file.finish();
+    w.write_all(&crc.to_le_bytes())?;
+    w.flush()?;
+    Ok(BackupSummary {
+        entries,
+        bytes: w.offset,
+    })
+}
+
+pub(crate) fn read_records(r: &mut impl Read) -> io::Result<(Records, Expiries)> {
+    let mut r = Checked::new(r);
+    let mut header = [0; MAGIC.len() + 1];
+    read_exact(&mut r, &mut header, "header")?;
+    if &header[..MAGIC.len()] == ENCRYPTED_MAGIC {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "snapshot is encrypted: load it with Store::load_encrypted",
+        ));
+    }
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "not a kvs snapshot: bad magic header",
+        ));
+    }
+    match header[MAGIC.len()] {
+        version @ (1 | 2) => read_unchecked(&mut r, version),
+        3 => read_checked(&mut r),
+        version => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported snapshot version {version}"),
+        )),
+    }
+}
+
+// Reads the records of a version 1 or 2 snapshot, which run to the end of
+// the file
+fn read_unchecked(r: &mut impl Read, version: u8) -> io::Result<(Records, Expiries)> {
+    let (mut records, mut expiries) = (Records::new(), Expiries::new());
+    while let Some(k) = read_chunk(r, "key")? {
+        let v = read_chunk(r, "value")?.ok_or_else(|| truncated("value length"))?;
+        let millis = match version {
+            1 => 0,
+            _ => read_u64(r, "deadline")?,
+        };
+        insert(&mut records, &mut expiries, k, v, millis);
+    }
+    Ok((records, expiries))
+}
+
+// Reads the records of a version 3 snapshot, verifying every checksum
+fn read_checked<R: Read>(r: &mut Checked<R>) -> io::Result<(Records, Expiries)> {
+    let (mut records, mut expiries) = (Records::new(), Expiries::new());
+    let count = read_u64(r, "record count")?;
+    for index in 0..count {
+        let offset = r.offset;
+        r.record = Crc32::new();
+        let k = read_chunk(r, "key")?.ok_or_else(|| truncated("key length"))?;
+        let v = read_chunk(r, "value")?.ok_or_else(|| truncated("value length"))?;
+        let millis = read_u64(r, "deadline")?;
+        let crc = r.record.finish();
+        if read_u32(r, "record checksum")? != crc {
+            return Err(corrupt(format!(
+                "record {index} at byte {offset} fails its checksum"
+            )));
+        }
+        insert(&mut records, &mut expiries, k, v, millis);
+    }
+
+    let offset = r.offset;
+    let crc = r.file.finish();
+    if read_u32(r, "file checksum")? != crc {
+        return Err(corrupt(format!(
+            "file checksum at byte {offset} doesn't match"
+        )));
+    }
+    if r.read(&mut [0])? != 0 {
+        return Err(corrupt(format!(
+            "unexpected data after the footer at byte {offset}",
+            offset = r.offset - 1
+        )));
+    }
+    Ok((records, expiries))
+}
+
+// Inserts a record read from a snapshot, skipping it if its deadline has
+// already passed
+fn insert(records: &mut Records, expiries: &mut Expiries, k: Bytes, v: Bytes, millis: u64) {
+    if millis != 0 {
+        match expiry::from_unix_millis(millis) {
+            Some(deadline) => {
+                expiries.insert(k.clone(), deadline);
+            }
+            None => return,
+        }
+    }
+    records.insert(k, v);
+}
+
+fn write_chunk(w: &mut impl Write, chunk: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(chunk.len()).map_err(|_| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            "record is too large for a snapshot",
+        )
+    })?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(chunk)
+}
+
+// Reads one length-prefixed chunk, returning `None` on a clean end of file
+fn read_chunk(r: &mut impl Read, what: &str) -> io::Result<Option<Bytes>> {
+    let mut len = [0; 4];
+    let mut filled = 0;
+    while filled < len.len() {
+        match r.read(&mut len[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(truncated(&format!("{what} length"))),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    // Read through `take` rather than preallocating so a corrupt length
+    // can't trigger a huge allocation
+    let len = u32::from_le_bytes(len) as u64;
+    let mut chunk = Vec::new();
+    r.take(len).read_to_end(&mut chunk)?;
+    if (chunk.len() as u64) < len {
+        return Err(truncated(what));
+    }
+    Ok(Some(Bytes::from(chunk)))
+}
+
+fn truncated(what: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::UnexpectedEof,
+        format!("snapshot is truncated: incomplete {what}"),
+    )
+}
+
+fn corrupt(what: String) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("snapshot is corrupt: {what}"),
+    )
+}
+
+fn read_exact(r: &mut impl Read, buf: &mut [u8], what: &str) -> io::Result<()> {
+    r.read_exact(buf).map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof => truncated(what),
+        _ => err,
+    })
+}
+
+fn read_u32(r: &mut impl Read, what: &str) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    read_exact(r, &mut buf, what)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read, what: &str) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    read_exact(r, &mut buf, what)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+// A reader or writer that checksums every byte passing through it, both for
+// the whole file and for the record being read or written, and counts them
+struct Checked<T> {
+    inner: T,
+    file: Crc32,
+    record: Crc32,
+    offset: u64,
+}
+
+impl<T> Checked<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            file: Crc32::new(),
+            record: Crc32::new(),
+            offset: 0,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.file.update(bytes);
+        self.record.update(bytes);
+        self.offset += bytes.len() as u64;
+    }
+}
+
+impl<R: Read> Read for Checked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Checked<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackupSummary, Store};
+    use crate::{MergeStats, MergeStrategy};
+    use bytes::Bytes;
+    use std::{
+        env, fs,
+        io::{Cursor, ErrorKind},
+        path::PathBuf,
+        process, thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn round_trip_empty() {
+        let path = temp_path("empty");
+        Store::new().save_to_path(&path).unwrap();
+
+        assert!(Store::load_from_path(&path).unwrap().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn round_trip_through_memory() {
+        let store = Store::new();
+        store.set(Bytes::from("k"), Bytes::from("v"));
+        store.set_with_ttl(
+            Bytes::from("ttl"),
+            Bytes::from("v"),
+            Duration::from_secs(60),
+        );
+
+        let mut dump = Vec::new();
+        store.dump(&mut dump).unwrap();
+        let loaded = Store::load(&mut Cursor::new(dump)).unwrap();
+
+        assert_eq!(loaded, store);
+        assert!(loaded.ttl(&Bytes::from("ttl")).is_some());
+    }
+
+    #[test]
+    fn round_trip_binary_and_large() {
+        let path = temp_path("binary");
+        let store = Store::new();
+        let binary = Bytes::from(vec![0, 159, 146, 150, 255]);
+        let large = Bytes::from(vec![7; 70 * 1024]);
+        store.set(binary.clone(), Bytes::from(vec![255, 0, 254]));
+        store.set(Bytes::from("large"), large.clone());
+        store.set(Bytes::from("empty"), Bytes::new());
+        store.save_to_path(&path).unwrap();
+
+        let loaded = Store::load_from_path(&path).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.get(binary), Some(Bytes::from(vec![255, 0, 254])));
+        assert_eq!(loaded.get(Bytes::from("large")), Some(large));
+        assert_eq!(loaded.get(Bytes::from("empty")), Some(Bytes::new()));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn round_trip_populated() {
+        let path = temp_path("populated");
+        let store = Store::new();
+        for n in 1..=5 {
+            store.set(
+                Bytes::from(format!("hello{n}")),
+                Bytes::from(format!("world{n}")),
+            );
+        }
+        store.save_to_path(&path).unwrap();
+
+        let loaded = Store::load_from_path(&path).unwrap();
+        assert_eq!(loaded.len(), 5);
+        for n in 1..=5 {
+            let k = Bytes::from(format!("hello{n}"));
+            assert_eq!(loaded.get(k.clone()), store.get(k));
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_files_are_rejected() {
+        let path = temp_path("corrupt");
+        let store = Store::new();
+        store.set(Bytes::from("hello"), Bytes::from("world"));
+        store.save_to_path(&path).unwrap();
+        let snapshot = fs::read(&path).unwrap();
+
+        fs::write(&path, &snapshot[..snapshot.len() - 2]).unwrap();
+        let err = Store::load_from_path(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        fs::write(&path, &snapshot[..3]).unwrap();
+        let err = Store::load_from_path(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        fs::write(&path, b"not a snapshot").unwrap();
+        let err = Store::load_from_path(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn round_trip_keeps_ttls() {
+        let path = temp_path("ttl");
+        let store = Store::new();
+        store.set_with_ttl(
+            Bytes::from("live"),
+            Bytes::from("v"),
+            Duration::from_secs(60),
+        );
+        store.set_with_ttl(
+            Bytes::from("dying"),
+            Bytes::from("v"),
+            Duration::from_millis(10),
+        );
+        thread::sleep(Duration::from_millis(20));
+        store.save_to_path(&path).unwrap();
+
+        let loaded = Store::load_from_path(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded.ttl(&Bytes::from("live")).unwrap() > Duration::from_secs(58));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn loads_version_one() {
+        let path = temp_path("v1");
+        let mut snapshot = b"KVS\0\x01".to_vec();
+        for chunk in [&b"hello"[..], b"world"] {
+            snapshot.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            snapshot.extend_from_slice(chunk);
+        }
+        fs::write(&path, snapshot).unwrap();
+
+        let loaded = Store::load_from_path(&path).unwrap();
+        assert_eq!(loaded.get(Bytes::from("hello")), Some(Bytes::from("world")));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn checksums_catch_flipped_bytes() {
+        // Every record is 24 bytes, so record `n` starts at byte `13 + 24 * n`
+        // whatever order the records are written in
+        let store: Store = (0..3).map(|n| (format!("k{n}"), format!("v{n}"))).collect();
+        let mut snapshot = Vec::new();
+        store.dump(&mut snapshot).unwrap();
+        assert_eq!(snapshot.len(), 13 + 3 * 24 + 4);
+
+        let load = |flip: usize| {
+            let mut corrupt = snapshot.clone();
+            corrupt[flip] ^= 1;
+            Store::load(&mut Cursor::new(corrupt)).unwrap_err()
+        };
+        let err = load(37 + 11);
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "snapshot is corrupt: record 1 at byte 37 fails its checksum"
+        );
+        assert_eq!(
+            load(85).to_string(),
+            "snapshot is corrupt: file checksum at byte 85 doesn't match"
+        );
+        // The record count is only covered by the file checksum
+        assert_eq!(load(5).kind(), ErrorKind::InvalidData);
+
+        snapshot.push(0);
+        let err = Store::load(&mut Cursor::new(snapshot)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "snapshot is corrupt: unexpected data after the footer at byte 89"
+        );
+    }
+
+    #[test]
+    fn loads_version_two() {
+        let mut snapshot = b"KVS\0\x02".to_vec();
+        for chunk in [&b"hello"[..], b"world"] {
+            snapshot.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            snapshot.extend_from_slice(chunk);
+        }
+        snapshot.extend_from_slice(&0_u64.to_le_bytes());
+
+        let loaded = Store::load(&mut Cursor::new(snapshot)).unwrap();
+        assert_eq!(loaded.get(Bytes::from("hello")), Some(Bytes::from("world")));
+    }
+
+    #[test]
+    fn backup_and_restore_through_memory() {
+        let store: Store = [("a", "1"), ("b", "22")].into_iter().collect();
+        let mut backup = Vec::new();
+        let summary = store.backup_to(&mut backup).unwrap();
+        assert_eq!(
+            summary,
+            BackupSummary {
+                entries: 2,
+                bytes: backup.len() as u64,
+            }
+        );
+        assert_eq!(Store::restore_from(backup.as_slice()).unwrap(), store);
+
+        let err = Store::restore_from(&backup[..backup.len() - 6]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn restore_into_settles_conflicts() {
+        let backup: Store = [("shared", "backup"), ("new", "backup")]
+            .into_iter()
+            .collect();
+        let mut bytes = Vec::new();
+        backup.backup_to(&mut bytes).unwrap();
+
+        let store: Store = [("shared", "live"), ("mine", "live")].into_iter().collect();
+        let stats = store
+            .restore_into(bytes.as_slice(), MergeStrategy::KeepExisting)
+            .unwrap();
+        assert_eq!(
+            stats,
+            MergeStats {
+                inserted: 1,
+                conflicts: 1,
+            }
+        );
+        assert_eq!(store.get(Bytes::from("shared")), Some(Bytes::from("live")));
+        assert_eq!(store.get(Bytes::from("new")), Some(Bytes::from("backup")));
+
+        store
+            .restore_into(bytes.as_slice(), MergeStrategy::TakeOther)
+            .unwrap();
+        assert_eq!(
+            store.get(Bytes::from("shared")),
+            Some(Bytes::from("backup"))
+        );
+        assert_eq!(store.len(), 3);
+
+        // A backup that fails to read changes nothing
+        store.set(Bytes::from("shared"), Bytes::from("live"));
+        assert!(store
+            .restore_into(&bytes[..10], MergeStrategy::TakeOther)
+            .is_err());
+        assert_eq!(store.get(Bytes::from("shared")), Some(Bytes::from("live")));
+    }
+
+    #[test]
+    fn restore_into_keeps_ttls() {
+        let backup = Store::new();
+        backup.set(Bytes::from("forever"), Bytes::from("1"));
+        backup.set_with_ttl(
+            Bytes::from("brief"),
+            Bytes::from("2"),
+            Duration::from_secs(60),
+        );
+        let mut bytes = Vec::new();
+        backup.backup_to(&mut bytes).unwrap();
+
+        let store = Store::new();
+        store
+            .restore_into(bytes.as_slice(), MergeStrategy::TakeOther)
+            .unwrap();
+        assert_eq!(store.ttl(&Bytes::from("forever")), None);
+        let ttl = store.ttl(&Bytes::from("brief")).unwrap();
+        assert!(ttl > Duration::from_secs(50) && ttl <= Duration::from_secs(60));
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("kvs-persist-{}-{name}", process::id()))
+    }
+}
@@ -0,0 +1,441 @@
+//! A subset of the RESP2 wire protocol, so existing Redis clients can talk to
+//! a [`Store`].
+//!
+//! Requests are arrays of bulk strings, or inline commands of
+//! whitespace-separated words on one line as typed into a terminal. The
+//! supported commands are:
+//!
+//! - `GET key`, replying with a bulk string or `$-1`
+//! - `SET key value`, replying `+OK`
+//! - `DEL key [key ...]` and `EXISTS key [key ...]`, replying with how many of
+//!   the keys were removed or present
+//! - `KEYS pattern`, replying with the matching keys in byte order. Patterns
+//!   are Redis globs: `*`, `?`, `[abc]`, `[^a-z]` and `\` escapes
+//! - `PING [message]`, replying `+PONG` or echoing the message
+//!
+//! Anything else gets a `-ERR` error reply.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use bytes::Bytes;
+
+use crate::{server, Store};
+
+// Longest `*<n>` or `$<len>` header line accepted, excluding the CRLF
+const MAX_HEADER: usize = 20;
+
+// Longest inline command accepted, excluding the line ending
+const MAX_INLINE: usize = 64 * 1024;
+
+// Longest bulk string accepted, the same as Redis's default
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+// Most arguments a command may have
+const MAX_ARGS: usize = 1024 * 1024;
+
+/// Listens on `addr` and serves `store` over RESP to every client, each on its
+/// own thread. Only returns if binding or accepting fails.
+pub fn serve(store: Store, addr: impl ToSocketAddrs) -> io::Result<()> {
+    serve_on(store, TcpListener::bind(addr)?)
+}
+
+/// Like [`serve`], but on a listener that is already bound.
+pub fn serve_on(store: Store, listener: TcpListener) -> io::Result<()> {
+    server::accept(store, listener, handle)
+}
+
+/// Parses one command from the front of `buf`, returning its arguments and how
+/// many bytes it took up, or `None` if `buf` doesn't hold a whole command yet.
+///
+/// Fails with [`ErrorKind::InvalidData`] if `buf` isn't valid RESP.
+pub fn parse(buf: &[u8]) -> io::Result<Option<(Vec<Bytes>, usize)>> {
+    match buf.first() {
+        None => return Ok(None),
+        Some(b'*') => {}
+        Some(_) => return parse_inline(buf),
+    }
+
+    let mut pos = 0;
+    let Some(n) = header(buf, &mut pos, b'*')? else {
+        return Ok(None);
+    };
+    if n > MAX_ARGS {
+        return Err(invalid("invalid length"));
+    }
+
+    let mut args = Vec::new();
+    for _ in 0..n {
+        let Some(len) = header(buf, &mut pos, b'$')? else {
+            return Ok(None);
+        };
+        if len > MAX_BULK_LEN {
+            return Err(invalid("invalid length"));
+        }
+        let end = len
+            .checked_add(pos + 2)
+            .ok_or_else(|| invalid("invalid length"))?;
+        let Some(arg) = buf.get(pos..end) else {
+            return Ok(None);
+        };
+        if !arg.ends_with(b"\r\n") {
+            return Err(invalid("bulk string is not terminated by CRLF"));
+        }
+        args.push(Bytes::copy_from_slice(&arg[..len]));
+        pos = end;
+    }
+    Ok(Some((args, pos)))
+}
+
+/// Runs a parsed command against `store`, returning the encoded reply.
+pub fn dispatch(store: &Store, args: &[Bytes]) -> Vec<u8> {
+    let Some(command) = args.first() else {
+        return error("empty command");
+    };
+
+    match (command.to_ascii_uppercase().as_slice(), &args[1..]) {
+        (b"GET", [k]) => match store.try_get(k.clone()) {
+            Ok(Some(v)) => bulk(&v),
+            Ok(None) => b"$-1\r\n".to_vec(),
+            Err(err) => error(&err.to_string()),
+        },
+        (b"SET", [k, v]) => match store.try_set(k.clone(), v.clone()) {
+            Ok(_) => b"+OK\r\n".to_vec(),
+            Err(err) => error(&err.to_string()),
+        },
+        (b"DEL", keys) if !keys.is_empty() => {
+            let mut removed = 0;
+            for k in keys {
+                match store.try_remove(k.clone()) {
+                    Ok(prev) => removed += prev.is_some() as usize,
+                    Err(err) => return error(&err.to_string()),
+                }
+            }
+            format!(":{removed}\r\n").into_bytes()
+        }
+        (b"EXISTS", keys) if !keys.is_empty() => {
+            let mut present = 0;
+            for k in keys {
+                match store.try_get(k.clone()) {
+                    Ok(v) => present += v.is_some() as usize,
+                    Err(err) => return error(&err.to_string()),
+                }
+            }
+            format!(":{present}\r\n").into_bytes()
+        }
+        (b"KEYS", [pattern]) => {
+            let mut keys = store.keys();
+            keys.retain(|k| glob(pattern, k));
+            keys.sort();
+            let mut reply = format!("*{}\r\n", keys.len()).into_bytes();
+            for k in keys {
+                reply.extend(bulk(&k));
+            }
+            reply
+        }
+        (b"PING", []) => b"+PONG\r\n".to_vec(),
+        (b"PING", [message]) => bulk(message),
+        (b"GET" | b"SET" | b"DEL" | b"EXISTS" | b"KEYS" | b"PING", _) => error(&format!(
+            "wrong number of arguments for '{}' command",
+            String::from_utf8_lossy(command).to_lowercase()
+        )),
+        _ => error("unknown command"),
+    }
+}
+
+// Answers commands from one client until it disconnects or breaks the protocol
+fn handle(store: &Store, mut stream: TcpStream) -> io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        loop {
+            match parse(&buf) {
+                Ok(Some((args, used))) => {
+                    buf.drain(..used);
+                    stream.write_all(&dispatch(store, &args))?;
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    stream.write_all(&error(&format!("Protocol error: {err}")))?;
+                    return Err(err);
+                }
+            }
+        }
+
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+// Splits one line at the front of `buf` into words, as `redis-cli` and
+// `telnet` send them
+fn parse_inline(buf: &[u8]) -> io::Result<Option<(Vec<Bytes>, usize)>> {
+    let Some(end) = buf.iter().position(|&b| b == b'\n') else {
+        return match buf.len() > MAX_INLINE {
+            true => Err(invalid("inline command is too long")),
+            false => Ok(None),
+        };
+    };
+    let args = buf[..end]
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(Bytes::copy_from_slice)
+        .collect();
+    Ok(Some((args, end + 1)))
+}
+
+// Whether `text` matches the Redis glob `pattern`. A `*` remembers where it
+// was, so a mismatch later on retries with it covering one more byte
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some(_) => {
+                if let Some(next) = glob_token(pattern, p, text[t]) {
+                    p = next;
+                    t += 1;
+                    continue;
+                }
+            }
+            None => {}
+        }
+        match star {
+            Some((star_p, star_t)) => {
+                star = Some((star_p, star_t + 1));
+                p = star_p + 1;
+                t = star_t + 1;
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+// Matches `b` against the literal, escape or class at `pattern[p]`, returning
+// where the next token starts if it matches
+fn glob_token(pattern: &[u8], p: usize, b: u8) -> Option<usize> {
+    match pattern[p] {
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == b).then_some(p + 2),
+        b'[' => {
+            let mut i = p + 1;
+            let negated = pattern.get(i) == Some(&b'^');
+            if negated {
+                i += 1;
+            }
+            let mut matched = false;
+            loop {
+                match pattern.get(i) {
+                    // An unterminated class is just a bracket
+                    None => return (b == b'[').then_some(p + 1),
+                    Some(b']') => break,
+                    Some(b'\\') if i + 1 < pattern.len() => {
+                        matched |= pattern[i + 1] == b;
+                        i += 2;
+                    }
+                    Some(&lo)
+                        if pattern.get(i + 1) == Some(&b'-')
+                            && i + 2 < pattern.len()
+                            && pattern[i + 2] != b']' =>
+                    {
+                        let hi = pattern[i + 2];
+                        matched |= (lo.min(hi)..=lo.max(hi)).contains(&b);
+                        i += 3;
+                    }
+                    Some(&c) => {
+                        matched |= c == b;
+                        i += 1;
+                    }
+                }
+            }
+            (matched != negated).then_some(i + 1)
+        }
+        c => (c == b).then_some(p + 1),
+    }
+}
+
+// Reads a `<prefix><count>\r\n` line at `pos`, advancing past it
+fn header(buf: &[u8], pos: &mut usize, prefix: u8) -> io::Result<Option<usize>> {
+    let Some(&first) = buf.get(*pos) else {
+        return Ok(None);
+    };
+    if first != prefix {
+        return Err(invalid(&format!("expected '{}'", prefix as char)));
+    }
+
+    let rest = &buf[*pos + 1..];
+    let Some(end) = rest.windows(2).position(|w| w == b"\r\n") else {
+        return match rest.len() > MAX_HEADER {
+            true => Err(invalid("header is too long")),
+            false => Ok(None),
+        };
+    };
+    let count = std::str::from_utf8(&rest[..end])
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| invalid("invalid length"))?;
+    *pos += 1 + end + 2;
+    Ok(Some(count))
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, reason.to_string())
+}
+
+fn bulk(v: &[u8]) -> Vec<u8> {
+    let mut reply = format!("${}\r\n", v.len()).into_bytes();
+    reply.extend_from_slice(v);
+    reply.extend_from_slice(b"\r\n");
+    reply
+}
+
+fn error(message: &str) -> Vec<u8> {
+    format!("-ERR {message}\r\n").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dispatch, glob, parse};
+    use crate::Store;
+
+    // Parses and dispatches every command in `input`, concatenating the replies
+    fn run(store: &Store, mut input: &[u8]) -> Vec<u8> {
+        let mut replies = Vec::new();
+        while let Some((args, used)) = parse(input).unwrap() {
+            replies.extend(dispatch(store, &args));
+            input = &input[used..];
+        }
+        assert!(input.is_empty());
+        replies
+    }
+
+    #[test]
+    fn get_set_del() {
+        let store = Store::new();
+        let replies = run(
+            &store,
+            b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n\
+              *3\r\n$3\r\nset\r\n$1\r\nk\r\n$4\r\n\r\n\0\xff\r\n\
+              *2\r\n$3\r\nGET\r\n$1\r\nk\r\n\
+              *3\r\n$3\r\nDEL\r\n$1\r\nk\r\n$7\r\nmissing\r\n",
+        );
+        assert_eq!(
+            replies,
+            b"$-1\r\n+OK\r\n$4\r\n\r\n\0\xff\r\n:1\r\n".to_vec()
+        );
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let store = Store::new();
+        let replies = run(&store, b"*1\r\n$4\r\nPUSH\r\n*1\r\n$3\r\nGET\r\n*0\r\n");
+        assert_eq!(
+            replies,
+            b"-ERR unknown command\r\n\
+              -ERR wrong number of arguments for 'get' command\r\n\
+              -ERR empty command\r\n"
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn waits_for_whole_command() {
+        let command = b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n";
+        for end in 0..command.len() {
+            assert!(parse(&command[..end]).unwrap().is_none());
+        }
+        assert_eq!(parse(command).unwrap().unwrap().1, command.len());
+    }
+
+    #[test]
+    fn parses_inline_commands() {
+        let store = Store::new();
+        let replies = run(
+            &store,
+            b"SET  k v\r\nPING\nEXISTS k missing k\r\nKEYS *\r\nping hello\r\n",
+        );
+        assert_eq!(
+            replies,
+            b"+OK\r\n+PONG\r\n:2\r\n*1\r\n$1\r\nk\r\n$5\r\nhello\r\n".to_vec()
+        );
+        assert!(parse(b"GET k").unwrap().is_none());
+    }
+
+    #[test]
+    fn keys_matches_globs() {
+        let store = Store::new();
+        for k in ["hello", "hallo", "hxllo", "heeeello", "h*llo", "world"] {
+            store.set(k.into(), "v".into());
+        }
+        let keys = |pattern: &[u8]| {
+            let mut command = format!("*2\r\n$4\r\nKEYS\r\n${}\r\n", pattern.len()).into_bytes();
+            command.extend_from_slice(pattern);
+            command.extend_from_slice(b"\r\n");
+            String::from_utf8(run(&store, &command)).unwrap()
+        };
+
+        assert_eq!(keys(b"w*"), "*1\r\n$5\r\nworld\r\n");
+        assert_eq!(keys(b"nothing*"), "*0\r\n");
+        assert_eq!(
+            keys(b"h?llo"),
+            "*4\r\n$5\r\nh*llo\r\n$5\r\nhallo\r\n$5\r\nhello\r\n$5\r\nhxllo\r\n"
+        );
+    }
+
+    #[test]
+    fn glob_patterns() {
+        for (pattern, text, expected) in [
+            ("*", "", true),
+            ("h*llo", "heeeello", true),
+            ("h*llo", "hell", false),
+            ("*a*b", "xaxxb", true),
+            ("*a*b", "xaxxbx", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[a-b]llo", "hcllo", false),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("h[llo", "h[llo", true),
+        ] {
+            assert_eq!(
+                glob(pattern.as_bytes(), text.as_bytes()),
+                expected,
+                "{pattern} against {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_framing() {
+        assert!(parse(&[b'x'; 128 * 1024]).is_err());
+        assert!(parse(b"*1\r\n$1\r\nkX\r\n").is_err());
+        assert!(parse(b"*x\r\n").is_err());
+        assert!(parse(&[b'*'; 64]).is_err());
+
+        // Lengths are capped before waiting for that many bytes to arrive
+        let err = parse(b"*1\r\n$536870913\r\n").unwrap_err();
+        assert_eq!(err.to_string(), "invalid length");
+        assert!(parse(b"*1\r\n$536870912\r\n").unwrap().is_none());
+        assert!(parse(b"*1048577\r\n").is_err());
+        assert!(parse(b"*1048576\r\n").unwrap().is_none());
+    }
+}
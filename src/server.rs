@@ -0,0 +1,101 @@
+//! A TCP front end speaking a line protocol, so a [`Store`] can run as a
+//! standalone daemon.
+//!
+//! Every request and response is a single `\n`-terminated line, with keys and
+//! values base64-encoded so arbitrary bytes are safe on the wire:
+//!
+//! | Request             | Response                         |
+//! |---------------------|----------------------------------|
+//! | `GET <key>`         | `VALUE <value>` or `NOT_FOUND`   |
+//! | `SET <key> <value>` | `OK`                             |
+//! | `DEL <key>`         | `OK` or `NOT_FOUND`              |
+//!
+//! A request that can't be parsed or applied gets `ERROR <reason>`, and the
+//! connection stays open.
+
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+
+use crate::Store;
+
+/// Listens on `addr` and serves `store` to every client, each on its own
+/// thread. Only returns if binding or accepting fails.
+pub fn serve(store: Store, addr: impl ToSocketAddrs) -> io::Result<()> {
+    serve_on(store, TcpListener::bind(addr)?)
+}
+
+/// Like [`serve`], but on a listener that is already bound, e.g. to learn
+/// which port an ephemeral bind picked.
+pub fn serve_on(store: Store, listener: TcpListener) -> io::Result<()> {
+    accept(store, listener, handle)
+}
+
+// Hands every client that connects to `listener` to `handle` on its own thread
+pub(crate) fn accept(
+    store: Store,
+    listener: TcpListener,
+    handle: fn(&Store, TcpStream) -> io::Result<()>,
+) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept()?;
+        let store = store.clone();
+        thread::spawn(move || {
+            // A client going away mid-request only ends its own connection
+            let _ = handle(&store, stream);
+        });
+    }
+}
+
+// Answers requests from one client until it disconnects
+fn handle(store: &Store, stream: TcpStream) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    for line in reader.lines() {
+        let response = match respond(store, &line?) {
+            Ok(response) => response,
+            Err(reason) => format!("ERROR {reason}"),
+        };
+        writeln!(writer, "{response}")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn respond(store: &Store, line: &str) -> Result<String, String> {
+    let words: Vec<&str> = line.split_ascii_whitespace().collect();
+    let response = match words[..] {
+        ["GET", k] => match store.try_get(decode(k)?).map_err(|e| e.to_string())? {
+            Some(v) => format!("VALUE {}", STANDARD.encode(v)),
+            None => "NOT_FOUND".to_string(),
+        },
+        ["SET", k, v] => {
+            store
+                .try_set(decode(k)?, decode(v)?)
+                .map_err(|e| e.to_string())?;
+            "OK".to_string()
+        }
+        ["DEL", k] => match store.try_remove(decode(k)?).map_err(|e| e.to_string())? {
+            Some(_) => "OK".to_string(),
+            None => "NOT_FOUND".to_string(),
+        },
+        [] => return Err("empty request".to_string()),
+        [command @ ("GET" | "SET" | "DEL"), ..] => {
+            return Err(format!("wrong number of arguments to {command}"))
+        }
+        [command, ..] => return Err(format!("unknown command {command}")),
+    };
+    Ok(response)
+}
+
+fn decode(word: &str) -> Result<Bytes, String> {
+    STANDARD
+        .decode(word)
+        .map(Bytes::from)
+        .map_err(|_| format!("{word} is not valid base64"))
+}
@@ -0,0 +1,431 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    fs::File,
+    hash::BuildHasher,
+    io::{self, BufReader, BufWriter},
+    num::NonZeroUsize,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+
+use bytes::Bytes;
+
+use crate::{persist, Expiries, Records, StoreError};
+
+/// A key value store that spreads its records across several independently
+/// locked shards, so writers to different keys rarely contend.
+///
+/// Operations on a single key only lock that key's shard. Like [`Store`],
+/// cloning a `ShardedStore` shares the same records.
+///
+/// [`Store`]: crate::Store
+#[derive(Clone)]
+pub struct ShardedStore(Arc<Shards>);
+
+struct Shards {
+    shards: Box<[Mutex<Records>]>,
+    hasher: RandomState,
+}
+
+impl ShardedStore {
+    /// Creates a store with one shard per available CPU.
+    pub fn new() -> Self {
+        Self::with_shards(thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+
+    /// Creates a store with `n` shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn with_shards(n: usize) -> Self {
+        assert!(n > 0, "a sharded store needs at least one shard");
+        Self(Arc::new(Shards {
+            shards: (0..n).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+        }))
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.0.shards.len()
+    }
+
+    pub fn get(&self, k: Bytes) -> Option<Bytes> {
+        self.try_get(k).unwrap_or_default()
+    }
+
+    /// Inserts a value, returning the one it replaced.
+    pub fn set(&self, k: Bytes, v: Bytes) -> Option<Bytes> {
+        self.try_set(k, v).unwrap_or_default()
+    }
+
+    /// Removes a value, returning it if it was present.
+    pub fn remove(&self, k: Bytes) -> Option<Bytes> {
+        self.try_remove(k).unwrap_or_default()
+    }
+
+    /// Like [`ShardedStore::get`], but reports a poisoned shard instead of
+    /// returning `None`.
+    pub fn try_get(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        Ok(self.shard(&k)?.get(&k).cloned())
+    }
+
+    /// Like [`ShardedStore::set`], but reports a poisoned shard instead of
+    /// returning `None`.
+    pub fn try_set(&self, k: Bytes, v: Bytes) -> Result<Option<Bytes>, StoreError> {
+        Ok(self.shard(&k)?.insert(k, v))
+    }
+
+    /// Like [`ShardedStore::remove`], but reports a poisoned shard instead of
+    /// returning `None`.
+    pub fn try_remove(&self, k: Bytes) -> Result<Option<Bytes>, StoreError> {
+        Ok(self.shard(&k)?.remove(&k))
+    }
+
+    /// Returns `false` if the key is absent or its shard can't be locked.
+    pub fn contains_key(&self, k: &Bytes) -> bool {
+        self.shard(k)
+            .map(|shard| shard.contains_key(k))
+            .unwrap_or_default()
+    }
+
+    /// Counts the records by locking one shard at a time, so concurrent writes
+    /// to other shards may or may not be reflected. Poisoned shards count as
+    /// empty.
+    pub fn len(&self) -> usize {
+        (0..self.shard_count())
+            .filter_map(|i| self.lock(i).ok())
+            .map(|shard| shard.len())
+            .sum()
+    }
+
+    /// Like [`ShardedStore::len`], this is only weakly consistent.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Looks up every key as one consistent snapshot, holding the lock of each
+    /// shard that owns one of them at once. The result is aligned with `keys`,
+    /// duplicates included, with `None` for absent keys. Returns all `None` if
+    /// a shard is poisoned.
+    pub fn get_many(&self, keys: &[Bytes]) -> Vec<Option<Bytes>> {
+        let groups = self.group(keys);
+        let Ok(shards) = self.lock_shards(groups.keys()) else {
+            return vec![None; keys.len()];
+        };
+        let mut found = vec![None; keys.len()];
+        for (group, shard) in groups.values().zip(shards) {
+            for (k, positions) in group {
+                let v = shard.get(*k);
+                for &i in positions {
+                    found[i] = v.cloned();
+                }
+            }
+        }
+        found
+    }
+
+    /// Removes every key, holding the lock of each shard that owns one of
+    /// them at once, so no reader sees some removed and others not.
+    pub fn remove_many(&self, keys: &[Bytes]) -> Result<(), StoreError> {
+        let groups = self.group(keys);
+        let shards = self.lock_shards(groups.keys())?;
+        for (group, mut shard) in groups.values().zip(shards) {
+            for k in group.keys() {
+                shard.remove(*k);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes every record, holding every shard's lock at once.
+    pub fn clear(&self) -> Result<(), StoreError> {
+        self.lock_all()?.iter_mut().for_each(|shard| shard.clear());
+        Ok(())
+    }
+
+    /// Copies out every record as one consistent snapshot, holding every
+    /// shard's lock at once.
+    pub fn entries(&self) -> Result<Vec<(Bytes, Bytes)>, StoreError> {
+        Ok(self
+            .lock_all()?
+            .iter()
+            .flat_map(|shard| shard.iter().map(|(k, v)| (k.clone(), v.clone())))
+            .collect())
+    }
+
+    /// Writes a consistent snapshot in the same format as
+    /// [`Store::save_to_path`](crate::Store::save_to_path).
+    pub fn save_to_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let records: Records = self
+            .entries()
+            .map_err(io::Error::other)?
+            .into_iter()
+            .collect();
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        persist::write_records(&mut writer, &records, &Expiries::new())?;
+        writer.into_inner()?.sync_all()
+    }
+
+    /// Restores a store with the default shard count from a snapshot. A
+    /// sharded store has no TTLs, so entries that hadn't expired yet when the
+    /// snapshot was taken are loaded without one.
+    pub fn load_from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (records, _) = persist::read_records(&mut reader)?;
+
+        let store = Self::new();
+        for (k, v) in records {
+            store.try_set(k, v).map_err(io::Error::other)?;
+        }
+        Ok(store)
+    }
+
+    // Locks the shard that owns `k`
+    fn shard(&self, k: &Bytes) -> Result<MutexGuard<'_, Records>, StoreError> {
+        self.lock(self.index(k))
+    }
+
+    // Locks the shard at index `i`
+    fn lock(&self, i: usize) -> Result<MutexGuard<'_, Records>, StoreError> {
+        self.0.shards[i].lock().map_err(StoreError::from)
+    }
+
+    // The index of the shard that owns `k`
+    fn index(&self, k: &Bytes) -> usize {
+        self.0.hasher.hash_one(k) as usize % self.0.shards.len()
+    }
+
+    // Groups each distinct key by the shard that owns it, along with the
+    // positions it appears at in `keys`
+    fn group<'a>(&self, keys: &'a [Bytes]) -> BTreeMap<usize, HashMap<&'a Bytes, Vec<usize>>> {
+        let mut groups: BTreeMap<_, HashMap<_, Vec<_>>> = BTreeMap::new();
+        for (i, k) in keys.iter().enumerate() {
+            groups
+                .entry(self.index(k))
+                .or_default()
+                .entry(k)
+                .or_default()
+                .push(i);
+        }
+        groups
+    }
+
+    // Locks each of `indices`, which must be ascending, in that order, so
+    // concurrent callers can't deadlock
+    fn lock_shards<'a>(
+        &self,
+        indices: impl Iterator<Item = &'a usize>,
+    ) -> Result<Vec<MutexGuard<'_, Records>>, StoreError> {
+        indices.map(|&i| self.lock(i)).collect()
+    }
+
+    // Locks every shard in index order, so concurrent callers can't deadlock
+    fn lock_all(&self) -> Result<Vec<MutexGuard<'_, Records>>, StoreError> {
+        (0..self.shard_count()).map(|i| self.lock(i)).collect()
+    }
+}
+
+impl Default for ShardedStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedStore;
+    use bytes::Bytes;
+    use std::{
+        env, fs, process,
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn get_set_remove() {
+        let store = ShardedStore::with_shards(4);
+        assert_eq!(store.shard_count(), 4);
+
+        store.set(Bytes::from("hello"), Bytes::from("world"));
+        assert_eq!(store.get(Bytes::from("hello")), Some(Bytes::from("world")));
+        assert!(store.contains_key(&Bytes::from("hello")));
+
+        store.remove(Bytes::from("hello"));
+        assert_eq!(store.get(Bytes::from("hello")), None);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn disjoint_writers_lose_nothing() {
+        let store = ShardedStore::with_shards(8);
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for n in 0..1000 {
+                        let v = Bytes::from(format!("{t}:{n}"));
+                        store.set(Bytes::from(format!("{t}:{n}")), v);
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        assert_eq!(store.len(), 8000);
+        for t in 0..8 {
+            for n in 0..1000 {
+                let k = Bytes::from(format!("{t}:{n}"));
+                assert_eq!(store.get(k.clone()), Some(k));
+            }
+        }
+    }
+
+    #[test]
+    fn clear_and_entries_span_shards() {
+        let store = ShardedStore::with_shards(3);
+        for n in 0..30 {
+            store.set(Bytes::from(format!("k{n}")), Bytes::from("v"));
+        }
+
+        let mut entries = store.entries().unwrap();
+        entries.sort();
+        assert_eq!(entries.len(), 30);
+        assert_eq!(entries[0], (Bytes::from("k0"), Bytes::from("v")));
+
+        store.clear().unwrap();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn single_shard_matches_default() {
+        let (single, default) = (ShardedStore::with_shards(1), ShardedStore::new());
+        for store in [&single, &default] {
+            for n in 0..50 {
+                store.set(
+                    Bytes::from(format!("k{}", n % 20)),
+                    Bytes::from(n.to_string()),
+                );
+                if n % 7 == 0 {
+                    store.remove(Bytes::from(format!("k{}", n % 5)));
+                }
+            }
+        }
+
+        let (mut a, mut b) = (single.entries().unwrap(), default.entries().unwrap());
+        a.sort();
+        b.sort();
+        assert_eq!(a, b);
+        assert_eq!(single.len(), default.len());
+        assert_eq!(
+            single.try_remove(Bytes::from("k19")),
+            default.try_remove(Bytes::from("k19"))
+        );
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture` to compare write
+    // throughput across shard counts
+    #[test]
+    #[ignore]
+    fn write_throughput() {
+        for shards in [1, 4, 16] {
+            let store = ShardedStore::with_shards(shards);
+            let start = Instant::now();
+            let handles: Vec<_> = (0..8)
+                .map(|t| {
+                    let store = store.clone();
+                    thread::spawn(move || {
+                        for n in 0..50_000 {
+                            store.set(Bytes::from(format!("{t}:{n}")), Bytes::from_static(b"v"));
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().for_each(|h| h.join().unwrap());
+
+            assert_eq!(store.len(), 8 * 50_000);
+            let rate = 8.0 * 50_000.0 / start.elapsed().as_secs_f64();
+            println!("{shards} shards: {rate:.0} sets/s");
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let path = env::temp_dir().join(format!("kvs-sharded-{}", process::id()));
+        let store = ShardedStore::with_shards(4);
+        for n in 0..20 {
+            store.set(Bytes::from(format!("k{n}")), Bytes::from(format!("v{n}")));
+        }
+        store.save_to_path(&path).unwrap();
+
+        let loaded = ShardedStore::load_from_path(&path).unwrap();
+        assert_eq!(loaded.len(), 20);
+        assert_eq!(loaded.get(Bytes::from("k7")), Some(Bytes::from("v7")));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn many_keys_lock_each_shard_once() {
+        let store = ShardedStore::with_shards(16);
+        for n in 0..8 {
+            store.set(Bytes::from(format!("k{n}")), Bytes::from(format!("v{n}")));
+        }
+        let keys: Vec<Bytes> = ["k1", "k2", "missing", "k1", "k7", "k2"]
+            .into_iter()
+            .map(Bytes::from)
+            .collect();
+
+        let groups = store.group(&keys);
+        assert!(groups.len() <= store.shard_count());
+        let distinct: usize = groups.values().map(|group| group.len()).sum();
+        assert_eq!(distinct, 4);
+
+        // The shards no key belongs to stay locked throughout, so a batch that
+        // locked one of them, or any shard twice, would never finish
+        let held: Vec<_> = (0..store.shard_count())
+            .filter(|i| !groups.contains_key(i))
+            .map(|i| store.0.shards[i].lock().unwrap())
+            .collect();
+        let (done, finished) = mpsc::channel();
+        let batches = {
+            let (store, keys) = (store.clone(), keys.clone());
+            thread::spawn(move || {
+                let found = store.get_many(&keys);
+                store.remove_many(&keys).unwrap();
+                done.send(found).unwrap();
+            })
+        };
+        let found = finished
+            .recv_timeout(Duration::from_secs(10))
+            .expect("a batch locked a shard none of its keys belong to");
+        drop(held);
+        batches.join().unwrap();
+
+        let v = |s| Some(Bytes::from(s));
+        assert_eq!(found, [v("v1"), v("v2"), None, v("v1"), v("v7"), v("v2")]);
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.get(Bytes::from("k7")), None);
+    }
+
+    #[test]
+    fn overlapping_batches_do_not_deadlock() {
+        let store = ShardedStore::with_shards(8);
+        let keys: Vec<Bytes> = (0..64).map(|n| Bytes::from(format!("k{n}"))).collect();
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let (store, mut keys) = (store.clone(), keys.clone());
+                thread::spawn(move || {
+                    keys.rotate_left(t * 16);
+                    for _ in 0..200 {
+                        store.get_many(&keys);
+                        store.remove_many(&keys[..8]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+    }
+}
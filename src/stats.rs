@@ -0,0 +1,185 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::Store;
+
+/// Counts of the operations a [`Store`] has served, as returned by
+/// [`Store::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Lookups of a single key, whether hits or misses.
+    pub gets: u64,
+    /// Lookups that found a live value.
+    pub hits: u64,
+    /// Lookups that found nothing, or only an expired value.
+    pub misses: u64,
+    /// Writes of a key, whichever call made them. Batches, transactions and
+    /// the like count once for every key they write.
+    pub sets: u64,
+    /// Removals of a single key, whether or not it was present.
+    pub removes: u64,
+    /// Records dropped because their TTL ran out, whether a lookup came
+    /// across them or a sweeper did.
+    pub expired: u64,
+}
+
+// The live counters behind `StoreStats`, kept outside the store's lock
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    sets: AtomicU64,
+    removes: AtomicU64,
+    expired: AtomicU64,
+}
+
+impl Store {
+    /// Counts the `get` and `remove` calls served so far, including their
+    /// `try_*` forms, every write of a key, and the records that expired,
+    /// since the store was created or [`Store::reset_stats`] last ran. Calls
+    /// that failed aren't counted.
+    ///
+    /// The counters are read one at a time, so a snapshot taken while other
+    /// threads are busy may be a few operations out between fields.
+    pub fn stats(&self) -> StoreStats {
+        let counters = &self.0.counters;
+        let (hits, misses) = (load(&counters.hits), load(&counters.misses));
+        StoreStats {
+            gets: hits + misses,
+            hits,
+            misses,
+            sets: load(&counters.sets),
+            removes: load(&counters.removes),
+            expired: load(&counters.expired),
+        }
+    }
+
+    /// The number of records dropped because their TTL ran out, as counted in
+    /// [`Store::stats`].
+    pub fn expired_count(&self) -> u64 {
+        load(&self.0.counters.expired)
+    }
+
+    /// Sets every counter in [`Store::stats`] back to zero.
+    pub fn reset_stats(&self) {
+        let counters = &self.0.counters;
+        for counter in [
+            &counters.hits,
+            &counters.misses,
+            &counters.sets,
+            &counters.removes,
+            &counters.expired,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Counters {
+    pub(crate) fn record_get(&self, hit: bool) {
+        let counter = match hit {
+            true => &self.hits,
+            false => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_set(&self) {
+        self.sets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_remove(&self) {
+        self.removes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_expired(&self, n: u64) {
+        self.expired.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StoreStats;
+    use crate::{Batch, Store, StoreError};
+    use bytes::Bytes;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let store = Store::new();
+        store.set(Bytes::from("k"), Bytes::from("v"));
+        store.get(Bytes::from("k"));
+        store.get(Bytes::from("k"));
+        store.get(Bytes::from("missing"));
+        store.remove(Bytes::from("k"));
+        store.try_get(Bytes::from("k")).unwrap();
+        // Snapshots and bulk reads aren't single-key lookups
+        store.snapshot();
+
+        assert_eq!(
+            store.stats(),
+            StoreStats {
+                gets: 4,
+                hits: 2,
+                misses: 2,
+                sets: 1,
+                removes: 1,
+                expired: 0,
+            }
+        );
+        assert_eq!(store.clone().stats(), store.stats());
+
+        store.reset_stats();
+        assert_eq!(store.stats(), StoreStats::default());
+    }
+
+    #[test]
+    fn counts_every_write() {
+        let store = Store::new();
+        store.set(Bytes::from("a"), Bytes::from("1"));
+        store.set_with_ttl(Bytes::from("b"), Bytes::from("2"), Duration::from_secs(60));
+        store
+            .entry(Bytes::from("c"))
+            .unwrap()
+            .or_insert(Bytes::from("3"))
+            .unwrap();
+        let mut batch = Batch::new();
+        batch
+            .set(Bytes::from("d"), Bytes::from("4"))
+            .set(Bytes::from("e"), Bytes::from("5"));
+        store.apply(batch).unwrap();
+        store
+            .transaction(|txn| {
+                txn.set(Bytes::from("f"), Bytes::from("6"));
+                Ok::<_, StoreError>(())
+            })
+            .unwrap();
+        assert_eq!(store.stats().sets, 6);
+    }
+
+    #[test]
+    fn counts_expired_records() {
+        let store = Store::new();
+        for i in 0..5 {
+            let k = Bytes::from(format!("k{i}"));
+            store.set_with_ttl(k, Bytes::from("v"), Duration::from_millis(10));
+        }
+        store.set(Bytes::from("kept"), Bytes::from("v"));
+        thread::sleep(Duration::from_millis(20));
+
+        // Two are found by lookups, and the sweeper finds the rest
+        store.get(Bytes::from("k0"));
+        store.entry(Bytes::from("k1")).unwrap();
+        assert_eq!(store.expired_count(), 2);
+        let sweeper = store.start_sweeper(Duration::from_millis(5));
+        while sweeper.evicted() < 3 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        sweeper.stop();
+        assert_eq!(store.expired_count(), 5);
+        assert_eq!(store.stats().expired, 5);
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    hash::BuildHasher,
+    sync::{RwLockReadGuard, RwLockWriteGuard, TryLockError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+use crate::{State, Store, StoreError};
+
+// The longest wait between attempts to take a contended lock, since std's
+// locks can't be waited on with a timeout
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+impl<S: BuildHasher> Store<S> {
+    /// Like [`Store::try_get`], but gives up with [`StoreError::LockTimeout`]
+    /// if the lock can't be taken within `timeout`. A zero timeout only tries
+    /// once, without blocking.
+    pub fn try_get_for(&self, k: Bytes, timeout: Duration) -> Result<Option<Bytes>, StoreError> {
+        self.get_by(k, deadline(timeout))
+    }
+
+    /// Like [`Store::try_set`], but gives up with [`StoreError::LockTimeout`]
+    /// if the lock can't be taken within `timeout`.
+    pub fn try_set_for(
+        &self,
+        k: Bytes,
+        v: Bytes,
+        timeout: Duration,
+    ) -> Result<Option<Bytes>, StoreError> {
+        self.set_by(k, v, deadline(timeout))
+    }
+
+    /// Like [`Store::try_remove`], but gives up with [`StoreError::LockTimeout`]
+    /// if the lock can't be taken within `timeout`.
+    pub fn try_remove_for(&self, k: Bytes, timeout: Duration) -> Result<Option<Bytes>, StoreError> {
+        self.remove_by(k, deadline(timeout))
+    }
+
+    // Takes a shared lock, waiting no later than `deadline` if there is one
+    pub(crate) fn read_by(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<RwLockReadGuard<'_, State<S>>, StoreError> {
+        let Some(deadline) = deadline else {
+            return Ok(self.read()?);
+        };
+        loop {
+            let guard = match self.0.state.try_read() {
+                Ok(guard) => guard,
+                Err(TryLockError::Poisoned(err)) => self.recover_read(err)?,
+                Err(TryLockError::WouldBlock) => {
+                    wait(deadline)?;
+                    continue;
+                }
+            };
+            if guard.appends.is_empty() {
+                return Ok(guard);
+            }
+            // Settling appends needs the exclusive lock
+            drop(guard);
+            drop(self.write_by(Some(deadline))?);
+        }
+    }
+
+    // Takes an exclusive lock, waiting no later than `deadline` if there is one
+    pub(crate) fn write_by(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<RwLockWriteGuard<'_, State<S>>, StoreError> {
+        let Some(deadline) = deadline else {
+            return Ok(self.write()?);
+        };
+        loop {
+            let mut guard = match self.0.state.try_write() {
+                Ok(guard) => guard,
+                Err(TryLockError::Poisoned(err)) => self.recover_write(err)?,
+                Err(TryLockError::WouldBlock) => {
+                    wait(deadline)?;
+                    continue;
+                }
+            };
+            guard.settle();
+            return Ok(guard);
+        }
+    }
+}
+
+// A timeout too long to represent is as good as none
+fn deadline(timeout: Duration) -> Option<Instant> {
+    Instant::now().checked_add(timeout)
+}
+
+// Sleeps before the next attempt at a lock, or fails if `deadline` has passed
+fn wait(deadline: Instant) -> Result<(), StoreError> {
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Err(StoreError::LockTimeout);
+    }
+    thread::sleep(left.min(POLL_INTERVAL));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Store, StoreError};
+    use bytes::Bytes;
+    use std::{
+        sync::mpsc,
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    // Holds the store's lock from another thread for `held`
+    fn hold_lock(store: &Store, held: Duration) -> JoinHandle<()> {
+        let (locked, wait) = mpsc::channel();
+        let store = store.clone();
+        let holder = thread::spawn(move || {
+            store
+                .with_write(|_| {
+                    locked.send(()).unwrap();
+                    thread::sleep(held);
+                })
+                .unwrap();
+        });
+        wait.recv().unwrap();
+        holder
+    }
+
+    #[test]
+    fn waits_are_bounded() {
+        let store = Store::new();
+        store.set(Bytes::from("k"), Bytes::from("v"));
+        let holder = hold_lock(&store, Duration::from_millis(200));
+
+        let k = Bytes::from("k");
+        let ms = Duration::from_millis;
+        assert_eq!(
+            store.try_get_for(k.clone(), ms(50)),
+            Err(StoreError::LockTimeout)
+        );
+        assert_eq!(
+            store.try_get_for(k.clone(), ms(500)),
+            Ok(Some(Bytes::from("v")))
+        );
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn zero_timeout_never_blocks() {
+        let store = Store::new();
+        let holder = hold_lock(&store, Duration::from_millis(200));
+
+        let (k, v) = (Bytes::from("k"), Bytes::from("v"));
+        assert_eq!(
+            store.try_set_for(k.clone(), v.clone(), Duration::ZERO),
+            Err(StoreError::LockTimeout)
+        );
+        assert_eq!(
+            store.try_remove_for(k.clone(), Duration::ZERO),
+            Err(StoreError::LockTimeout)
+        );
+        holder.join().unwrap();
+
+        assert_eq!(
+            store.try_set_for(k.clone(), v.clone(), Duration::ZERO),
+            Ok(None)
+        );
+        assert_eq!(store.try_remove_for(k, Duration::ZERO), Ok(Some(v)));
+    }
+}
@@ -0,0 +1,215 @@
+use std::{collections::HashMap, time::Instant};
+
+use bytes::Bytes;
+
+use crate::{State, Store, StoreError};
+
+/// The view of the store inside [`Store::transaction`]. Writes are buffered
+/// until the transaction commits, and reads see them.
+pub struct Txn<'a> {
+    state: &'a State,
+    now: Instant,
+    // Pending writes, where `None` is a removal
+    writes: HashMap<Bytes, Option<Bytes>>,
+}
+
+impl Store {
+    /// Runs `f` against a transaction and applies its writes only if it
+    /// returns `Ok`. Returning `Err` leaves the store untouched.
+    ///
+    /// The store's lock is held for as long as `f` runs, so `f` must not call
+    /// back into the store, and it blocks every other handle until it returns.
+    /// If `f` panics, nothing is applied but the lock is poisoned, unless the
+    /// store was created with [`Store::with_poison_recovery`].
+    /// The commit is all or nothing: writes that would break the store's
+    /// [`StoreOptions`] fail it before anything is applied, and the writes
+    /// reach the write-ahead log as a single record, so a failing append
+    /// applies none of them either.
+    ///
+    /// [`StoreOptions`]: crate::StoreOptions
+    pub fn transaction<R, E, F>(&self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Txn) -> Result<R, E>,
+        E: From<StoreError>,
+    {
+        self.mutate(|state| {
+            let mut txn = Txn {
+                state,
+                now: Instant::now(),
+                writes: HashMap::new(),
+            };
+            let result = f(&mut txn);
+            let writes = txn.writes;
+            if result.is_ok() {
+                state.write_all(writes.into_iter().collect())?;
+            }
+            Ok(result)
+        })?
+    }
+}
+
+impl Store {
+    /// Runs `f` against a transaction that always commits, and returns what
+    /// `f` returns. Use [`Store::transaction`] to decide whether to commit.
+    ///
+    /// `f` may move what it captures, so owned buffers can be stored without
+    /// copying. The same locking rules as for `transaction` apply.
+    pub fn with_write<R>(&self, f: impl FnOnce(&mut Txn) -> R) -> Result<R, StoreError> {
+        self.transaction(|txn| Ok::<_, StoreError>(f(txn)))
+    }
+}
+
+impl Txn<'_> {
+    /// Reads a value as of this transaction, including its own pending writes.
+    pub fn get(&self, k: &Bytes) -> Option<Bytes> {
+        match self.writes.get(k) {
+            Some(pending) => pending.clone(),
+            None => self.state.get(k, self.now).cloned(),
+        }
+    }
+
+    /// Like [`Store::set`], this replaces any TTL on the key with the store's
+    /// default TTL, or clears it if there is none, once committed.
+    pub fn set(&mut self, k: Bytes, v: Bytes) {
+        self.writes.insert(k, Some(v));
+    }
+
+    pub fn remove(&mut self, k: Bytes) {
+        self.writes.insert(k, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Store, StoreError};
+    use bytes::Bytes;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    fn balance(v: Option<Bytes>) -> i64 {
+        std::str::from_utf8(&v.unwrap()).unwrap().parse().unwrap()
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum TransferError {
+        Insufficient,
+        Store(StoreError),
+    }
+
+    impl From<StoreError> for TransferError {
+        fn from(err: StoreError) -> Self {
+            Self::Store(err)
+        }
+    }
+
+    fn transfer(store: &Store, amount: i64) -> Result<(), TransferError> {
+        store.transaction(|txn| {
+            let (a, b) = (Bytes::from("a"), Bytes::from("b"));
+            let from = balance(txn.get(&a)) - amount;
+            txn.set(a.clone(), Bytes::from(from.to_string()));
+            let to = balance(txn.get(&b)) + amount;
+            txn.set(b, Bytes::from(to.to_string()));
+
+            // Reads see the transaction's own writes
+            assert_eq!(balance(txn.get(&a)), from);
+            match from < 0 {
+                true => Err(TransferError::Insufficient),
+                false => Ok(()),
+            }
+        })
+    }
+
+    fn init_store() -> Store {
+        let store = Store::new();
+        store.set(Bytes::from("a"), Bytes::from("100"));
+        store.set(Bytes::from("b"), Bytes::from("0"));
+        store
+    }
+
+    #[test]
+    fn failed_transaction_leaves_no_trace() {
+        let store = init_store();
+        assert_eq!(transfer(&store, 150), Err(TransferError::Insufficient));
+
+        assert_eq!(store.get(Bytes::from("a")), Some(Bytes::from("100")));
+        assert_eq!(store.get(Bytes::from("b")), Some(Bytes::from("0")));
+    }
+
+    #[test]
+    fn sequential_transactions_compose() {
+        let store = init_store();
+        assert_eq!(transfer(&store, 30), Ok(()));
+        assert_eq!(transfer(&store, 30), Ok(()));
+
+        assert_eq!(store.get(Bytes::from("a")), Some(Bytes::from("40")));
+        assert_eq!(store.get(Bytes::from("b")), Some(Bytes::from("60")));
+    }
+
+    #[test]
+    fn removal_is_visible_inside_transaction() {
+        let store = init_store();
+        let seen = store.transaction(|txn| {
+            txn.remove(Bytes::from("a"));
+            Ok::<_, StoreError>(txn.get(&Bytes::from("a")))
+        });
+
+        assert_eq!(seen, Ok(None));
+        assert!(!store.contains_key(&Bytes::from("a")));
+    }
+
+    #[test]
+    fn write_closures_can_move_owned_buffers() {
+        let store = Store::new();
+        let buffer = vec![7u8; 1 << 20];
+        let ptr = buffer.as_ptr();
+
+        let len = store.with_write(move |txn| {
+            let len = buffer.len();
+            txn.set(Bytes::from("big"), Bytes::from(buffer));
+            len
+        });
+
+        assert_eq!(len, Ok(1 << 20));
+        // The buffer was stored as is, not copied
+        assert_eq!(store.get(Bytes::from("big")).unwrap().as_ptr(), ptr);
+    }
+
+    #[test]
+    fn readers_never_see_half_a_swap() {
+        let store = init_store();
+        let (a, b) = (Bytes::from("a"), Bytes::from("b"));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let reader = {
+            let (store, a, b, done) = (store.clone(), a.clone(), b.clone(), done.clone());
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    let (x, y) = store
+                        .transaction(|txn| Ok::<_, StoreError>((txn.get(&a), txn.get(&b))))
+                        .unwrap();
+                    assert_ne!(x, y);
+                }
+            })
+        };
+        for _ in 0..1000 {
+            store
+                .transaction(|txn| {
+                    let (x, y) = (txn.get(&a), txn.get(&b));
+                    txn.set(a.clone(), y.unwrap());
+                    txn.set(b.clone(), x.unwrap());
+                    Ok::<_, StoreError>(())
+                })
+                .unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+
+        assert_eq!(store.get(a), Some(Bytes::from("100")));
+        assert_eq!(store.get(b), Some(Bytes::from("0")));
+    }
+}
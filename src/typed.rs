@@ -0,0 +1,259 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    hash::Hash,
+    marker::PhantomData,
+};
+
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Store, StoreError};
+
+/// A [`Store`] of typed keys and values, which are encoded to bytes with
+/// `bincode` on the way in and decoded on the way out.
+///
+/// Keys are looked up by their encoding, so two keys that are equal must
+/// encode to the same bytes and two that differ must not. `bincode` gives both
+/// for most types, but not for ones whose serialized form depends on more
+/// than their value, such as a `HashMap` whose iteration order varies.
+///
+/// Like `Store`, cloning a `TypedStore` shares the same records.
+pub struct TypedStore<K, V> {
+    store: Store,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+/// Errors surfaced by the fallible `try_*` methods of a [`TypedStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TypedError {
+    /// A key or value couldn't be encoded.
+    Encode(String),
+    /// A stored value couldn't be decoded as the store's value type.
+    Decode(String),
+    /// The underlying store failed.
+    Store(StoreError),
+}
+
+impl Display for TypedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Encode(reason) => write!(f, "failed to encode: {reason}"),
+            Self::Decode(reason) => write!(f, "failed to decode: {reason}"),
+            Self::Store(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl Error for TypedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Store(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<StoreError> for TypedError {
+    fn from(err: StoreError) -> Self {
+        Self::Store(err)
+    }
+}
+
+impl<K, V> TypedStore<K, V>
+where
+    K: Serialize + DeserializeOwned + Eq + Hash,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn new() -> Self {
+        Self::from_store(Store::new())
+    }
+
+    /// Wraps an existing store. Its records must have been written by a
+    /// `TypedStore` of the same types to be readable.
+    pub fn from_store(store: Store) -> Self {
+        Self {
+            store,
+            types: PhantomData,
+        }
+    }
+
+    /// The untyped store underneath.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Returns `None` if the key is absent or anything fails.
+    pub fn get(&self, k: &K) -> Option<V> {
+        self.try_get(k).unwrap_or_default()
+    }
+
+    /// Does nothing if the key or value can't be encoded.
+    pub fn set(&self, k: &K, v: &V) {
+        self.try_set(k, v).unwrap_or_default();
+    }
+
+    pub fn remove(&self, k: &K) {
+        self.try_remove(k).unwrap_or_default();
+    }
+
+    /// Like [`TypedStore::get`], but reports why a lookup failed.
+    pub fn try_get(&self, k: &K) -> Result<Option<V>, TypedError> {
+        self.store
+            .try_get(encode(k)?)?
+            .map(|v| bincode::deserialize(&v).map_err(|err| TypedError::Decode(err.to_string())))
+            .transpose()
+    }
+
+    /// Like [`TypedStore::set`], but reports why a write failed.
+    pub fn try_set(&self, k: &K, v: &V) -> Result<(), TypedError> {
+        self.store.try_set(encode(k)?, encode(v)?)?;
+        Ok(())
+    }
+
+    /// Like [`TypedStore::remove`], but reports why a removal failed.
+    pub fn try_remove(&self, k: &K) -> Result<(), TypedError> {
+        self.store.try_remove(encode(k)?)?;
+        Ok(())
+    }
+}
+
+impl<K, V> Clone for TypedStore<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            types: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Default for TypedStore<K, V>
+where
+    K: Serialize + DeserializeOwned + Eq + Hash,
+    V: Serialize + DeserializeOwned,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Bytes, TypedError> {
+    bincode::serialize(value)
+        .map(Bytes::from)
+        .map_err(|err| TypedError::Encode(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TypedError, TypedStore};
+    use crate::Store;
+    use serde::{Deserialize, Serialize, Serializer};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+        tags: Vec<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    enum Id {
+        Numeric(u64),
+        Named { team: String, seat: Option<u8> },
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Role {
+        Guest,
+        Member { since: u32 },
+        Admin(Box<Role>),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        user: User,
+        role: Role,
+        history: Vec<Option<Role>>,
+    }
+
+    // Fails to serialize, to exercise the error path
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Unencodable;
+
+    impl Serialize for Unencodable {
+        fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("refusing to serialize"))
+        }
+    }
+
+    #[test]
+    fn round_trips_typed_values() {
+        let store = TypedStore::<String, User>::new();
+        let user = User {
+            name: "ferris".to_string(),
+            age: 8,
+            tags: vec!["crab".to_string()],
+        };
+
+        store.set(&"ferris".to_string(), &user);
+        assert_eq!(store.get(&"ferris".to_string()), Some(user));
+        assert_eq!(store.get(&"missing".to_string()), None);
+
+        store.remove(&"ferris".to_string());
+        assert_eq!(store.get(&"ferris".to_string()), None);
+    }
+
+    #[test]
+    fn stores_share_inner_store() {
+        let inner = Store::new();
+        let writer = TypedStore::<Id, Account>::from_store(inner.clone());
+        let reader = TypedStore::<Id, Account>::from_store(inner);
+
+        let id = Id::Named {
+            team: "core".to_string(),
+            seat: Some(3),
+        };
+        let account = Account {
+            user: User {
+                name: "ferris".to_string(),
+                age: 8,
+                tags: Vec::new(),
+            },
+            role: Role::Admin(Box::new(Role::Member { since: 2015 })),
+            history: vec![None, Some(Role::Guest)],
+        };
+        writer.set(&id, &account);
+        writer.set(&Id::Numeric(3), &account);
+
+        assert_eq!(reader.get(&id), Some(account));
+        assert_eq!(
+            reader.get(&Id::Named {
+                team: "core".to_string(),
+                seat: None
+            }),
+            None
+        );
+        assert_eq!(reader.store().len(), 2);
+    }
+
+    #[test]
+    fn codec_failures_are_errors() {
+        let store = TypedStore::<String, User>::new();
+        let k = bincode::serialize("k").unwrap();
+        store.store().set(k.into(), "not a user".into());
+
+        assert_eq!(store.get(&"k".to_string()), None);
+        assert!(matches!(
+            store.try_get(&"k".to_string()),
+            Err(TypedError::Decode(_))
+        ));
+
+        let unencodable = TypedStore::<String, Unencodable>::new();
+        assert!(matches!(
+            unencodable.try_set(&"k".to_string(), &Unencodable),
+            Err(TypedError::Encode(_))
+        ));
+        assert!(unencodable.store().is_empty());
+    }
+}
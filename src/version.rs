@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+    hash::BuildHasher,
+    time::Instant,
+};
+
+use bytes::Bytes;
+
+use crate::{expiry::Expiry, Records, State, Store, StoreError};
+
+// The version of every record. Versions come from one clock shared by every
+// key, so a key that is removed and set again never reuses a version
+#[derive(Debug)]
+pub(crate) struct Versions {
+    clock: u64,
+    by_key: HashMap<Bytes, u64>,
+}
+
+/// Why a [`Store::set_if_version`] didn't write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VersionError {
+    /// The key was at version `current` rather than the expected one.
+    Mismatch { current: u64 },
+    /// The store itself failed.
+    Store(StoreError),
+}
+
+impl Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mismatch { current } => {
+                write!(f, "key is at version {current}, not the expected one")
+            }
+            Self::Store(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl Error for VersionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Mismatch { .. } => None,
+            Self::Store(err) => Some(err),
+        }
+    }
+}
+
+impl From<StoreError> for VersionError {
+    fn from(err: StoreError) -> Self {
+        Self::Store(err)
+    }
+}
+
+impl Store {
+    /// Returns the value of `k` along with its version, which grows every
+    /// time the key is written. Pass the version to [`Store::set_if_version`]
+    /// to write only if nobody else has since.
+    pub fn get_versioned(&self, k: &Bytes) -> Option<(Bytes, u64)> {
+        let now = Instant::now();
+        self.read_state(|state| {
+            let v = state.get(k, now)?;
+            Some((v.clone(), state.version(k, now)))
+        })
+        .unwrap_or_default()
+    }
+
+    /// Sets `k` to `v` only if it is still at `expected` version, where `0`
+    /// means the key is absent, and returns its new version. Like
+    /// [`Store::set`], this replaces any TTL with the store's default TTL, or
+    /// clears it if there is none.
+    ///
+    /// This is a lighter [`Store::try_compare_and_swap`]: callers only need to
+    /// hold on to a version rather than the whole value they read.
+    pub fn set_if_version(&self, k: Bytes, v: Bytes, expected: u64) -> Result<u64, VersionError> {
+        self.mutate(|state| {
+            let now = Instant::now();
+            let current = state.version(&k, now);
+            if current != expected {
+                return Ok(Err(VersionError::Mismatch { current }));
+            }
+            state.set(k.clone(), v, Expiry::Default)?;
+            Ok(Ok(state.version(&k, now)))
+        })?
+    }
+}
+
+impl Versions {
+    // Every record starts at version `1`
+    pub(crate) fn new<S>(records: &Records<S>) -> Self {
+        Self {
+            clock: 1,
+            by_key: records.keys().map(|k| (k.clone(), 1)).collect(),
+        }
+    }
+
+    // Moves `k` to the next version
+    pub(crate) fn bump(&mut self, k: &Bytes) {
+        self.clock += 1;
+        self.by_key.insert(k.clone(), self.clock);
+    }
+
+    pub(crate) fn forget(&mut self, k: &Bytes) {
+        self.by_key.remove(k);
+    }
+}
+
+impl<S: BuildHasher> State<S> {
+    // The version of `k`, which is `0` if the key is absent or expired
+    fn version(&self, k: &Bytes, now: Instant) -> u64 {
+        match self.get(k, now) {
+            Some(_) => self.versions.by_key.get(k).copied().unwrap_or_default(),
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VersionError;
+    use crate::Store;
+    use bytes::Bytes;
+    use std::thread;
+
+    #[test]
+    fn versions_grow_with_every_write() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+        assert_eq!(store.get_versioned(&k), None);
+
+        let first = store
+            .set_if_version(k.clone(), Bytes::from("a"), 0)
+            .unwrap();
+        assert_eq!(store.get_versioned(&k), Some((Bytes::from("a"), first)));
+        assert_eq!(
+            store.set_if_version(k.clone(), Bytes::from("b"), 0),
+            Err(VersionError::Mismatch { current: first })
+        );
+
+        // A key removed and set again doesn't go back to an old version
+        store.remove(k.clone());
+        store.set(k.clone(), Bytes::from("c"));
+        let (_, again) = store.get_versioned(&k).unwrap();
+        assert!(again > first);
+        assert!(store
+            .set_if_version(k.clone(), Bytes::from("d"), first)
+            .is_err());
+        assert!(store.set_if_version(k, Bytes::from("d"), again).is_ok());
+    }
+
+    #[test]
+    fn loaded_records_have_a_version() {
+        let store: Store = [("k", "v")].into_iter().collect();
+        let (_, version) = store.get_versioned(&Bytes::from("k")).unwrap();
+        assert_ne!(version, 0);
+    }
+
+    #[test]
+    fn concurrent_writers_never_clobber_each_other() {
+        let store = Store::new();
+        let k = Bytes::from("n");
+        store.set(k.clone(), Bytes::from("0"));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let (store, k) = (store.clone(), k.clone());
+                thread::spawn(move || {
+                    let mut written = 0;
+                    while written < 1000 {
+                        let (v, version) = store.get_versioned(&k).unwrap();
+                        let n: u64 = std::str::from_utf8(&v).unwrap().parse().unwrap();
+                        let next = Bytes::from((n + 1).to_string());
+                        if store.set_if_version(k.clone(), next, version).is_ok() {
+                            written += 1;
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        assert_eq!(store.get(k), Some(Bytes::from("2000")));
+    }
+}
@@ -0,0 +1,74 @@
+use std::{collections::hash_map::RandomState, hash::BuildHasher, time::Instant};
+
+use bytes::Bytes;
+
+use crate::{State, Store, StoreError};
+
+/// A read-only view of the store inside [`Store::with_read`]. Expired entries
+/// are hidden, as they are from every other read.
+pub struct View<'a, S = RandomState> {
+    state: &'a State<S>,
+    now: Instant,
+}
+
+impl<S: BuildHasher> Store<S> {
+    /// Runs `f` against a view of the store under a single shared lock and
+    /// returns what it returns, e.g. to check or aggregate many entries
+    /// without copying them out. See [`Store::with_write`] for writes.
+    ///
+    /// The lock is held for as long as `f` runs, so `f` must not call back
+    /// into the store, and it holds off writers until it returns.
+    pub fn with_read<R>(&self, f: impl FnOnce(&View<S>) -> R) -> Result<R, StoreError> {
+        let now = Instant::now();
+        self.read_state(|state| f(&View { state, now }))
+    }
+}
+
+impl<S: BuildHasher> View<'_, S> {
+    pub fn get(&self, k: &Bytes) -> Option<&Bytes> {
+        self.state.get(k, self.now)
+    }
+
+    pub fn contains_key(&self, k: &Bytes) -> bool {
+        self.get(k).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.len(self.now)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over every live entry, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Bytes)> {
+        self.state
+            .records
+            .iter()
+            .filter(|(k, _)| !self.state.is_expired(k, self.now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Store;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    #[test]
+    fn aggregates_under_one_lock() {
+        let store = Store::new();
+        store.set(Bytes::from("a"), Bytes::from("12"));
+        store.set(Bytes::from("b"), Bytes::from("345"));
+        store.set_with_ttl(Bytes::from("gone"), Bytes::from("6"), Duration::ZERO);
+
+        let total = store.with_read(|view| {
+            assert!(view.contains_key(&Bytes::from("a")));
+            assert_eq!(view.get(&Bytes::from("gone")), None);
+            assert_eq!(view.len(), 2);
+            view.iter().map(|(_, v)| v.len()).sum::<usize>()
+        });
+        assert_eq!(total, Ok(5));
+    }
+}
@@ -0,0 +1,1019 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    hash::BuildHasher,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::PoisonError,
+    time::Instant,
+};
+
+use bytes::Bytes;
+
+use crate::{crc, expiry, Expiries, Records, State, Store, StoreError};
+
+// A log starts with the magic bytes followed by a format version, and every
+// record after that is framed as `u32 length, u32 CRC-32, op tag, payload`,
+// where the length covers the tag and payload and the checksum covers the
+// length too. A set's payload is
+// `u32 key length, key, value`, an expiring set prefixes that with a `u64`
+// deadline in Unix milliseconds, and a remove's payload is just the key. A
+// batch's payload is a run of `u32 length, op tag, payload` for every
+// mutation in it, which replay applies all together or not at all.
+//
+// Logs written before the header was introduced start straight with their
+// records, which have no checksum. They are rewritten in the current format
+// when opened for appending.
+pub(crate) const MAGIC: &[u8; 4] = b"KVSW";
+pub(crate) const VERSION: u8 = 2;
+pub(crate) const HEADER_LEN: u64 = MAGIC.len() as u64 + 1;
+
+pub(crate) const TAG_SET: u8 = 1;
+pub(crate) const TAG_REMOVE: u8 = 2;
+const TAG_SET_EXPIRING: u8 = 3;
+const TAG_BATCH: u8 = 4;
+
+// Auto-compaction never kicks in while the log has fewer dead bytes than this
+const MIN_DEAD_BYTES: u64 = 4096;
+
+// A mutation as it is written to the log. A set carries its deadline, if any,
+// in Unix milliseconds
+#[derive(Clone, Copy)]
+pub(crate) enum Op<'a> {
+    Set(&'a [u8], &'a [u8], Option<u64>),
+    Remove(&'a [u8]),
+}
+
+impl<'a> Op<'a> {
+    fn key(self) -> &'a [u8] {
+        match self {
+            Op::Set(k, ..) | Op::Remove(k) => k,
+        }
+    }
+}
+
+// An append-only log of every mutation applied to a store
+#[derive(Debug)]
+pub(crate) struct Wal {
+    file: File,
+    path: PathBuf,
+    // Length of the log and the number of records in it
+    size: u64,
+    entries: u64,
+    // Length the log would be if it only held the live records
+    live: u64,
+    auto_compact: Option<u32>,
+    compacting: bool,
+}
+
+/// What replaying a write-ahead log does on finding a record that fails its
+/// checksum, passed to [`Store::open_with_wal_mode`] and
+/// [`Store::replay_wal_mode`].
+///
+/// A torn record at the very end of the log, as a crash partway through an
+/// append leaves behind, isn't corruption: it is dropped in either mode. A
+/// record whose length was corrupted to run past the end of the log is told
+/// apart from a torn one by the intact records still after it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecoveryMode {
+    /// Fail with [`ErrorKind::InvalidData`], naming the record and its byte
+    /// offset, and leave the log untouched.
+    #[default]
+    Strict,
+    /// Recover every record before the corrupt one and drop the rest, which
+    /// [`Store::open_with_wal_mode`] truncates away so appends carry on after
+    /// the recovered records.
+    Tolerant,
+}
+
+/// What a call to [`Store::compact`] reclaimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub records_dropped: u64,
+}
+
+impl Store {
+    /// Opens a store whose every `set`/`remove` is appended to the log at
+    /// `path` before it is applied, replaying any existing log first.
+    ///
+    /// A torn record at the end of the log (e.g. from power loss mid-append)
+    /// is truncated away; everything before it is recovered. A record that
+    /// fails its checksum is an error, as with [`RecoveryMode::Strict`].
+    pub fn open_with_wal(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_wal_mode(path, RecoveryMode::Strict)
+    }
+
+    /// Like [`Store::open_with_wal`], but handles a record that fails its
+    /// checksum as `mode` says.
+    pub fn open_with_wal_mode(path: impl AsRef<Path>, mode: RecoveryMode) -> io::Result<Self> {
+        let (mut records, mut expiries) = (Records::new(), Expiries::new());
+        let wal = Wal::open(path.as_ref(), &mut records, &mut expiries, mode)?;
+        Ok(Self::from_state(State {
+            wal: Some(wal),
+            ..State::new(records, expiries)
+        }))
+    }
+
+    /// Rebuilds the state recorded in the log at `path` into an in-memory store
+    /// that doesn't log its own writes, leaving the file untouched. A torn
+    /// record at the end of the log is ignored, and one that fails its
+    /// checksum is an error.
+    ///
+    /// Use [`Store::open_with_wal`] to carry on appending to the log instead.
+    pub fn replay_wal(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::replay_wal_mode(path, RecoveryMode::Strict)
+    }
+
+    /// Like [`Store::replay_wal`], but handles a record that fails its
+    /// checksum as `mode` says.
+    pub fn replay_wal_mode(path: impl AsRef<Path>, mode: RecoveryMode) -> io::Result<Self> {
+        let (mut records, mut expiries) = (Records::new(), Expiries::new());
+        let mut reader = BufReader::new(File::open(path)?);
+        replay(&mut reader, &mut records, &mut expiries, mode)?;
+        Ok(Self::from_state(State::new(records, expiries)))
+    }
+}
+
+impl<S: BuildHasher> Store<S> {
+    /// Rewrites the write-ahead log so it only holds the live records,
+    /// replacing the old log with an atomic rename.
+    ///
+    /// The live records are copied out under the lock and written without it,
+    /// so the store stays usable throughout; writes made in the meantime are
+    /// carried over into the new log before it is swapped in.
+    pub fn compact(&self) -> io::Result<CompactionStats> {
+        let (records, expiries, path, offset, entries) = {
+            let mut guard = self.write().map_err(poisoned)?;
+            let state = &mut *guard;
+            let wal = state.wal.as_mut().ok_or_else(no_wal)?;
+            if wal.compacting {
+                return Err(io::Error::other("log compaction is already in progress"));
+            }
+            wal.compacting = true;
+            // Copied into a map with the default hasher, since the store's
+            // needn't be `Clone`
+            let records: Records = state
+                .records
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            (
+                records,
+                state.expiries.clone(),
+                wal.path.clone(),
+                wal.size,
+                wal.entries,
+            )
+        };
+
+        let temp = compaction_path(&path);
+        let result = self.swap_log(&records, &expiries, &temp, offset, entries);
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+            if let Ok(mut guard) = self.write() {
+                if let Some(wal) = &mut guard.wal {
+                    wal.compacting = false;
+                }
+            }
+        }
+        result
+    }
+
+    /// Compacts the log automatically once its dead bytes exceed `factor`
+    /// times its live bytes, or never if `None` (the default).
+    ///
+    /// Has no effect on a store without a write-ahead log.
+    pub fn set_auto_compaction(&self, factor: Option<u32>) {
+        if let Ok(mut guard) = self.write() {
+            if let Some(wal) = &mut guard.wal {
+                wal.auto_compact = factor;
+            }
+        }
+    }
+
+    // Writes the live `records` to `temp`, appends whatever was logged after
+    // `offset`, and renames it over the live log
+    fn swap_log(
+        &self,
+        records: &Records,
+        expiries: &Expiries,
+        temp: &Path,
+        offset: u64,
+        entries: u64,
+    ) -> io::Result<CompactionStats> {
+        let (mut file, written, kept) = write_log(temp, records, expiries)?;
+
+        let mut guard = self.write().map_err(poisoned)?;
+        let wal = guard.wal.as_mut().ok_or_else(no_wal)?;
+
+        // Carry over anything logged while the snapshot was being written
+        let mut old = File::open(&wal.path)?;
+        old.seek(SeekFrom::Start(offset))?;
+        let tail = io::copy(&mut old.take(wal.size - offset), &mut file)?;
+        file.sync_all()?;
+        fs::rename(temp, &wal.path)?;
+
+        let stats = CompactionStats {
+            bytes_before: wal.size,
+            bytes_after: written + tail,
+            records_dropped: entries - kept,
+        };
+        wal.file = file;
+        wal.size = stats.bytes_after;
+        // Expired records aren't written, so only what was is live. Records
+        // carried over in the tail are counted as live too, which at worst
+        // delays the next compaction
+        wal.live = stats.bytes_after - HEADER_LEN;
+        wal.entries -= stats.records_dropped;
+        wal.compacting = false;
+        Ok(stats)
+    }
+}
+
+impl Wal {
+    // Replays the log at `path` into `records` and `expiries` and positions it
+    // for appending
+    fn open(
+        path: &Path,
+        records: &mut Records,
+        expiries: &mut Expiries,
+        mode: RecoveryMode,
+    ) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let replayed = replay(&mut BufReader::new(&file), records, expiries, mode)?;
+        let (size, entries, live) = match replayed {
+            Replayed::Current { valid, entries } => {
+                file.set_len(valid)?;
+                let live = records
+                    .keys()
+                    .filter_map(|k| logged(records, expiries, k))
+                    .map(encoded_len)
+                    .sum();
+                (valid, entries, live)
+            }
+            // A new or old-format log is rewritten with a header and
+            // checksums before anything is appended to it
+            Replayed::Legacy => {
+                let temp = compaction_path(path);
+                let (rewritten, written, kept) = write_log(&temp, records, expiries)?;
+                rewritten.sync_all()?;
+                fs::rename(&temp, path)?;
+                file = rewritten;
+                (written, kept, written - HEADER_LEN)
+            }
+        };
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            file,
+            path: path.to_owned(),
+            size,
+            entries,
+            live,
+            auto_compact: None,
+            compacting: false,
+        })
+    }
+
+    // Durably appends a single record to the log. `prev` is the record the
+    // mutation supersedes, if any
+    pub(crate) fn append(&mut self, op: Op, prev: Option<Op>) -> io::Result<()> {
+        self.write(&encode(op)?)?;
+        self.supersede(op, prev);
+        Ok(())
+    }
+
+    // Durably appends every op as a single batch record, superseding what
+    // `records` and `expiries` hold for their keys and then one another
+    pub(crate) fn append_all<S: BuildHasher>(
+        &mut self,
+        ops: &[Op],
+        records: &Records<S>,
+        expiries: &Expiries,
+    ) -> io::Result<()> {
+        self.write(&encode_all(ops)?)?;
+        let mut latest = HashMap::new();
+        for &op in ops {
+            let k = op.key();
+            let prev = match latest.get(k) {
+                Some(prev) => *prev,
+                None => logged(records, expiries, k),
+            };
+            self.supersede(op, prev);
+            latest.insert(k, Some(op).filter(|op| matches!(op, Op::Set(..))));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: &[u8]) -> io::Result<()> {
+        if let Err(err) = self
+            .file
+            .write_all(record)
+            .and_then(|()| self.file.sync_data())
+        {
+            // Cut off whatever part of the record made it, so that the next
+            // one lands where it is expected to
+            let _ = self.file.set_len(self.size);
+            let _ = self.file.seek(SeekFrom::Start(self.size));
+            return Err(err);
+        }
+        self.size += record.len() as u64;
+        self.entries += 1;
+        Ok(())
+    }
+
+    // Accounts for `op` replacing `prev` as the live record of its key
+    fn supersede(&mut self, op: Op, prev: Option<Op>) {
+        if let Op::Set(..) = op {
+            self.live += encoded_len(op);
+        }
+        // `prev` may be an expired record that compaction already dropped
+        if let Some(prev) = prev {
+            self.live = self.live.saturating_sub(encoded_len(prev));
+        }
+    }
+
+    pub(crate) fn compaction_due(&self) -> bool {
+        let dead = self.size.saturating_sub(self.live);
+        self.auto_compact.is_some_and(|factor| {
+            !self.compacting && dead >= MIN_DEAD_BYTES && dead > self.live * factor as u64
+        })
+    }
+}
+
+// The set record that would recreate the current state of `k`, if it exists
+pub(crate) fn logged<'a, S: BuildHasher>(
+    records: &'a Records<S>,
+    expiries: &Expiries,
+    k: &[u8],
+) -> Option<Op<'a>> {
+    let (k, v) = records.get_key_value(k)?;
+    let deadline = expiries.get(k).copied().map(expiry::to_unix_millis);
+    Some(Op::Set(k, v, deadline))
+}
+
+// What `replay` found in a log
+enum Replayed {
+    // A log in the current format, whose first `valid` bytes hold `entries`
+    // intact records
+    Current { valid: u64, entries: u64 },
+    // An empty log, or one written before checksums were added
+    Legacy,
+}
+
+// Writes a log holding only the live `records` to `path`, returning the file
+// along with its length and how many records it holds
+fn write_log(path: &Path, records: &Records, expiries: &Expiries) -> io::Result<(File, u64, u64)> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    let mut writer = BufWriter::new(&file);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])?;
+    let now = Instant::now();
+    let (mut written, mut kept) = (HEADER_LEN, 0);
+    for k in records.keys() {
+        if expiries.get(k).is_some_and(|deadline| *deadline <= now) {
+            continue;
+        }
+        let record = encode(logged(records, expiries, k).unwrap())?;
+        writer.write_all(&record)?;
+        written += record.len() as u64;
+        kept += 1;
+    }
+    writer.flush()?;
+    drop(writer);
+    Ok((file, written, kept))
+}
+
+// Applies every complete record to `records` and `expiries`, stopping at a
+// torn record at the end, and at a corrupt one if `mode` allows
+fn replay(
+    r: &mut impl Read,
+    records: &mut Records,
+    expiries: &mut Expiries,
+    mode: RecoveryMode,
+) -> io::Result<Replayed> {
+    let mut header = [0; HEADER_LEN as usize];
+    let mut filled = 0;
+    while filled < header.len() {
+        match r.read(&mut header[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    if filled < header.len() || header[..MAGIC.len()] != *MAGIC {
+        // The bytes read so far are the start of the first record
+        let mut r = (&header[..filled]).chain(r);
+        replay_records(&mut r, records, expiries, false, mode)?;
+        return Ok(Replayed::Legacy);
+    }
+    let version = header[MAGIC.len()];
+    if version != VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported write-ahead log version {version}"),
+        ));
+    }
+
+    let (valid, entries) = replay_records(r, records, expiries, true, mode)?;
+    Ok(Replayed::Current {
+        valid: HEADER_LEN + valid,
+        entries,
+    })
+}
+
+// Applies the records that follow the header, returning the length of the
+// valid prefix of them and how many it holds
+fn replay_records(
+    r: &mut impl Read,
+    records: &mut Records,
+    expiries: &mut Expiries,
+    checksummed: bool,
+    mode: RecoveryMode,
+) -> io::Result<(u64, u64)> {
+    let frame = match checksummed {
+        true => 8,
+        false => 4,
+    };
+    let (mut valid, mut entries) = (0, 0);
+    loop {
+        let mut head = [0; 8];
+        if !read_full(r, &mut head[..frame])? {
+            return Ok((valid, entries));
+        }
+
+        let len = u32::from_le_bytes(head[..4].try_into().unwrap()) as u64;
+        let mut body = Vec::new();
+        r.take(len).read_to_end(&mut body)?;
+        if (body.len() as u64) < len {
+            // A crash partway through an append only ever tears the last
+            // record, so an intact one among the bytes left means it was the
+            // length that got corrupted
+            if checksummed && mode == RecoveryMode::Strict && holds_record(&body) {
+                return Err(corrupt(entries, valid, "has a corrupt length"));
+            }
+            return Ok((valid, entries));
+        }
+        if checksummed
+            && crc::checksum(&[&head[..4], &body])
+                != u32::from_le_bytes(head[4..].try_into().unwrap())
+        {
+            match mode {
+                RecoveryMode::Strict => return Err(corrupt(entries, valid, "fails its checksum")),
+                RecoveryMode::Tolerant => return Ok((valid, entries)),
+            }
+        }
+
+        apply(Bytes::from(body), records, expiries)?;
+        valid += frame as u64 + len;
+        entries += 1;
+    }
+}
+
+// Whether an intact record starts anywhere in `bytes`
+fn holds_record(bytes: &[u8]) -> bool {
+    (0..bytes.len().saturating_sub(8)).any(|start| {
+        let (head, rest) = bytes[start..].split_at(8);
+        let len = u32::from_le_bytes(head[..4].try_into().unwrap()) as usize;
+        len > 0
+            && len <= rest.len()
+            && crc::checksum(&[&head[..4], &rest[..len]])
+                == u32::from_le_bytes(head[4..].try_into().unwrap())
+    })
+}
+
+fn apply(mut body: Bytes, records: &mut Records, expiries: &mut Expiries) -> io::Result<()> {
+    if body.is_empty() {
+        return Err(malformed());
+    }
+    match body.split_to(1)[0] {
+        TAG_SET => {
+            let (k, v) = split_entry(body)?;
+            expiries.remove(&k);
+            records.insert(k, v);
+        }
+        TAG_SET_EXPIRING => {
+            if body.len() < 8 {
+                return Err(malformed());
+            }
+            let millis = u64::from_le_bytes(body.split_to(8)[..].try_into().unwrap());
+            let (k, v) = split_entry(body)?;
+            match expiry::from_unix_millis(millis) {
+                Some(deadline) => {
+                    expiries.insert(k.clone(), deadline);
+                    records.insert(k, v);
+                }
+                None => {
+                    expiries.remove(&k);
+                    records.remove(&k);
+                }
+            }
+        }
+        TAG_REMOVE => {
+            expiries.remove(&body);
+            records.remove(&body);
+        }
+        TAG_BATCH => {
+            // Every op is split out before any is applied
+            let mut ops = Vec::new();
+            while !body.is_empty() {
+                if body.len() < 4 {
+                    return Err(malformed());
+                }
+                let len = u32::from_le_bytes(body.split_to(4)[..].try_into().unwrap()) as usize;
+                if body.len() < len {
+                    return Err(malformed());
+                }
+                ops.push(body.split_to(len));
+            }
+            for op in ops {
+                apply(op, records, expiries)?;
+            }
+        }
+        _ => return Err(malformed()),
+    }
+    Ok(())
+}
+
+// Splits a `u32 key length, key, value` payload
+pub(crate) fn split_entry(mut body: Bytes) -> io::Result<(Bytes, Bytes)> {
+    if body.len() < 4 {
+        return Err(malformed());
+    }
+    let len = u32::from_le_bytes(body.split_to(4)[..].try_into().unwrap()) as usize;
+    if body.len() < len {
+        return Err(malformed());
+    }
+    Ok((body.split_to(len), body))
+}
+
+pub(crate) fn encode(op: Op) -> io::Result<Vec<u8>> {
+    frame(encode_body(op)?)
+}
+
+// Encodes `ops` as a single batch record
+fn encode_all(ops: &[Op]) -> io::Result<Vec<u8>> {
+    let mut body = vec![TAG_BATCH];
+    for &op in ops {
+        let mut op = encode_body(op)?;
+        body.extend_from_slice(&frame_len(op.len())?.to_le_bytes());
+        body.append(&mut op);
+    }
+    frame(body)
+}
+
+// The op tag and payload of `op`
+fn encode_body(op: Op) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    match op {
+        Op::Set(k, v, deadline) => {
+            match deadline {
+                Some(millis) => {
+                    body.push(TAG_SET_EXPIRING);
+                    body.extend_from_slice(&millis.to_le_bytes());
+                }
+                None => body.push(TAG_SET),
+            }
+            body.extend_from_slice(&frame_len(k.len())?.to_le_bytes());
+            body.extend_from_slice(k);
+            body.extend_from_slice(v);
+        }
+        Op::Remove(k) => {
+            body.push(TAG_REMOVE);
+            body.extend_from_slice(k);
+        }
+    }
+
+    Ok(body)
+}
+
+// Prefixes a record's body with its length and checksum
+fn frame(mut body: Vec<u8>) -> io::Result<Vec<u8>> {
+    let len = frame_len(body.len())?.to_le_bytes();
+    let mut record = len.to_vec();
+    record.extend_from_slice(&crc::checksum(&[&len, &body]).to_le_bytes());
+    record.append(&mut body);
+    Ok(record)
+}
+
+// The length `encode` produces for `op`
+fn encoded_len(op: Op) -> u64 {
+    let payload = match op {
+        Op::Set(k, v, None) => 4 + k.len() + v.len(),
+        Op::Set(k, v, Some(_)) => 8 + 4 + k.len() + v.len(),
+        Op::Remove(k) => k.len(),
+    };
+    (4 + 4 + 1 + payload) as u64
+}
+
+// Fills `buf`, returning `false` if the reader ran out first
+pub(crate) fn read_full(r: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
+
+fn compaction_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".compact");
+    PathBuf::from(name)
+}
+
+fn frame_len(len: usize) -> io::Result<u32> {
+    u32::try_from(len)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "record is too large for the log"))
+}
+
+// Record `index` of the log, `offset` bytes past the header, is `what`
+fn corrupt(index: u64, offset: u64, what: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "write-ahead log record {index} at byte {} {what}",
+            HEADER_LEN + offset
+        ),
+    )
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        "write-ahead log contains a malformed record",
+    )
+}
+
+fn no_wal() -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, "store has no write-ahead log")
+}
+
+pub(crate) fn poisoned<T>(_: PoisonError<T>) -> io::Error {
+    io::Error::other(StoreError::Poisoned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecoveryMode, Store};
+    use crate::{Batch, StoreError};
+    use bytes::Bytes;
+    use std::{env, fs, io::ErrorKind, path::PathBuf, process, thread, time::Duration};
+
+    #[test]
+    fn reopen_replays_log() {
+        let path = temp_path("replay");
+        let store = Store::open_with_wal(&path).unwrap();
+        store.set(Bytes::from("hello1"), Bytes::from("world1"));
+        store.set(Bytes::from("hello2"), Bytes::from("world2"));
+        store.set(Bytes::from("hello1"), Bytes::from("again"));
+        store.remove(Bytes::from("hello2"));
+        drop(store);
+
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(Bytes::from("hello1")), Some(Bytes::from("again")));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replay_wal_leaves_log_alone() {
+        let path = temp_path("replay-only");
+        let store = Store::open_with_wal(&path).unwrap();
+        store.set(Bytes::from("hello1"), Bytes::from("world1"));
+        store.set(Bytes::from("hello2"), Bytes::from("world2"));
+        store.remove(Bytes::from("hello1"));
+        drop(store);
+        let logged = fs::metadata(&path).unwrap().len();
+
+        let replayed = Store::replay_wal(&path).unwrap();
+        assert_eq!(
+            replayed.snapshot(),
+            [(Bytes::from("hello2"), Bytes::from("world2"))]
+        );
+        replayed.set(Bytes::from("hello3"), Bytes::from("world3"));
+        assert_eq!(fs::metadata(&path).unwrap().len(), logged);
+
+        assert!(Store::replay_wal(temp_path("missing")).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn torn_record_is_truncated() {
+        let path = temp_path("torn");
+        let store = Store::open_with_wal(&path).unwrap();
+        for n in 0..10 {
+            store.set(
+                Bytes::from(format!("key{n}")),
+                Bytes::from(format!("val{n}")),
+            );
+        }
+        store.remove(Bytes::from("key0"));
+        let intact = fs::metadata(&path).unwrap().len();
+        store.set(Bytes::from("key10"), Bytes::from("val10"));
+        drop(store);
+
+        // Simulate a crash partway through appending the last record
+        let log = fs::read(&path).unwrap();
+        fs::write(&path, &log[..log.len() - 3]).unwrap();
+
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+        assert_eq!(store.len(), 9);
+        assert_eq!(store.get(Bytes::from("key0")), None);
+        assert_eq!(store.get(Bytes::from("key9")), Some(Bytes::from("val9")));
+        assert_eq!(store.get(Bytes::from("key10")), None);
+
+        // The log stays appendable after recovery
+        store.set(Bytes::from("key11"), Bytes::from("val11"));
+        drop(store);
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(store.len(), 10);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compact_drops_dead_records() {
+        let path = temp_path("compact");
+        let store = Store::open_with_wal(&path).unwrap();
+        for n in 0..100 {
+            store.set(Bytes::from("counter"), Bytes::from(format!("{n}")));
+        }
+        store.set(Bytes::from("gone"), Bytes::from("soon"));
+        store.remove(Bytes::from("gone"));
+
+        let stats = store.compact().unwrap();
+        assert_eq!(stats.records_dropped, 101);
+        assert!(stats.bytes_after < stats.bytes_before);
+        assert_eq!(stats.bytes_after, fs::metadata(&path).unwrap().len());
+
+        store.set(Bytes::from("after"), Bytes::from("compaction"));
+        drop(store);
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(Bytes::from("counter")), Some(Bytes::from("99")));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compact_drops_expired_records() {
+        let path = temp_path("compact-expired");
+        let store = Store::open_with_wal(&path).unwrap();
+        store.set_auto_compaction(Some(1));
+        for n in 0..200 {
+            store.set_with_ttl(
+                Bytes::from(format!("key{n}")),
+                Bytes::from("expiring"),
+                Duration::from_millis(10),
+            );
+        }
+        thread::sleep(Duration::from_millis(30));
+
+        let stats = store.compact().unwrap();
+        assert_eq!(stats.records_dropped, 200);
+        // The expired records are still in memory but no longer in the log,
+        // which mustn't throw off the accounting of later writes
+        store.set(Bytes::from("key0"), Bytes::from("again"));
+        store.set(Bytes::from("after"), Bytes::from("compaction"));
+        let logged = fs::metadata(&path).unwrap().len();
+        store.set(Bytes::from("more"), Bytes::from("writes"));
+        assert!(fs::metadata(&path).unwrap().len() > logged);
+        drop(store);
+
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(Bytes::from("key0")), Some(Bytes::from("again")));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compact_keeps_concurrent_writes() {
+        let path = temp_path("concurrent");
+        let store = Store::open_with_wal(&path).unwrap();
+
+        let writer = store.clone();
+        let handle = thread::spawn(move || {
+            for n in 0..500 {
+                writer.set(Bytes::from(format!("key{n}")), Bytes::from("old"));
+                writer.set(Bytes::from(format!("key{n}")), Bytes::from("new"));
+            }
+        });
+        while !handle.is_finished() {
+            store.compact().unwrap();
+        }
+        handle.join().unwrap();
+        drop(store);
+
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(store.len(), 500);
+        for n in 0..500 {
+            assert_eq!(
+                store.get(Bytes::from(format!("key{n}"))),
+                Some(Bytes::from("new"))
+            );
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn auto_compaction_bounds_log() {
+        let path = temp_path("auto");
+        let store = Store::open_with_wal(&path).unwrap();
+        store.set_auto_compaction(Some(2));
+        for n in 0..1000 {
+            store.set(Bytes::from("counter"), Bytes::from(vec![n as u8; 100]));
+        }
+
+        assert!(fs::metadata(&path).unwrap().len() < 8 * 1024);
+        drop(store);
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(
+            store.get(Bytes::from("counter")),
+            Some(Bytes::from(vec![231; 100]))
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replay_keeps_ttls() {
+        let path = temp_path("ttl");
+        let store = Store::open_with_wal(&path).unwrap();
+        store.set_with_ttl(
+            Bytes::from("live"),
+            Bytes::from("v"),
+            Duration::from_secs(60),
+        );
+        store.set_with_ttl(
+            Bytes::from("dying"),
+            Bytes::from("v"),
+            Duration::from_millis(10),
+        );
+        store.set_with_ttl(
+            Bytes::from("cleared"),
+            Bytes::from("v"),
+            Duration::from_millis(10),
+        );
+        store.set(Bytes::from("cleared"), Bytes::from("v"));
+        drop(store);
+        thread::sleep(Duration::from_millis(20));
+
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert!(store.ttl(&Bytes::from("live")).unwrap() > Duration::from_secs(58));
+        assert_eq!(store.get(Bytes::from("cleared")), Some(Bytes::from("v")));
+
+        // Expired entries don't survive compaction either
+        store.set_with_ttl(Bytes::from("dying"), Bytes::from("v"), Duration::ZERO);
+        assert_eq!(store.compact().unwrap().records_dropped, 3);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_records_in_each_mode() {
+        // Every record is 17 bytes after the 5-byte header, so record `n`
+        // starts at byte `5 + 17 * n`
+        let path = temp_path("corrupt");
+        let store = Store::open_with_wal(&path).unwrap();
+        for n in 0..4 {
+            store.set(Bytes::from(format!("k{n}")), Bytes::from(format!("v{n}")));
+        }
+        drop(store);
+        let mut log = fs::read(&path).unwrap();
+        assert_eq!(log.len(), 5 + 4 * 17);
+        log[5 + 2 * 17 + 16] ^= 1;
+        fs::write(&path, &log).unwrap();
+
+        let err = Store::replay_wal(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "write-ahead log record 2 at byte 39 fails its checksum"
+        );
+        assert!(Store::open_with_wal(&path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), log);
+
+        let replayed = Store::replay_wal_mode(&path, RecoveryMode::Tolerant).unwrap();
+        assert_eq!(replayed.len(), 2);
+        assert_eq!(fs::read(&path).unwrap(), log);
+
+        let store = Store::open_with_wal_mode(&path, RecoveryMode::Tolerant).unwrap();
+        assert_eq!(store.get(Bytes::from("k1")), Some(Bytes::from("v1")));
+        assert_eq!(store.get(Bytes::from("k2")), None);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 39);
+        store.set(Bytes::from("k4"), Bytes::from("v4"));
+        drop(store);
+        assert_eq!(Store::open_with_wal(&path).unwrap().len(), 3);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_length_is_not_a_torn_tail() {
+        let path = temp_path("corrupt-length");
+        let store = Store::open_with_wal(&path).unwrap();
+        for n in 0..4 {
+            store.set(Bytes::from(format!("k{n}")), Bytes::from(format!("v{n}")));
+        }
+        drop(store);
+        // Record 1 now claims to run past the end of the log
+        let mut log = fs::read(&path).unwrap();
+        log[5 + 17 + 2] = 1;
+        fs::write(&path, &log).unwrap();
+
+        let err = Store::open_with_wal(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "write-ahead log record 1 at byte 22 has a corrupt length"
+        );
+        assert_eq!(fs::read(&path).unwrap(), log);
+
+        let store = Store::open_with_wal_mode(&path, RecoveryMode::Tolerant).unwrap();
+        assert_eq!(store.len(), 1);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn batches_replay_whole_or_not_at_all() {
+        let path = temp_path("batch");
+        let store = Store::open_with_wal(&path).unwrap();
+        store.set(Bytes::from("gone"), Bytes::from("v"));
+        let mut batch = Batch::new();
+        batch
+            .set(Bytes::from("a"), Bytes::from("1"))
+            .set(Bytes::from("a"), Bytes::from("2"))
+            .remove(Bytes::from("gone"));
+        store.apply(batch).unwrap();
+        drop(store);
+
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(Bytes::from("a")), Some(Bytes::from("2")));
+        let intact = fs::metadata(&path).unwrap().len();
+        store
+            .transaction(|txn| {
+                txn.set(Bytes::from("b"), Bytes::from("3"));
+                txn.set(Bytes::from("c"), Bytes::from("4"));
+                Ok::<_, StoreError>(())
+            })
+            .unwrap();
+        drop(store);
+
+        // Tearing the transaction's record drops all of it
+        let log = fs::read(&path).unwrap();
+        fs::write(&path, &log[..log.len() - 1]).unwrap();
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.compact().unwrap().records_dropped, 1);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn old_logs_are_upgraded() {
+        let path = temp_path("legacy");
+        // `u32 length, tag, u32 key length, key, value`, with no header or
+        // checksum
+        let mut log = Vec::new();
+        for (k, v) in [("k0", "v0"), ("k1", "v1")] {
+            log.extend_from_slice(&9_u32.to_le_bytes());
+            log.push(1);
+            log.extend_from_slice(&2_u32.to_le_bytes());
+            log.extend_from_slice(k.as_bytes());
+            log.extend_from_slice(v.as_bytes());
+        }
+        fs::write(&path, &log).unwrap();
+        assert_eq!(Store::replay_wal(&path).unwrap().len(), 2);
+
+        let store = Store::open_with_wal(&path).unwrap();
+        assert_eq!(store.get(Bytes::from("k1")), Some(Bytes::from("v1")));
+        assert!(fs::read(&path).unwrap().starts_with(b"KVSW\x02"));
+        store.set(Bytes::from("k2"), Bytes::from("v2"));
+        drop(store);
+        assert_eq!(Store::open_with_wal(&path).unwrap().len(), 3);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compact_requires_wal() {
+        assert!(Store::new().compact().is_err());
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("kvs-wal-{}-{name}", process::id()))
+    }
+}
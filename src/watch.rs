@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    hash::BuildHasher,
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+};
+
+use bytes::Bytes;
+
+use crate::{State, Store};
+
+// How many events a watcher can fall behind by before new ones are dropped
+pub(crate) const WATCH_BUFFER: usize = 64;
+
+// The senders of every watcher, by the key they watch
+pub(crate) type Watchers = HashMap<Bytes, Vec<Watcher>>;
+
+// The sending half of a watcher's channel
+#[derive(Debug)]
+pub(crate) enum Watcher {
+    Blocking(SyncSender<WatchEvent>),
+    #[cfg(feature = "tokio")]
+    Async(tokio::sync::mpsc::Sender<WatchEvent>),
+}
+
+/// A change to a watched key, as delivered by [`Store::watch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// The key was set to this value.
+    Set(Bytes),
+    /// The key was removed, whether explicitly, by expiring, or by eviction.
+    Removed,
+}
+
+impl Store {
+    /// Returns a channel that receives an event for every change to `k`, made
+    /// through any handle and any write path. Each event is sent once the
+    /// change is applied, so a watcher that reads `k` on receiving it sees the
+    /// change or a later one.
+    ///
+    /// Writers never wait on watchers: a watcher more than a few dozen events
+    /// behind misses new ones until it catches up. Dropping the receiver
+    /// unregisters the watcher the next time `k` changes. If the lock is
+    /// poisoned the receiver is disconnected straight away.
+    pub fn watch(&self, k: Bytes) -> Receiver<WatchEvent> {
+        let (sender, receiver) = mpsc::sync_channel(WATCH_BUFFER);
+        self.add_watcher(k, Watcher::Blocking(sender));
+        receiver
+    }
+
+    // Registers `watcher` for changes to `k`, dropping it if the lock is
+    // poisoned
+    pub(crate) fn add_watcher(&self, k: Bytes, watcher: Watcher) {
+        if let Ok(mut guard) = self.write() {
+            guard.watchers.entry(k).or_default().push(watcher);
+        }
+    }
+}
+
+impl Watcher {
+    // Sends `event` unless the watcher is too far behind, returning whether
+    // the watcher is still there
+    fn send(&self, event: WatchEvent) -> bool {
+        match self {
+            Self::Blocking(sender) => {
+                !matches!(sender.try_send(event), Err(TrySendError::Disconnected(_)))
+            }
+            #[cfg(feature = "tokio")]
+            Self::Async(sender) => !matches!(
+                sender.try_send(event),
+                Err(tokio::sync::mpsc::error::TrySendError::Closed(_))
+            ),
+        }
+    }
+}
+
+impl<S: BuildHasher> State<S> {
+    // Tells every watcher of `k` about a change, dropping any that have gone
+    pub(crate) fn notify(&mut self, k: &Bytes, event: WatchEvent) {
+        let Some(senders) = self.watchers.get_mut(k) else {
+            return;
+        };
+        senders.retain(|watcher| watcher.send(event.clone()));
+        if senders.is_empty() {
+            self.watchers.remove(k);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WatchEvent;
+    use crate::{Batch, Store};
+    use bytes::Bytes;
+    use std::thread;
+
+    #[test]
+    fn watcher_sees_changes_in_order() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+        let events = store.watch(k.clone());
+
+        let watcher = thread::spawn(move || events.iter().take(4).collect::<Vec<_>>());
+        store.set(k.clone(), Bytes::from("1"));
+        store.set(Bytes::from("other"), Bytes::from("x"));
+        store.remove(k.clone());
+        // Removing an absent key isn't a change
+        store.remove(k.clone());
+        let mut batch = Batch::new();
+        batch.set(k.clone(), Bytes::from("2")).remove(k.clone());
+        store.apply(batch).unwrap();
+
+        assert_eq!(
+            watcher.join().unwrap(),
+            [
+                WatchEvent::Set(Bytes::from("1")),
+                WatchEvent::Removed,
+                WatchEvent::Set(Bytes::from("2")),
+                WatchEvent::Removed,
+            ]
+        );
+    }
+
+    #[test]
+    fn change_is_visible_when_event_arrives() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+        let events = store.watch(k.clone());
+
+        let reader = store.clone();
+        let watcher = thread::spawn(move || {
+            assert_eq!(events.recv(), Ok(WatchEvent::Set(Bytes::from("v"))));
+            reader.get(Bytes::from("k"))
+        });
+        store.set(k, Bytes::from("v"));
+        assert_eq!(watcher.join().unwrap(), Some(Bytes::from("v")));
+    }
+
+    #[test]
+    fn every_watcher_gets_every_event() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+        let (first, second) = (store.watch(k.clone()), store.watch(k.clone()));
+
+        store.set(k, Bytes::from("v"));
+        for events in [first, second] {
+            assert_eq!(events.try_recv(), Ok(WatchEvent::Set(Bytes::from("v"))));
+        }
+    }
+
+    #[test]
+    fn dropped_watchers_are_unregistered() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+        drop(store.watch(k.clone()));
+        let kept = store.watch(k.clone());
+
+        store.set(k.clone(), Bytes::from("v"));
+        assert_eq!(store.read().unwrap().watchers[&k].len(), 1);
+
+        drop(kept);
+        store.set(k.clone(), Bytes::from("v"));
+        assert!(store.read().unwrap().watchers.is_empty());
+    }
+
+    #[test]
+    fn slow_watcher_does_not_block_writers() {
+        let store = Store::new();
+        let k = Bytes::from("k");
+        let events = store.watch(k.clone());
+
+        for n in 0..1000 {
+            store.set(k.clone(), Bytes::from(n.to_string()));
+        }
+        assert_eq!(events.try_iter().count(), super::WATCH_BUFFER);
+    }
+}
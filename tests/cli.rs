@@ -0,0 +1,75 @@
+#![cfg(feature = "cli")]
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+use kvs::Store;
+
+// Runs the `kvs` binary, returning its exit code and standard output
+fn kvs(args: &[&str]) -> (i32, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_kvs"))
+        .args(args)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    (output.status.code().unwrap(), stdout)
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("kvs-cli-{}-{name}", std::process::id()))
+}
+
+#[test]
+fn edits_a_saved_store() {
+    let path = temp_path("edit");
+    let _ = fs::remove_file(&path);
+    let file = path.to_str().unwrap();
+
+    assert_eq!(kvs(&["set", file, "hello", "world"]).0, 0);
+    assert_eq!(kvs(&["set", file, "help", "me"]).0, 0);
+    assert_eq!(kvs(&["--hex", "set", file, "00ff", "0102"]).0, 0);
+
+    assert_eq!(kvs(&["get", file, "hello"]), (0, "world\n".to_string()));
+    assert_eq!(kvs(&["get", file, "missing"]).0, 1);
+    assert_eq!(
+        kvs(&["keys", file, "hel"]),
+        (0, "hello\nhelp\n".to_string())
+    );
+    assert_eq!(
+        kvs(&["--hex", "get", file, "00ff"]),
+        (0, "0102\n".to_string())
+    );
+    assert_eq!(
+        kvs(&["--hex", "dump", file]),
+        (
+            0,
+            "{\"key\":\"00ff\",\"value\":\"0102\"}\n\
+             {\"key\":\"68656c6c6f\",\"value\":\"776f726c64\"}\n\
+             {\"key\":\"68656c70\",\"value\":\"6d65\"}\n"
+                .to_string()
+        )
+    );
+
+    assert_eq!(kvs(&["del", file, "help"]).0, 0);
+    assert_eq!(kvs(&["del", file, "help"]).0, 1);
+
+    // The file is an ordinary snapshot
+    let store = Store::load_from_path(&path).unwrap();
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.get("hello".into()), Some("world".into()));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reports_errors() {
+    let path = temp_path("errors");
+    let file = path.to_str().unwrap();
+    fs::write(&path, "not a snapshot").unwrap();
+
+    assert_eq!(kvs(&["get", file, "k"]).0, 2);
+    assert_eq!(kvs(&["get", "/nonexistent/kvs", "k"]).0, 2);
+    assert_eq!(kvs(&["frobnicate", file]).0, 2);
+    for bad in ["zz", "0", "+f"] {
+        assert_eq!(kvs(&["--hex", "set", file, bad, "00"]).0, 2);
+    }
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "resp")]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use kvs::{resp, Store};
+
+// Just enough of a RESP client to read the replies the server sends
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn connect(addr: impl std::net::ToSocketAddrs) -> Self {
+        let writer = TcpStream::connect(addr).unwrap();
+        let reader = BufReader::new(writer.try_clone().unwrap());
+        Self { reader, writer }
+    }
+
+    // Sends a command as an array of bulk strings, like `redis-cli` does
+    fn send(&mut self, args: &[&[u8]]) -> String {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend(format!("${}\r\n", arg.len()).into_bytes());
+            request.extend_from_slice(arg);
+            request.extend_from_slice(b"\r\n");
+        }
+        self.writer.write_all(&request).unwrap();
+        self.reply()
+    }
+
+    fn send_inline(&mut self, line: &str) -> String {
+        write!(self.writer, "{line}\r\n").unwrap();
+        self.reply()
+    }
+
+    // Reads one reply, rendering nested arrays on one line
+    fn reply(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let line = line.trim_end().to_string();
+        match line.split_at(1) {
+            ("$", "-1") => "(nil)".to_string(),
+            ("$", len) => {
+                let mut v = vec![0; len.parse::<usize>().unwrap() + 2];
+                self.reader.read_exact(&mut v).unwrap();
+                v.truncate(v.len() - 2);
+                format!("{v:?}")
+            }
+            ("*", n) => {
+                let items: Vec<String> = (0..n.parse().unwrap()).map(|_| self.reply()).collect();
+                format!("[{}]", items.join(", "))
+            }
+            _ => line,
+        }
+    }
+}
+
+#[test]
+fn redis_style_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let store = Store::new();
+    {
+        let store = store.clone();
+        thread::spawn(move || resp::serve_on(store, listener));
+    }
+
+    let mut client = Client::connect(addr);
+    assert_eq!(client.send(&[b"PING"]), "+PONG");
+    assert_eq!(client.send(&[b"SET", b"foo", b"bar"]), "+OK");
+    assert_eq!(client.send(&[b"GET", b"foo"]), format!("{:?}", b"bar"));
+    assert_eq!(client.send(&[b"SET", b"bin", b"\0\xff"]), "+OK");
+    assert_eq!(store.get("bin".into()), Some(b"\0\xff"[..].into()));
+
+    let mut other = Client::connect(addr);
+    assert_eq!(other.send_inline("EXISTS foo bin nope"), ":2");
+    assert_eq!(
+        other.send_inline("KEYS *"),
+        format!("[{:?}, {:?}]", b"bin", b"foo")
+    );
+    assert_eq!(other.send_inline("DEL foo"), ":1");
+    assert_eq!(client.send(&[b"GET", b"foo"]), "(nil)");
+    assert_eq!(client.send(&[b"FLUSHALL"]), "-ERR unknown command");
+}
@@ -0,0 +1,55 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use kvs::{server, Store};
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn connect(addr: impl std::net::ToSocketAddrs) -> Self {
+        let writer = TcpStream::connect(addr).unwrap();
+        let reader = BufReader::new(writer.try_clone().unwrap());
+        Self { reader, writer }
+    }
+
+    fn send(&mut self, request: &str) -> String {
+        writeln!(self.writer, "{request}").unwrap();
+        let mut response = String::new();
+        self.reader.read_line(&mut response).unwrap();
+        response.trim_end().to_string()
+    }
+}
+
+#[test]
+fn set_get_del_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let store = Store::new();
+    {
+        let store = store.clone();
+        thread::spawn(move || server::serve_on(store, listener));
+    }
+
+    // "hello" and "\0\xffworld" in base64
+    let mut client = Client::connect(addr);
+    assert_eq!(client.send("GET aGVsbG8="), "NOT_FOUND");
+    assert_eq!(client.send("SET aGVsbG8= AP93b3JsZA=="), "OK");
+    assert_eq!(client.send("GET aGVsbG8="), "VALUE AP93b3JsZA==");
+    assert_eq!(store.get("hello".into()), Some(b"\0\xffworld"[..].into()));
+
+    // A second connection shares the same store
+    let mut other = Client::connect(addr);
+    assert_eq!(other.send("DEL aGVsbG8="), "OK");
+    assert_eq!(client.send("DEL aGVsbG8="), "NOT_FOUND");
+
+    assert!(client.send("GET not-base64!").starts_with("ERROR"));
+    assert!(client.send("SET aGVsbG8=").starts_with("ERROR"));
+    assert!(client.send("PUT aGVsbG8=").starts_with("ERROR"));
+    assert_eq!(client.send("GET aGVsbG8="), "NOT_FOUND");
+}